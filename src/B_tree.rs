@@ -1,15 +1,85 @@
-use std::{cmp::Ordering, vec};
+#![allow(non_snake_case)]
+#![allow(clippy::needless_return)]
+use std::{
+    cmp::Ordering,
+    io,
+    ops::Bound,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+    vec,
+};
 
-pub static HEADER: u16 = 4;
+use crate::{
+    comparator::Comparator,
+    pager::{page_number, Pager},
+};
+
+pub static HEADER: u16 = 12;
+// |key length 2B|value length 2B| in front of every key-value pair
+pub static KV_HEADER: u16 = 4;
+// the page size of heap trees, and of database files unless they are created with another
 pub const BTREE_PAGE_SIZE: usize = 4096;
-pub const BTREE_MAX_KEY_SIZE: usize = 1000;
-pub const BTREE_MAX_VAL_SIZE: usize = 3000;
+// the page sizes a database file can be created with
+pub const PAGE_SIZES: [usize; 4] = [4096, 8192, 16384, 65536];
+pub const BTREE_MAX_KEY_SIZE: usize = max_key_size(BTREE_PAGE_SIZE);
+pub const BTREE_MAX_VAL_SIZE: usize = max_val_size(BTREE_PAGE_SIZE);
+// the largest key and value with pages of `page_size`: a leaf holding the sentinel and one
+// such entry still fits a page. 1000 and 3000 bytes for 4 KiB.
+pub const fn max_key_size(page_size: usize) -> usize {
+    return page_size / 4 - 24;
+}
+pub const fn max_val_size(page_size: usize) -> usize {
+    return page_size / 4 * 3 - 72;
+}
+// the longest common prefix a node keeps out of its keys. Putting whole keys back into a
+// node then grows it by at most this much per key.
+pub const BTREE_MAX_PREFIX: usize = 128;
+// set in the prefix length when the first key is the empty key, the sentinel every node on
+// the left edge of the tree starts with. It is kept whole and does not count for the prefix.
+const PREFIX_SENTINEL: u16 = 0x8000;
 pub const BNODE_LEAF: u16 = 2;
 pub const BNODE_NODE: u16 = 1;
 pub const BNODE_INVALID: u16 = 0;
+// update modes understood by `BTree::update`
+pub const MODE_UPSERT: u8 = 0; // insert the key or replace its value
+pub const MODE_UPDATE_ONLY: u8 = 1; // only replace the value of an existing key
+pub const MODE_INSERT_ONLY: u8 = 2; // only add a key that is not present yet
+pub const MODE_CAS: u8 = 3; // write only if the current value equals `expected`
+pub const MODE_MERGE: u8 = 4; // combine the current value with `value` through the merge operator
+
+// leaves have no children, their pointer slots hold flags instead. A value flagged
+// LEAF_BUCKET is a nested tree: its root page as u64 little-endian, then whatever the
+// owner of the bucket keeps there. Plain writes and deletes leave such entries alone.
+pub const LEAF_BUCKET: u64 = 1;
+// a value flagged LEAF_TTL starts with the time it expires, u64 little-endian in
+// milliseconds since the unix epoch. From then on reads act as if the key was not there
+// and `sweep_expired` removes it. Any plain write to the key clears the expiry.
+pub const LEAF_TTL: u64 = 2;
+
+pub fn now_millis() -> u64 {
+    let since = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    return since.as_millis() as u64;
+}
+// whether an entry with `flags` and the stored `value` has expired at `now`. A value too
+// short for its expiry, which only a damaged file has, never expires and reads as empty.
+pub fn is_expired(flags: u64, value: &[u8], now: u64) -> bool {
+    let Some(expiry) = value.get(..8) else {
+        return false;
+    };
+    return flags == LEAF_TTL && u64::from_le_bytes(expiry.try_into().unwrap()) <= now;
+}
+// bucket entries only give way to bucket entries, plain ones to plain ones
+fn same_kind(a: u64, b: u64) -> bool {
+    return (a == LEAF_BUCKET) == (b == LEAF_BUCKET);
+}
 pub struct BNode {
     pub data: Vec<u8>,
 }
+impl Default for BNode {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 // impl Iterator for BNode {
 //     type Item = Vec<u8>;
 //     fn next(&mut self) -> Option<Self::Item> {
@@ -30,9 +100,28 @@ impl BNode {
     pub fn nkeys(&self) -> u16 {
         return u16::from_ne_bytes(self.data[2..4].try_into().unwrap());
     }
+    // the commit that wrote the node to the file, stamped by the pager. 0 for heap
+    // trees and for files from before it was kept.
+    pub fn txid(&self) -> u64 {
+        return u64::from_ne_bytes(self.data[4..12].try_into().unwrap());
+    }
+    pub fn set_txid(&mut self, txid: u64) {
+        self.data[4..12].copy_from_slice(&txid.to_ne_bytes());
+    }
     pub fn set_header(&mut self, btype: u16, nkeys: u16) {
         self.data[..2].copy_from_slice(&btype.to_ne_bytes());
         self.data[2..4].copy_from_slice(&nkeys.to_ne_bytes());
+        // no prefix until set_prefix
+        let position = self.prefix_position();
+        self.reserve(position + 2);
+        self.data[position..position + 2].copy_from_slice(&0u16.to_ne_bytes());
+    }
+    // grows the buffer to at least `len` bytes. A node being built can outgrow its page
+    // before it is split, more so when a new key shortens the prefix of every key in it.
+    fn reserve(&mut self, len: usize) {
+        if self.data.len() < len {
+            self.data.resize(len, 0);
+        }
     }
     pub fn get_pointer(&self, index: u16) -> u64 {
         assert!(index < self.nkeys());
@@ -44,97 +133,134 @@ impl BNode {
         let position: usize = HEADER as usize + 8 * index as usize;
         self.data[position..position + 8].copy_from_slice(&value.to_ne_bytes());
     }
-    // structure of a node is |node type (2B)|number of keys(2B)|pointers(each pointer is 8B)|offsets(each offset is 2B)|key-value pairs
-    pub fn offset_position(&self, index: u16) -> u16 {
+    // structure of a node is |node type (2B)|number of keys(2B)|txid (8B)|pointers(each pointer is 8B)|offsets(each offset is 4B)|prefix length(2B)|prefix|key-value pairs
+    // every key of the node starts with the prefix, the key-value pairs only hold the rest.
+    // The offsets are 4 bytes so that 64 KiB pages, and nodes not split to size yet, fit.
+    pub fn offset_position(&self, index: u16) -> usize {
         assert!(index <= self.nkeys());
-        if index >= 1 {
-            return HEADER + 8 * self.nkeys() + 2 * (index);
-        } else {
-            return HEADER + 8 * self.nkeys();
-        }
+        return HEADER as usize + 8 * self.nkeys() as usize + 4 * index as usize;
     }
-    pub fn get_offset(&self, index: u16) -> u16 {
-        let offset_position = self.offset_position(index) as usize;
-        // println!("offset-{offset_position}");
-        return u16::from_ne_bytes(
-            self.data[offset_position..offset_position + 2]
+    pub fn get_offset(&self, index: u16) -> u32 {
+        let offset_position = self.offset_position(index);
+        return u32::from_ne_bytes(
+            self.data[offset_position..offset_position + 4]
                 .try_into()
                 .unwrap(),
         );
     }
-    pub fn set_offset(&mut self, index: u16, offset: u16) {
+    pub fn set_offset(&mut self, index: u16, offset: u32) {
         if index < self.nkeys() {
-            let position = self.offset_position(index) as usize;
-            // println!("{index}");
-            // println!("{position}");
-            println!("setting_offset:{offset}");
-            self.data[position..position + 2].copy_from_slice(&offset.to_ne_bytes());
+            let position = self.offset_position(index);
+            self.data[position..position + 4].copy_from_slice(&offset.to_ne_bytes());
         }
     }
-    pub fn kvpos(&self, index: u16) -> u16 {
-        println!("index:{index}");
+    fn prefix_position(&self) -> usize {
+        return HEADER as usize + 12 * self.nkeys() as usize;
+    }
+    fn prefix_field(&self) -> u16 {
+        let position = self.prefix_position();
+        return u16::from_ne_bytes(self.data[position..position + 2].try_into().unwrap());
+    }
+    pub fn prefix_len(&self) -> u16 {
+        return self.prefix_field() & !PREFIX_SENTINEL;
+    }
+    pub fn starts_with_sentinel(&self) -> bool {
+        return self.prefix_field() & PREFIX_SENTINEL != 0;
+    }
+    pub fn prefix(&self) -> Vec<u8> {
+        let position = self.prefix_position() + 2;
+        return self.data[position..position + self.prefix_len() as usize].to_vec();
+    }
+    // only right after set_header, before any key-value pair is appended. Prefixes
+    // longer than BTREE_MAX_PREFIX are cut short.
+    pub fn set_prefix(&mut self, prefix: &[u8]) {
+        let prefix = &prefix[..prefix.len().min(BTREE_MAX_PREFIX)];
+        let position = self.prefix_position();
+        self.reserve(position + 2 + prefix.len());
+        self.data[position..position + 2].copy_from_slice(&(prefix.len() as u16).to_ne_bytes());
+        self.data[position + 2..position + 2 + prefix.len()].copy_from_slice(prefix);
+    }
+    pub fn kvpos(&self, index: u16) -> usize {
         assert!(index <= self.nkeys());
-        println!("offset_value-{}", self.get_offset(index));
-        return HEADER + self.nkeys() * 2 + self.nkeys() * 8 + self.get_offset(index);
+        let start = self.prefix_position() + 2 + self.prefix_len() as usize;
+        return start + self.get_offset(index) as usize;
+    }
+    // length of the key as stored, without the prefix
+    fn klen(&self, index: u16) -> usize {
+        let key_pos = self.kvpos(index);
+        return u16::from_ne_bytes(self.data[key_pos..key_pos + 2].try_into().unwrap()) as usize;
+    }
+    // where the key-value pair at `index` ends
+    fn kv_end(&self, index: u16) -> usize {
+        let key_pos = self.kvpos(index);
+        let vlen =
+            u16::from_ne_bytes(self.data[key_pos + 2..key_pos + 4].try_into().unwrap()) as usize;
+        return key_pos + KV_HEADER as usize + self.klen(index) + vlen;
     }
     pub fn get_key(&self, index: u16) -> Vec<u8> {
         assert!(index <= self.nkeys());
-        let key_pos = self.kvpos(index) as usize;
-        // println!("position:{key_pos}");
-        let klen = u16::from_ne_bytes(self.data[key_pos..key_pos + 2].try_into().unwrap()) as usize;
+        if index == 0 && self.starts_with_sentinel() {
+            return vec![];
+        }
+        let key_pos = self.kvpos(index);
         // key-value pair structure: |key_length(2B)|Value_length(2B)|key|value|
-        return self.data[key_pos + 4..key_pos + 4 + klen].to_vec();
+        let mut key = self.prefix();
+        key.extend_from_slice(&self.data[key_pos + 4..key_pos + 4 + self.klen(index)]);
+        return key;
     }
     pub fn get_value(&self, index: u16) -> Vec<u8> {
         assert!(index <= self.nkeys());
-        let key_pos = self.kvpos(index) as usize;
-        let klen = u16::from_ne_bytes(self.data[key_pos..key_pos + 2].try_into().unwrap()) as usize;
-        let vlen =
-            u16::from_ne_bytes(self.data[key_pos + 2..key_pos + 4].try_into().unwrap()) as usize;
-        return self.data[key_pos + 4 + klen..key_pos + 4 + klen + vlen].to_vec();
+        let key_pos = self.kvpos(index);
+        return self.data[key_pos + 4 + self.klen(index)..self.kv_end(index)].to_vec();
     }
-    pub fn size(&self) -> u16 {
-        let position = self.kvpos(self.nkeys() - 1);
-        let last_index_containing_value = position as usize
-            + self.get_key(self.nkeys() - 1).len()
-            + self.get_value(self.nkeys() - 1).len();
-        return last_index_containing_value as u16;
+    pub fn size(&self) -> usize {
+        if self.nkeys() == 0 {
+            return self.prefix_position() + 2 + self.prefix_len() as usize;
+        }
+        return self.kv_end(self.nkeys() - 1);
+    }
+    pub fn lookup_key(&self, key: &[u8]) -> u16 {
+        return self.lookup_key_with(key, &Comparator::bytewise());
     }
-    pub fn lookup_key(&self, key: &Vec<u8>) -> u16 {
+    // index of the last key that is <= `key` in the order given by `comparator`
+    pub fn lookup_key_with(&self, key: &[u8], comparator: &Comparator) -> u16 {
         let mut found: u16 = 0;
         let mut i: u16 = 1;
 
         while i < self.nkeys() {
-            println!("{:?}", self.get_key(i));
-            if self.get_key(i).cmp(&vec![]) == Ordering::Equal {
+            let current = self.get_key(i);
+            if current.is_empty() {
                 break;
             }
-            if key.cmp(&self.get_key(i)) == Ordering::Equal
-                || key.cmp(&self.get_key(i)) == Ordering::Greater
-            {
-                found = i;
-            } else if key.cmp(&self.get_key(i)) == Ordering::Equal
-                || key.cmp(&self.get_key(i)) == Ordering::Less
-            {
+            if comparator.cmp(key, &current) == Ordering::Less {
                 break;
             }
-            i = i + 1;
+            found = i;
+            i += 1;
         }
         return found;
     }
     pub fn node_append_kv_pair(&mut self, pointer: u64, index: u16, key: Vec<u8>, value: Vec<u8>) {
         self.set_pointer(index, pointer);
-        // println!("{index}");
-        let position = self.kvpos(index) as usize;
-        // println!("position-{position}");
-        self.data[position..position + 2]
-            .copy_from_slice(&(key.clone().len() as u16).to_ne_bytes());
+        let prefix_len = self.prefix_len() as usize;
+        let key = if index == 0 && key.is_empty() {
+            let field = self.prefix_field() | PREFIX_SENTINEL;
+            let position = self.prefix_position();
+            self.data[position..position + 2].copy_from_slice(&field.to_ne_bytes());
+            &key[..]
+        } else {
+            assert!(key[..prefix_len.min(key.len())] == self.prefix()[..]);
+            &key[prefix_len..]
+        };
+        let position = self.kvpos(index);
+        self.reserve(position + 4 + key.len() + value.len());
+        self.data[position..position + 2].copy_from_slice(&(key.len() as u16).to_ne_bytes());
         self.data[position + 2..position + 4]
             .copy_from_slice(&(value.clone().len() as u16).to_ne_bytes());
-        self.data[position + 4..position + 4 + key.len()].copy_from_slice(&key);
+        self.data[position + 4..position + 4 + key.len()].copy_from_slice(key);
         self.data[position + 4 + key.len()..position + 4 + key.len() + value.len()]
             .copy_from_slice(&value);
-        let offset_value = self.get_offset(index) + HEADER + key.len() as u16 + value.len() as u16;
+        let offset_value = self.get_offset(index) + (4 + key.len() + value.len()) as u32;
 
         self.set_offset(index + 1, offset_value);
     }
@@ -145,11 +271,13 @@ pub fn leaf_insert(
     index: u16,
     key: Vec<u8>,
     value: Vec<u8>,
+    flags: u64,
 ) {
     new_leaf_node.set_header(BNODE_LEAF, old_leaf_node.nkeys() + 1);
-    println!("{index}");
+    let prefix = old_leaf_node.prefix();
+    new_leaf_node.set_prefix(&prefix[..common_prefix_len(&prefix, &key)]);
     node_append_range(old_leaf_node, new_leaf_node, 0, 0, index);
-    new_leaf_node.node_append_kv_pair(0, index, key, value);
+    new_leaf_node.node_append_kv_pair(flags, index, key, value);
     // println!("{:?}", new_leaf_node.get_key(1).len());
     // println!("{:?}", new_leaf_node.data);
     node_append_range(
@@ -168,20 +296,25 @@ pub fn leaf_update(
     index: u16,
     key: Vec<u8>,
     value: Vec<u8>,
+    flags: u64,
 ) {
-    println!("{index}");
-    new_leaf_node.set_header(BNODE_LEAF, old_leaf_node.nkeys() + 1);
-
+    new_leaf_node.set_header(BNODE_LEAF, old_leaf_node.nkeys());
+    let prefix = old_leaf_node.prefix();
+    new_leaf_node.set_prefix(&prefix[..common_prefix_len(&prefix, &key)]);
     node_append_range(old_leaf_node, new_leaf_node, 0, 0, index);
-    new_leaf_node.node_append_kv_pair(0, index, key, value);
+    new_leaf_node.node_append_kv_pair(flags, index, key, value);
     node_append_range(
         old_leaf_node,
         new_leaf_node,
         index + 1,
         index + 1,
-        old_leaf_node.nkeys() - index,
+        old_leaf_node.nkeys() - index - 1,
     );
 }
+// the number of leading bytes `a` and `b` have in common
+pub fn common_prefix_len(a: &[u8], b: &[u8]) -> usize {
+    return a.iter().zip(b).take_while(|(a, b)| a == b).count();
+}
 pub fn node_append_range(
     old_leaf_node: &BNode,
     new_leaf_node: &mut BNode,
@@ -191,12 +324,26 @@ pub fn node_append_range(
 ) {
     assert!(destination_new + range_size <= new_leaf_node.nkeys());
     if source_old + range_size > old_leaf_node.nkeys() {
-        println!("{source_old} + {range_size}");
         return;
     }
     if range_size == 0 {
         return;
     }
+    // the stored keys can only be copied as they are when both nodes cut off the same
+    // prefix, and the sentinel only where it gets marked as one
+    if old_leaf_node.prefix() != new_leaf_node.prefix()
+        || (source_old == 0 && old_leaf_node.starts_with_sentinel())
+    {
+        for i in 0..range_size {
+            new_leaf_node.node_append_kv_pair(
+                old_leaf_node.get_pointer(source_old + i),
+                destination_new + i,
+                old_leaf_node.get_key(source_old + i),
+                old_leaf_node.get_value(source_old + i),
+            );
+        }
+        return;
+    }
     for i in 0..range_size {
         new_leaf_node.set_pointer(
             destination_new + i,
@@ -205,52 +352,166 @@ pub fn node_append_range(
     }
 
     let new_offset_start = new_leaf_node.get_offset(destination_new);
-    println!("new_offset_start:{new_offset_start}");
-
     let old_offset_start = old_leaf_node.get_offset(source_old);
-    println!("{old_offset_start}");
     for i in 0..range_size {
         let offset_value =
             new_offset_start + old_leaf_node.get_offset(source_old + i) - old_offset_start;
-        println!("offset:{offset_value}");
-
         new_leaf_node.set_offset(destination_new + i, offset_value);
     }
-    // println!("{:?}", old_leaf_node.get_key(source_old));
-    let begin = old_leaf_node.kvpos(source_old) as usize;
-    let mut end: usize = 0;
-    let index = source_old + range_size;
-
-    if index == old_leaf_node.nkeys() {
-        end = old_leaf_node.kvpos(index - 1) as usize
-            + HEADER as usize
-            + old_leaf_node.get_key(index - 1).len()
-            + old_leaf_node.get_value(index - 1).len();
-    } else {
-        end = old_leaf_node.kvpos(index) as usize;
-    }
-    println!("{begin}-{end}");
-    let destination_begin = new_leaf_node.kvpos(destination_new) as usize;
+    let begin = old_leaf_node.kvpos(source_old);
+    let end = old_leaf_node.kv_end(source_old + range_size - 1);
+    let destination_begin = new_leaf_node.kvpos(destination_new);
     let destination_end = destination_begin + end - begin;
-    println!("{destination_begin}-{destination_end}");
-    // let size = end - begin;
-    // let mut slice = vec![0; size];
-
-    // println!("{:?}", old_leaf_node.data);
-    // slice.copy_from_slice(&old_leaf_node.data[begin..end]);
-    // println!("{:?}", slice);
+    new_leaf_node.reserve(destination_end);
     new_leaf_node.data[destination_begin..destination_end]
         .copy_from_slice(&old_leaf_node.data[begin..end]);
-    // println!("{:?}", new_leaf_node.data[begin..end].to_vec());
     let index = destination_new + range_size;
     let offset_value = new_leaf_node.get_offset(index - 1)
-        + HEADER
-        + new_leaf_node.get_key(index - 1).len() as u16
-        + new_leaf_node.get_value(index - 1).len() as u16;
+        + (new_leaf_node.kv_end(index - 1) - new_leaf_node.kvpos(index - 1)) as u32;
     new_leaf_node.set_offset(index, offset_value);
 }
 pub struct BTree {
     pub root: u64,
+    pub merge_operator: Option<MergeOperator>,
+    pub comparator: Comparator,
+    // where the pages live, the heap when there is no pager
+    pub pager: Option<Arc<Mutex<Pager>>>,
+    // the size nodes are split to, the one of the pager's file when there is one
+    pub page_size: usize,
+}
+pub type MergeFn = Box<dyn Fn(Option<&[u8]>, &[u8]) -> Vec<u8> + Send + Sync>;
+// how `BTree::merge` folds an operand into the value already stored under a key
+pub enum MergeOperator {
+    // values are i64 in little-endian, a missing value counts as 0. A stored value or
+    // an operand of another length is rejected.
+    Add,
+    Append,
+    // byte-wise max/min of the stored value and the operand
+    Max,
+    Min,
+    Custom(MergeFn),
+}
+impl MergeOperator {
+    // the merged value, or `None` when the operator rejects the values
+    pub fn apply(&self, old: Option<&[u8]>, operand: &[u8]) -> Option<Vec<u8>> {
+        match self {
+            MergeOperator::Add => {
+                let decode = |bytes: &[u8]| bytes.try_into().ok().map(i64::from_le_bytes);
+                let current = match old {
+                    Some(old) => decode(old)?,
+                    None => 0,
+                };
+                let sum = current.wrapping_add(decode(operand)?);
+                return Some(sum.to_le_bytes().to_vec());
+            }
+            MergeOperator::Append => {
+                let mut value = old.unwrap_or(&[]).to_vec();
+                value.extend_from_slice(operand);
+                return Some(value);
+            }
+            MergeOperator::Max => match old {
+                Some(old) if old >= operand => Some(old.to_vec()),
+                _ => Some(operand.to_vec()),
+            },
+            MergeOperator::Min => match old {
+                Some(old) if old <= operand => Some(old.to_vec()),
+                _ => Some(operand.to_vec()),
+            },
+            MergeOperator::Custom(merge) => Some(merge(old, operand)),
+        }
+    }
+}
+// puts and deletes gathered up and applied to the tree in one descent by `BTree::write`,
+// a `None` value marks a delete
+#[derive(Default)]
+pub struct WriteBatch {
+    pub ops: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+}
+impl WriteBatch {
+    pub fn new() -> WriteBatch {
+        return WriteBatch { ops: vec![] };
+    }
+    // empty keys and sizes past the limits of the tree are errors of `BTree::write`
+    pub fn put(&mut self, key: Vec<u8>, value: Vec<u8>) {
+        self.ops.push((key, Some(value)));
+    }
+    pub fn delete(&mut self, key: Vec<u8>) {
+        self.ops.push((key, None));
+    }
+    pub fn len(&self) -> usize {
+        return self.ops.len();
+    }
+    pub fn is_empty(&self) -> bool {
+        return self.ops.is_empty();
+    }
+    // sorted by key, when a key shows up more than once the last operation wins
+    pub fn sorted_ops(self, comparator: &Comparator) -> Vec<(Vec<u8>, Option<Vec<u8>>)> {
+        let mut ops = self.ops;
+        ops.sort_by(|a, b| comparator.cmp(&a.0, &b.0));
+        let mut sorted: Vec<(Vec<u8>, Option<Vec<u8>>)> = Vec::with_capacity(ops.len());
+        for op in ops {
+            match sorted.last_mut() {
+                Some(last) if comparator.cmp(&last.0, &op.0) == Ordering::Equal => *last = op,
+                _ => sorted.push(op),
+            }
+        }
+        return sorted;
+    }
+}
+// a single write against the tree: the key/value and mode go in, the outcome comes out
+pub struct UpdateReq {
+    pub key: Vec<u8>,
+    pub value: Vec<u8>,
+    pub mode: u8,
+    // only used by MODE_CAS, `None` means the key must be absent
+    pub expected: Option<Vec<u8>>,
+    // leaf flags of the entry, a bucket entry is only replaced by another bucket entry
+    pub flags: u64,
+    // only used with LEAF_TTL, when the entry expires in milliseconds since the epoch
+    pub expires: u64,
+    // out
+    pub added: bool,
+    pub updated: bool,
+    pub old: Option<Vec<u8>>,
+}
+impl UpdateReq {
+    pub fn new(key: Vec<u8>, value: Vec<u8>, mode: u8) -> UpdateReq {
+        return UpdateReq {
+            key,
+            value,
+            mode,
+            expected: None,
+            flags: 0,
+            expires: 0,
+            added: false,
+            updated: false,
+            old: None,
+        };
+    }
+    // the value as it is kept in the leaf
+    pub fn stored_value(&self) -> Vec<u8> {
+        if self.flags != LEAF_TTL {
+            return self.value.clone();
+        }
+        let mut value = self.expires.to_le_bytes().to_vec();
+        value.extend_from_slice(&self.value);
+        return value;
+    }
+    // decides from the current value (if any) whether the write goes ahead
+    pub fn accepts(&self, old: Option<&Vec<u8>>) -> bool {
+        match self.mode {
+            MODE_UPSERT | MODE_MERGE => true,
+            MODE_UPDATE_ONLY => old.is_some(),
+            MODE_INSERT_ONLY => old.is_none(),
+            MODE_CAS => old == self.expected.as_ref(),
+            _ => false,
+        }
+    }
+}
+impl Default for BTree {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 pub fn del(pointer: u64) {
     unsafe {
@@ -268,13 +529,258 @@ pub fn get(pointer: u64) -> BNode {
         let ptr = pointer as *mut Vec<u8>;
         let data = Box::from_raw(ptr);
         let result = *data.clone();
-        Box::into_raw(data);
+        let _ = Box::into_raw(data);
         return BNode { data: result };
     }
 }
 impl BTree {
     pub fn new() -> BTree {
-        return BTree { root: 0 };
+        return BTree::with_comparator(Comparator::bytewise());
+    }
+    pub fn with_comparator(comparator: Comparator) -> BTree {
+        return BTree {
+            root: 0,
+            merge_operator: None,
+            comparator,
+            pager: None,
+            page_size: BTREE_PAGE_SIZE,
+        };
+    }
+    pub fn max_key_size(&self) -> usize {
+        return max_key_size(self.page_size);
+    }
+    pub fn max_val_size(&self) -> usize {
+        return max_val_size(self.page_size);
+    }
+    // the node at `pointer`, which fails when its page can not be read back from the file
+    pub fn read_node(&self, pointer: u64) -> io::Result<BNode> {
+        match &self.pager {
+            Some(pager) => return pager.lock().unwrap().page_get(pointer),
+            None => return Ok(get(pointer)),
+        }
+    }
+    // for the writes, which do not get far on a file their pages can not be read from
+    pub fn get_node(&self, pointer: u64) -> BNode {
+        return self.read_node(pointer).expect("reading a page");
+    }
+    pub fn new_node(&self, node: BNode) -> u64 {
+        match &self.pager {
+            Some(pager) => return pager.lock().unwrap().page_new(node),
+            None => return new(node),
+        }
+    }
+    pub fn del_node(&self, pointer: u64) {
+        match &self.pager {
+            Some(pager) => pager.lock().unwrap().page_del(pointer),
+            None => del(pointer),
+        }
+    }
+    // fills the empty tree from entries (flags, key, value) already in the order of the
+    // comparator, without duplicates. Every node is packed full, unlike after inserts,
+    // and only the keys of the nodes above the leaves are held in memory.
+    pub fn bulk_load(&mut self, entries: impl IntoIterator<Item = (u64, Vec<u8>, Vec<u8>)>) {
+        assert!(self.root == 0, "bulk_load needs an empty tree");
+        let mut kids = vec![];
+        let mut page = vec![(0, vec![], vec![])];
+        let mut size = NodeSize::new();
+        size.add(&[], &[]);
+        // of the leaf before the current page
+        let mut last_key = vec![];
+        for (flags, key, value) in entries {
+            assert!(!key.is_empty() && key.len() <= self.max_key_size());
+            assert!(value.len() <= self.max_val_size());
+            let last: &Vec<u8> = &page.last().unwrap().1;
+            assert!(
+                last.is_empty() || self.comparator.cmp(last, &key) == Ordering::Less,
+                "bulk_load needs sorted keys without duplicates"
+            );
+            if size.with(&key, &value) > self.page_size {
+                let first = page[0].1.clone();
+                let separator = match kids.is_empty() {
+                    true => first,
+                    false => self.shortest_separator(&last_key, first),
+                };
+                last_key = page.last().unwrap().1.clone();
+                let node =
+                    build_nodes(BNODE_LEAF, std::mem::take(&mut page), self.page_size).remove(0);
+                kids.push((self.new_node(node), separator, vec![]));
+                size = NodeSize::new();
+            }
+            size.add(&key, &value);
+            page.push((flags, key, value));
+        }
+        let first = page[0].1.clone();
+        let separator = match kids.is_empty() {
+            true => first,
+            false => self.shortest_separator(&last_key, first),
+        };
+        let mut nodes = build_nodes(BNODE_LEAF, page, self.page_size);
+        kids.push((self.new_node(nodes.remove(0)), separator, vec![]));
+        loop {
+            for node in nodes {
+                let first = node.get_key(0);
+                kids.push((self.new_node(node), first, vec![]));
+            }
+            if kids.len() == 1 {
+                self.root = kids[0].0;
+                return;
+            }
+            nodes = build_nodes(BNODE_NODE, std::mem::take(&mut kids), self.page_size);
+        }
+    }
+    // moves every node kept at page `boundary` or above, in this tree and the buckets
+    // nested in it, to a fresh page, which the pager takes from the lowest free ones.
+    // The nodes on the path to a moved node are copied along with it.
+    pub fn relocate(&mut self, boundary: u64) {
+        if self.root != 0 {
+            self.root = self.relocate_node(self.root, boundary);
+        }
+    }
+    fn relocate_node(&self, pointer: u64, boundary: u64) -> u64 {
+        let node = self.get_node(pointer);
+        let mut entries = vec![];
+        let mut changed = page_number(pointer) >= boundary;
+        for i in 0..node.nkeys() {
+            let (mut kid, key, mut value) =
+                (node.get_pointer(i), node.get_key(i), node.get_value(i));
+            if node.btype() == BNODE_NODE {
+                let moved = self.relocate_node(kid, boundary);
+                changed |= moved != kid;
+                kid = moved;
+            } else if kid == LEAF_BUCKET {
+                let root = u64::from_le_bytes(value[..8].try_into().unwrap());
+                if root != 0 {
+                    let moved = self.relocate_node(root, boundary);
+                    changed |= moved != root;
+                    value[..8].copy_from_slice(&moved.to_le_bytes());
+                }
+            }
+            entries.push((kid, key, value));
+        }
+        if !changed {
+            return pointer;
+        }
+        self.del_node(pointer);
+        return self.new_node(build_nodes(node.btype(), entries, self.page_size).remove(0));
+    }
+    // frees every page of the tree, and of the buckets nested in it, and leaves it empty
+    pub fn destroy(&mut self) {
+        if self.root != 0 {
+            self.destroy_node(self.root);
+            self.root = 0;
+        }
+    }
+    // every page of the tree and of the buckets nested in it
+    pub fn pages(&self) -> io::Result<Vec<u64>> {
+        return self.pages_since(None);
+    }
+    // the pages written after commit `txid`, or all of them without one. Every node on
+    // the path to a changed node is written along with it, so no node below one
+    // written earlier has changed either.
+    pub fn pages_since(&self, txid: Option<u64>) -> io::Result<Vec<u64>> {
+        let mut pages = vec![];
+        if self.root != 0 {
+            self.collect_pages(self.root, txid, &mut pages)?;
+        }
+        return Ok(pages);
+    }
+    fn collect_pages(
+        &self,
+        pointer: u64,
+        since: Option<u64>,
+        pages: &mut Vec<u64>,
+    ) -> io::Result<()> {
+        let node = self.read_node(pointer)?;
+        if since.is_some_and(|since| node.txid() <= since) {
+            return Ok(());
+        }
+        pages.push(pointer);
+        for i in 0..node.nkeys() {
+            if node.btype() == BNODE_NODE {
+                self.collect_pages(node.get_pointer(i), since, pages)?;
+            } else if node.get_pointer(i) == LEAF_BUCKET {
+                let root = u64::from_le_bytes(node.get_value(i)[..8].try_into().unwrap());
+                if root != 0 {
+                    self.collect_pages(root, since, pages)?;
+                }
+            }
+        }
+        return Ok(());
+    }
+    fn destroy_node(&self, pointer: u64) {
+        let node = self.get_node(pointer);
+        for i in 0..node.nkeys() {
+            if node.btype() == BNODE_NODE {
+                self.destroy_node(node.get_pointer(i));
+            } else if node.get_pointer(i) == LEAF_BUCKET {
+                let root = u64::from_le_bytes(node.get_value(i)[..8].try_into().unwrap());
+                if root != 0 {
+                    self.destroy_node(root);
+                }
+            }
+        }
+        self.del_node(pointer);
+    }
+    // the key the parent keeps for `right`, the node after `left` on the same level. For
+    // leaves that is the shortest start of the first key of `right` that still sorts after
+    // the last key of `left`. The first key of an internal node is a separator already.
+    pub fn separator(&self, left: &BNode, right: &BNode) -> Vec<u8> {
+        if right.btype() != BNODE_LEAF || left.nkeys() == 0 {
+            return right.get_key(0);
+        }
+        return self.shortest_separator(&left.get_key(left.nkeys() - 1), right.get_key(0));
+    }
+    // the shortest start of `first` that sorts after `last` and not after `first`. Not
+    // after the sentinel, where it would cut the prefix the parent can keep to a byte.
+    fn shortest_separator(&self, last: &[u8], first: Vec<u8>) -> Vec<u8> {
+        if last.is_empty() {
+            return first;
+        }
+        for len in 1..first.len() {
+            let candidate = &first[..len];
+            if self.comparator.cmp(last, candidate) == Ordering::Less
+                && self.comparator.cmp(candidate, &first) != Ordering::Greater
+            {
+                return candidate.to_vec();
+            }
+        }
+        return first;
+    }
+    // stores `kids`, consecutive nodes of one level, and gives their entries for the
+    // parent: the first one under `first`, the key the parent had for what they replace,
+    // and the others under the separator from the kid before them. Keeping the key of
+    // the first one means an internal node always starts with its key in the parent.
+    pub fn kid_entries(&self, first: Vec<u8>, kids: Vec<BNode>) -> Vec<(u64, Vec<u8>, Vec<u8>)> {
+        let mut keys = vec![first];
+        for pair in kids.windows(2) {
+            keys.push(self.separator(&pair[0], &pair[1]));
+        }
+        return kids
+            .into_iter()
+            .zip(keys)
+            .map(|(kid, key)| (self.new_node(kid), key, vec![]))
+            .collect();
+    }
+    // `node` as the right one of two kids merged or sharing their entries, under
+    // `separator`, the key their parent has for it. An internal node takes the separator
+    // as its first key: its own first key may sort above the separator, as a leaf can
+    // start above its own, and inside the merged node it would route the keys in between
+    // away from the left kid holding them. `None` when the longer key does not fit a page.
+    fn under_separator(&self, node: &BNode, separator: Vec<u8>) -> Option<BNode> {
+        if node.btype() != BNODE_NODE || node.get_key(0) == separator {
+            return Some(BNode {
+                data: node.data.clone(),
+            });
+        }
+        let mut entries: Vec<(u64, Vec<u8>, Vec<u8>)> = (0..node.nkeys())
+            .map(|i| (node.get_pointer(i), node.get_key(i), vec![]))
+            .collect();
+        entries[0].1 = separator;
+        let mut nodes = build_nodes(BNODE_NODE, entries, self.page_size);
+        if nodes.len() > 1 {
+            return None;
+        }
+        return nodes.pop();
     }
     pub fn node_replace_kidN(
         &mut self,
@@ -285,13 +791,15 @@ impl BTree {
     ) {
         let inc = kids.len();
         new_node.set_header(BNODE_NODE, old_node.nkeys() + inc as u16 - 1);
+        let entries = self.kid_entries(old_node.get_key(index), kids);
+        let mut prefix = old_node.prefix();
+        for (_, key, _) in entries.iter().filter(|(_, key, _)| !key.is_empty()) {
+            prefix.truncate(common_prefix_len(&prefix, key));
+        }
+        new_node.set_prefix(&prefix);
         node_append_range(old_node, new_node, 0, 0, index);
-        let mut i: u16 = 0;
-        for node in kids {
-            let temp = node.data.clone();
-            let temp_node = BNode { data: temp };
-            new_node.node_append_kv_pair(new(node), index + i, temp_node.get_key(0), vec![]);
-            i = i + 1;
+        for (i, (pointer, key, _)) in (0_u16..).zip(entries) {
+            new_node.node_append_kv_pair(pointer, index + i, key, vec![]);
         }
 
         node_append_range(
@@ -302,89 +810,442 @@ impl BTree {
             old_node.nkeys() - index - 1,
         )
     }
-    pub fn tree_insert(&mut self, node: BNode, key: Vec<u8>, value: Vec<u8>) -> BNode {
+    // returns `None` when the request is rejected, in which case nothing was written
+    pub fn tree_insert(&mut self, req: &mut UpdateReq, node: BNode) -> Option<BNode> {
         let mut new = BNode {
-            data: vec![0; 2 * BTREE_PAGE_SIZE],
+            data: vec![0; 2 * self.page_size],
         };
-        let index = node.lookup_key(&key);
-        println!("during insertion in tree:{index}");
+        let index = node.lookup_key_with(&req.key, &self.comparator);
         match node.btype() {
             BNODE_LEAF => {
-                if key.cmp(&node.get_key(index)) == Ordering::Equal {
-                    println!("voila, reached leaf-update");
-                    leaf_update(&node, &mut new, index, key, value)
+                let exists = self.comparator.cmp(&req.key, &node.get_key(index)) == Ordering::Equal;
+                let flags = node.get_pointer(index);
+                if exists && !same_kind(flags, req.flags) {
+                    return None;
+                }
+                // an expired entry counts as absent and is written over
+                let old = match node.get_value(index) {
+                    _ if !exists => None,
+                    value if is_expired(flags, &value, now_millis()) => None,
+                    value if flags == LEAF_TTL => Some(value.get(8..).unwrap_or_default().to_vec()),
+                    value => Some(value),
+                };
+                if !self.resolve_value(req, old.as_ref()) {
+                    req.old = old;
+                    return None;
+                }
+                let (key, value) = (req.key.clone(), req.stored_value());
+                if value.len() > self.max_val_size() {
+                    return None;
+                }
+                if exists {
+                    leaf_update(&node, &mut new, index, key, value, req.flags);
+                    req.updated = old.is_some();
+                    req.added = old.is_none();
                 } else {
-                    println!("here inside leaf-insert");
-                    leaf_insert(&node, &mut new, index + 1, key, value)
+                    // the first key of a leaf can sort after its separator in the parent,
+                    // keys in between go in front of it
+                    let at = match self.comparator.cmp(&key, &node.get_key(index)) {
+                        Ordering::Less => index,
+                        _ => index + 1,
+                    };
+                    leaf_insert(&node, &mut new, at, key, value, req.flags);
+                    req.added = true;
                 }
+                req.old = old;
             }
             BNODE_NODE => {
-                self.node_insert(&node, &mut new, index, key, value);
+                if !self.node_insert(req, &node, &mut new, index) {
+                    return None;
+                }
             }
             _ => {
-                println!("bad node!")
+                println!("bad node!");
+                return None;
             }
         }
-        // println!("{:?}", new.data);
-        return new;
+        return Some(new);
     }
     pub fn node_insert(
         &mut self,
+        req: &mut UpdateReq,
         old_node: &BNode,
         new_node: &mut BNode,
         index: u16,
-        key: Vec<u8>,
-        value: Vec<u8>,
-    ) {
+    ) -> bool {
         let kptr = old_node.get_pointer(index);
-        let knode = self.tree_insert(get(kptr), key, value);
-        del(kptr);
-        let split = node_split3(knode);
-        self.node_replace_kidN(new_node, index, old_node, split)
+        let knode = match self.tree_insert(req, self.get_node(kptr)) {
+            Some(knode) => knode,
+            None => return false,
+        };
+        self.del_node(kptr);
+        let split = node_split3(knode, self.page_size);
+        self.node_replace_kidN(new_node, index, old_node, split);
+        return true;
     }
-    pub fn insert(&mut self, key: Vec<u8>, value: Vec<u8>) {
-        assert!(key.len() != 0 && key.len() <= BTREE_MAX_KEY_SIZE);
-        assert!(value.len() <= BTREE_MAX_VAL_SIZE);
+    // checks the request against the current value and, for merges, replaces the
+    // operand in `req.value` with the merged value that is going to be written
+    fn resolve_value(&self, req: &mut UpdateReq, old: Option<&Vec<u8>>) -> bool {
+        if !req.accepts(old) {
+            return false;
+        }
+        if req.mode == MODE_MERGE {
+            // a tree without a merge operator rejects merges
+            let Some(operator) = self.merge_operator.as_ref() else {
+                return false;
+            };
+            let merged = match operator.apply(old.map(|v| v.as_slice()), &req.value) {
+                Some(merged) if merged.len() <= self.max_val_size() => merged,
+                _ => return false,
+            };
+            req.value = merged;
+        }
+        return true;
+    }
+    // applies a write according to `req.mode`, returns false if the mode rejected it
+    pub fn update(&mut self, req: &mut UpdateReq) -> bool {
+        assert!(!req.key.is_empty() && req.key.len() <= self.max_key_size());
+        assert!(req.value.len() <= self.max_val_size());
         if self.root == 0 {
+            if !self.resolve_value(req, None) {
+                return false;
+            }
             let mut root = BNode::new();
             root.set_header(BNODE_LEAF, 2);
             root.node_append_kv_pair(0, 0, vec![], vec![]);
-            root.node_append_kv_pair(0, 1, key, value);
-            self.root = new(root);
-            return;
+            root.node_append_kv_pair(req.flags, 1, req.key.clone(), req.stored_value());
+            self.root = self.new_node(root);
+            req.added = true;
+            return true;
+        }
+        let node = match self.tree_insert(req, self.get_node(self.root)) {
+            Some(node) => node,
+            None => return false,
+        };
+        let nodes = node_split3(node, self.page_size);
+        self.del_node(self.root);
+        if nodes.len() > 1 {
+            let first = nodes[0].get_key(0);
+            let root =
+                build_nodes(BNODE_NODE, self.kid_entries(first, nodes), self.page_size).remove(0);
+            self.root = self.new_node(root);
         } else {
-            let node = self.tree_insert(get(self.root), key, value);
-            // println!("{:?}", node.data);
-            let nodes = node_split3(node);
-            del(self.root);
-            if nodes.len() > 1 {
-                let mut root = BNode::new();
-                root.set_header(BNODE_NODE, 2);
-                let mut i = 0;
-                for node in nodes {
-                    println!("{:?}", node.data);
-                    let key = node.get_key(0);
-                    let pointer = new(node);
-                    root.node_append_kv_pair(pointer, i, key, vec![]);
-                    i = i + 1;
+            let data = nodes[0].data.clone();
+            self.root = self.new_node(BNode { data });
+        }
+        return true;
+    }
+    pub fn insert(&mut self, key: Vec<u8>, value: Vec<u8>) {
+        self.update(&mut UpdateReq::new(key, value, MODE_UPSERT));
+    }
+    // fails if the key is already present
+    pub fn insert_new(&mut self, key: Vec<u8>, value: Vec<u8>) -> bool {
+        return self.update(&mut UpdateReq::new(key, value, MODE_INSERT_ONLY));
+    }
+    // fails if the key is missing
+    pub fn update_existing(&mut self, key: Vec<u8>, value: Vec<u8>) -> bool {
+        return self.update(&mut UpdateReq::new(key, value, MODE_UPDATE_ONLY));
+    }
+    // inserts or replaces, returning the value that was there before
+    pub fn upsert(&mut self, key: Vec<u8>, value: Vec<u8>) -> Option<Vec<u8>> {
+        let mut req = UpdateReq::new(key, value, MODE_UPSERT);
+        self.update(&mut req);
+        return req.old;
+    }
+    // rewrites `node` with a sorted run of batch operations. Returns the nodes replacing it
+    // (possibly none, if everything in it was deleted) or `None` if nothing in it changed.
+    // Every touched node is copied exactly once no matter how many keys land in it.
+    pub fn tree_apply(
+        &mut self,
+        node: &BNode,
+        ops: &[(Vec<u8>, Option<Vec<u8>>)],
+        skipped: &mut usize,
+    ) -> io::Result<Option<Vec<BNode>>> {
+        let mut entries: Vec<(u64, Vec<u8>, Vec<u8>)> = vec![];
+        // which of the kids in `entries` were rewritten
+        let mut touched = vec![];
+        let mut changed = false;
+        match node.btype() {
+            BNODE_LEAF => {
+                let mut j = 0;
+                for i in 0..node.nkeys() {
+                    let key = node.get_key(i);
+                    while j < ops.len() && self.comparator.cmp(&ops[j].0, &key) == Ordering::Less {
+                        if let Some(value) = &ops[j].1 {
+                            entries.push((0, ops[j].0.clone(), value.clone()));
+                            changed = true;
+                        }
+                        j += 1;
+                    }
+                    let flags = node.get_pointer(i);
+                    if j < ops.len()
+                        && flags != LEAF_BUCKET
+                        && self.comparator.cmp(&ops[j].0, &key) == Ordering::Equal
+                    {
+                        if let Some(value) = &ops[j].1 {
+                            entries.push((0, key, value.clone()));
+                        }
+                        changed = true;
+                        j += 1;
+                        continue;
+                    }
+                    // a bucket is left alone, put or delete
+                    if j < ops.len() && self.comparator.cmp(&ops[j].0, &key) == Ordering::Equal {
+                        *skipped += 1;
+                        j += 1;
+                    }
+                    entries.push((flags, key, node.get_value(i)));
+                }
+                for (key, value) in &ops[j..] {
+                    if let Some(value) = value {
+                        entries.push((0, key.clone(), value.clone()));
+                        changed = true;
+                    }
+                }
+            }
+            BNODE_NODE => {
+                // a kid owns every key below the first key of the next kid, same as lookup_key
+                let mut j = 0;
+                for i in 0..node.nkeys() {
+                    let end = if i + 1 < node.nkeys() {
+                        let next = node.get_key(i + 1);
+                        let below = |op: &&(Vec<u8>, Option<Vec<u8>>)| {
+                            self.comparator.cmp(&op.0, &next) == Ordering::Less
+                        };
+                        j + ops[j..].iter().take_while(below).count()
+                    } else {
+                        ops.len()
+                    };
+                    let kptr = node.get_pointer(i);
+                    let kids = if end > j {
+                        self.tree_apply(&self.read_node(kptr)?, &ops[j..end], skipped)?
+                    } else {
+                        None
+                    };
+                    j = end;
+                    match kids {
+                        Some(kids) => {
+                            self.del_node(kptr);
+                            changed = true;
+                            touched.resize(entries.len() + kids.len(), true);
+                            entries.extend(self.kid_entries(node.get_key(i), kids));
+                        }
+                        None => {
+                            entries.push((kptr, node.get_key(i), vec![]));
+                            touched.push(false);
+                        }
+                    }
+                }
+            }
+            btype => {
+                let message = format!("node of unknown type {btype}");
+                return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+            }
+        }
+        if !changed {
+            return Ok(None);
+        }
+        if node.btype() == BNODE_NODE {
+            self.rebalance_kids(&mut entries, touched);
+        }
+        // whatever kid comes first now goes under the key the parent has for this node,
+        // and takes it as its own first key when it is an internal node
+        if node.btype() == BNODE_NODE && !entries.is_empty() && entries[0].1 != node.get_key(0) {
+            entries[0].1 = node.get_key(0);
+            let first = self.get_node(entries[0].0);
+            if first.btype() == BNODE_NODE && first.get_key(0) != entries[0].1 {
+                if let Some(first) = self.under_separator(&first, entries[0].1.clone()) {
+                    self.del_node(entries[0].0);
+                    entries[0].0 = self.new_node(first);
                 }
-                self.root = new(root);
+            }
+        }
+        return Ok(Some(build_nodes(node.btype(), entries, self.page_size)));
+    }
+    // what `node_delete` does for the one kid a delete went through, for every rewritten
+    // kid of an internal node: an underfilled one is merged into a sibling when the two
+    // fit a page, or takes entries over from it otherwise
+    fn rebalance_kids(
+        &mut self,
+        entries: &mut Vec<(u64, Vec<u8>, Vec<u8>)>,
+        mut touched: Vec<bool>,
+    ) {
+        let mut i = 0;
+        while i < entries.len() && entries.len() > 1 {
+            if !touched[i] || !self.underfilled(&self.get_node(entries[i].0)) {
+                i += 1;
+                continue;
+            }
+            let (l, r) = if i > 0 { (i - 1, i) } else { (i, i + 1) };
+            let left = self.get_node(entries[l].0);
+            let right = self.get_node(entries[r].0);
+            let Some(right) = self.under_separator(&right, entries[r].1.clone()) else {
+                i += 1;
+                continue;
+            };
+            if merged_size(&left, &right) <= self.page_size {
+                let mut merged = BNode::new();
+                self.node_merge(&left, &right, &mut merged);
+                self.del_node(entries[l].0);
+                self.del_node(entries[r].0);
+                entries[l].0 = self.new_node(merged);
+                entries.remove(r);
+                touched.remove(r);
+                // the merged node may still be underfilled
+                touched[l] = true;
+                i = l;
+            } else if let Some((left, right)) = redistribute(&left, &right, self.page_size) {
+                self.del_node(entries[l].0);
+                self.del_node(entries[r].0);
+                entries[r].1 = self.separator(&left, &right);
+                entries[l].0 = self.new_node(left);
+                entries[r].0 = self.new_node(right);
+                i += 1;
             } else {
-                let data = nodes[0].data.clone();
-                self.root = new(BNode { data });
+                i += 1;
             }
         }
     }
+    // applies all the puts and deletes of the batch in a single pass over the tree.
+    // Returns how many of them were left out because their key is a bucket. Nothing is
+    // written when a key or value is too long for the tree.
+    pub fn write(&mut self, batch: WriteBatch) -> io::Result<usize> {
+        let ops = batch.sorted_ops(&self.comparator);
+        for (key, value) in &ops {
+            if key.is_empty() || key.len() > self.max_key_size() {
+                let message = "the key is empty or too long";
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+            }
+            if value
+                .as_ref()
+                .is_some_and(|value| value.len() > self.max_val_size())
+            {
+                let message = "the value is too long";
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+            }
+        }
+        if ops.is_empty() {
+            return Ok(0);
+        }
+        if self.root == 0 {
+            let mut root = BNode::new();
+            root.set_header(BNODE_LEAF, 1);
+            root.node_append_kv_pair(0, 0, vec![], vec![]);
+            self.root = self.new_node(root);
+        }
+        let mut skipped = 0;
+        let mut nodes = match self.tree_apply(&self.read_node(self.root)?, &ops, &mut skipped)? {
+            Some(nodes) => nodes,
+            None => return Ok(skipped),
+        };
+        self.del_node(self.root);
+        // the leftmost leaf keeps the empty sentinel key, so the tree never empties out
+        assert!(!nodes.is_empty());
+        while nodes.len() > 1 {
+            let first = nodes[0].get_key(0);
+            nodes = build_nodes(BNODE_NODE, self.kid_entries(first, nodes), self.page_size);
+        }
+        let mut root = nodes.pop().unwrap();
+        while root.btype() == BNODE_NODE && root.nkeys() == 1 {
+            let kptr = root.get_pointer(0);
+            root = self.get_node(kptr);
+            self.del_node(kptr);
+        }
+        self.root = self.new_node(root);
+        return Ok(skipped);
+    }
+    pub fn set_merge_operator(&mut self, operator: MergeOperator) {
+        self.merge_operator = Some(operator);
+    }
+    // folds `operand` into the stored value in one descent and returns the new value,
+    // or `None` if the tree has no merge operator, the operator rejects the values or the
+    // merged value would be larger than the largest value
+    pub fn merge(&mut self, key: Vec<u8>, operand: Vec<u8>) -> Option<Vec<u8>> {
+        let mut req = UpdateReq::new(key, operand, MODE_MERGE);
+        if self.update(&mut req) {
+            return Some(req.value);
+        }
+        return None;
+    }
+    // inserts or replaces the key with a value that reads as absent once `ttl` is over.
+    // The expiry is stored in front of the value, which leaves 8 bytes less for it.
+    pub fn insert_with_ttl(
+        &mut self,
+        key: Vec<u8>,
+        value: Vec<u8>,
+        ttl: Duration,
+    ) -> io::Result<()> {
+        if key.is_empty() || key.len() > self.max_key_size() {
+            let message = "the key is empty or too long";
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+        }
+        if value.len() + 8 > self.max_val_size() {
+            let message = "the value is too long";
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+        }
+        let mut req = UpdateReq::new(key, value, MODE_UPSERT);
+        req.flags = LEAF_TTL;
+        req.expires = now_millis().saturating_add(ttl.as_millis() as u64);
+        self.update(&mut req);
+        return Ok(());
+    }
+    // deletes every plain key within the bounds in one pass, returns how many there were
+    pub fn delete_range(&mut self, start: Bound<&[u8]>, end: Bound<&[u8]>) -> usize {
+        let mut batch = WriteBatch::new();
+        let mut iter = self.scan(start, end).include_expired();
+        while let Some((flags, key, _)) = iter.next_entry() {
+            if flags != LEAF_BUCKET {
+                batch.delete(key);
+            }
+        }
+        let count = batch.len();
+        self.write(batch).expect("reading a page");
+        return count;
+    }
+    // removes the expired keys, each run of them next to each other with one range
+    // delete. Returns how many were removed.
+    pub fn sweep_expired(&mut self) -> usize {
+        let mut runs: Vec<(Vec<u8>, Vec<u8>)> = vec![];
+        let mut iter = self
+            .scan(Bound::Unbounded, Bound::Unbounded)
+            .include_expired();
+        let mut in_run = false;
+        while let Some((flags, key, value)) = iter.next_entry() {
+            if !is_expired(flags, &value, iter.now) {
+                in_run = false;
+                continue;
+            }
+            match runs.last_mut() {
+                Some(run) if in_run => run.1 = key,
+                _ => runs.push((key.clone(), key)),
+            }
+            in_run = true;
+        }
+        let mut removed = 0;
+        for (first, last) in runs {
+            removed += self.delete_range(Bound::Included(&first), Bound::Included(&last));
+        }
+        return removed;
+    }
+    // writes `value` only if the current value is `expected` (`None` meaning absent),
+    // otherwise hands back the current value so the caller can retry
+    pub fn compare_and_swap(
+        &mut self,
+        key: Vec<u8>,
+        expected: Option<Vec<u8>>,
+        value: Vec<u8>,
+    ) -> Result<(), Option<Vec<u8>>> {
+        let mut req = UpdateReq::new(key, value, MODE_CAS);
+        req.expected = expected;
+        if self.update(&mut req) {
+            return Ok(());
+        }
+        return Err(req.old);
+    }
     pub fn leaf_delete(&mut self, new_leaf_node: &mut BNode, old_leaf_node: &BNode, index: u16) {
-        println!("leaf delete gets called");
-        println!("old_leaf data: {:?}", old_leaf_node.data);
         new_leaf_node.set_header(old_leaf_node.btype(), old_leaf_node.nkeys() - 1);
+        new_leaf_node.set_prefix(&old_leaf_node.prefix());
         node_append_range(old_leaf_node, new_leaf_node, 0, 0, index);
-        println!(
-            "index:{}  remaining:{}",
-            index,
-            old_leaf_node.nkeys() - index - 1
-        );
         node_append_range(
             old_leaf_node,
             new_leaf_node,
@@ -395,8 +1256,9 @@ impl BTree {
         // println!("updated datata after deletion:{:?}", new_leaf_node.data);
     }
     pub fn node_merge(&mut self, left_node: &BNode, right_node: &BNode, new_node: &mut BNode) {
-        println!("merging nodes");
         new_node.set_header(left_node.btype(), left_node.nkeys() + right_node.nkeys());
+        let prefix = left_node.prefix();
+        new_node.set_prefix(&prefix[..common_prefix_len(&prefix, &right_node.prefix())]);
         node_append_range(left_node, new_node, 0, 0, left_node.nkeys());
         node_append_range(
             right_node,
@@ -405,85 +1267,146 @@ impl BTree {
             0,
             right_node.nkeys(),
         );
-        println!("{:?}", new_node.data);
     }
-    pub fn tree_delete(&mut self, node: &mut BNode, key: Vec<u8>) -> BNode {
-        let index = node.lookup_key(&key);
-        println!("inside_delete");
+    // returns an empty `data` when the key was not found and nothing changed
+    pub fn tree_delete(&mut self, node: &mut BNode, key: Vec<u8>, flags: u64) -> BNode {
+        let index = node.lookup_key_with(&key, &self.comparator);
         match node.btype() {
             BNODE_LEAF => {
-                if !(key.cmp(&node.get_key(index)) == Ordering::Equal) {
-                    return BNode::new();
+                if self.comparator.cmp(&key, &node.get_key(index)) != Ordering::Equal
+                    || !same_kind(node.get_pointer(index), flags)
+                {
+                    return BNode { data: vec![] };
                 }
                 let mut new_node = BNode::new();
                 self.leaf_delete(&mut new_node, node, index);
-                // println!("new node data: {:?}", new_node.data);
                 return new_node;
             }
             BNODE_NODE => {
-                println!("bnode");
-                return self.node_delete(node, index, key);
+                return self.node_delete(node, index, key, flags);
             }
             _ => {
-                return BNode::new();
+                return BNode { data: vec![] };
             }
         }
     }
     pub fn delete(&mut self, key: Vec<u8>) -> bool {
-        assert!(key.len() != 0 && key.len() <= BTREE_MAX_KEY_SIZE);
+        return self.delete_flagged(key, 0);
+    }
+    // deletes the key only if its entry is a bucket entry when `flags` is LEAF_BUCKET
+    // and a plain one otherwise
+    pub fn delete_flagged(&mut self, key: Vec<u8>, flags: u64) -> bool {
+        assert!(!key.is_empty() && key.len() <= self.max_key_size());
         if self.root == 0 {
             return false;
         }
         // println!("key ot be deleted:{:?}", key);
-        let updated_node = self.tree_delete(&mut get(self.root), key);
-        if updated_node.data.len() == 0 {
+        let updated_node = self.tree_delete(&mut self.get_node(self.root), key, flags);
+        if updated_node.data.is_empty() {
             return false;
         }
-        del(self.root);
+        self.del_node(self.root);
 
         if updated_node.btype() == BNODE_NODE && updated_node.nkeys() == 1 {
             self.root = updated_node.get_pointer(0);
         } else {
-            self.root = new(updated_node);
+            self.root = self.new_node(updated_node);
         }
         return true;
     }
-    pub fn node_delete(&mut self, node: &mut BNode, index: u16, key: Vec<u8>) -> BNode {
-        println!("inside_node_delete");
+    pub fn node_delete(&mut self, node: &mut BNode, index: u16, key: Vec<u8>, flags: u64) -> BNode {
         let pointer = node.get_pointer(index);
-        let mut updated_node = self.tree_delete(&mut get(pointer), key);
-        if updated_node.data.len() == 0 {
-            return BNode::new();
+        let mut updated_node = self.tree_delete(&mut self.get_node(pointer), key, flags);
+        if updated_node.data.is_empty() {
+            return updated_node;
         }
-        println!("{:?}", updated_node.data);
-        del(pointer);
+        self.del_node(pointer);
         let mut new_node = BNode::new();
-        println!("{index}");
         let (merge_dir, sibling) = self.should_merge(&mut updated_node, node, index);
-        println!("merge_direction:{merge_dir}");
         if merge_dir < 0 {
             let mut merged = BNode::new();
             self.node_merge(&sibling, &updated_node, &mut merged);
-            del(node.get_pointer(index - 1));
-            let key = merged.get_key(0);
-            let pointer = new(merged);
+            self.del_node(node.get_pointer(index - 1));
+            let key = node.get_key(index - 1);
+            let pointer = self.new_node(merged);
             BTree::node_replace_kid2(&mut new_node, node, index - 1, pointer, key)
         }
         if merge_dir > 0 {
             let mut merged = BNode::new();
             self.node_merge(&updated_node, &sibling, &mut merged);
-            del(node.get_pointer(index + 1));
-            let key = merged.get_key(0);
-            let pointer = new(merged);
-            BTree::node_replace_kid2(&mut new_node, node, index - 1, pointer, key)
+            self.del_node(node.get_pointer(index + 1));
+            let key = node.get_key(index);
+            let pointer = self.new_node(merged);
+            BTree::node_replace_kid2(&mut new_node, node, index, pointer, key)
         }
-        if merge_dir == 0 {
-            assert!(updated_node.nkeys() > 0);
-            println!("shouldn't merge");
-            self.node_replace_kidN(&mut new_node, index, &node, vec![updated_node]);
+        if merge_dir == 0 && updated_node.nkeys() == 0 {
+            // an emptied kid with no sibling to merge into is just dropped
+            self.leaf_delete(&mut new_node, node, index);
+        } else if merge_dir == 0
+            && !self.borrow_from_sibling(&mut new_node, node, index, &updated_node)
+        {
+            self.node_replace_kidN(&mut new_node, index, node, vec![updated_node]);
         }
         return new_node;
     }
+    // when `kid`, the new version of the kid at `index`, is underfilled but can not be
+    // merged, entries move over from the fuller of its siblings so both end up about half
+    // full. The parent with both kids and the separator of the right one goes into
+    // `new_node`. Returns false when there is no sibling to borrow from.
+    fn borrow_from_sibling(
+        &mut self,
+        new_node: &mut BNode,
+        node: &BNode,
+        index: u16,
+        kid: &BNode,
+    ) -> bool {
+        if !self.underfilled(kid) {
+            return false;
+        }
+        let mut siblings = vec![];
+        if index > 0 {
+            siblings.push((index - 1, self.get_node(node.get_pointer(index - 1))));
+        }
+        if index + 1 < node.nkeys() {
+            siblings.push((index + 1, self.get_node(node.get_pointer(index + 1))));
+        }
+        let Some((sibling_index, sibling)) = siblings.into_iter().max_by_key(|(_, n)| n.size())
+        else {
+            return false;
+        };
+        let kid = BNode {
+            data: kid.data.clone(),
+        };
+        // the right one of the two goes under the separator the parent has for it
+        let (first, pair) = if sibling_index < index {
+            let kid = self.under_separator(&kid, node.get_key(index));
+            let pair = kid.and_then(|kid| redistribute(&sibling, &kid, self.page_size));
+            (sibling_index, pair)
+        } else {
+            let sibling = self.under_separator(&sibling, node.get_key(sibling_index));
+            let pair = sibling.and_then(|sibling| redistribute(&kid, &sibling, self.page_size));
+            (index, pair)
+        };
+        let Some((left, right)) = pair else {
+            return false;
+        };
+        let mut entries = vec![];
+        for i in 0..node.nkeys() {
+            entries.push((node.get_pointer(i), node.get_key(i), vec![]));
+        }
+        entries[first as usize + 1].1 = self.separator(&left, &right);
+        // the new separator may be longer than the old one, or share less of the prefix
+        let mut parent = build_nodes(BNODE_NODE, entries, self.page_size);
+        if parent.len() > 1 {
+            return false;
+        }
+        let mut parent = parent.remove(0);
+        self.del_node(node.get_pointer(sibling_index));
+        parent.set_pointer(first, self.new_node(left));
+        parent.set_pointer(first + 1, self.new_node(right));
+        *new_node = parent;
+        return true;
+    }
     pub fn node_replace_kid2(
         new_node: &mut BNode,
         old_node: &BNode,
@@ -492,6 +1415,12 @@ impl BTree {
         key: Vec<u8>,
     ) {
         new_node.set_header(old_node.btype(), old_node.nkeys() - 1);
+        let prefix = old_node.prefix();
+        if key.is_empty() {
+            new_node.set_prefix(&prefix);
+        } else {
+            new_node.set_prefix(&prefix[..common_prefix_len(&prefix, &key)]);
+        }
         node_append_range(old_node, new_node, 0, 0, index);
         // [2,4,5,6,10,12,14]-new_node key
         // [2,4,5,6,7,10,12,14]-old_node key
@@ -514,54 +1443,178 @@ impl BTree {
         )
     }
 
+    // a node below half full is merged into a sibling or takes entries over from one
+    pub fn underfilled(&self, node: &BNode) -> bool {
+        return node.size() < self.page_size / 2;
+    }
     pub fn should_merge(
         &mut self,
         updated_node: &mut BNode,
         old_node: &BNode,
         index: u16,
     ) -> (i8, BNode) {
-        if BTREE_PAGE_SIZE / 4 < updated_node.size() as usize {
-            println!("size of updated_node:{:?}", updated_node.size());
+        if !self.underfilled(updated_node) {
             return (0, BNode::new());
         }
+        // the right one of the two goes under the separator the parent has for it
         if index > 0 {
-            let sibling = get(old_node.get_pointer(index - 1));
-            let size = sibling.size() + updated_node.size() - HEADER;
-            println!("sibling size:{size}");
-            if BTREE_PAGE_SIZE >= size as usize {
+            let sibling = self.get_node(old_node.get_pointer(index - 1));
+            let right = self.under_separator(updated_node, old_node.get_key(index));
+            if let Some(right) = right.filter(|right| {
+                return self.page_size >= merged_size(&sibling, right);
+            }) {
+                *updated_node = right;
                 return (-1, sibling);
             }
         }
         if index + 1 < old_node.nkeys() {
-            let sibling = get(old_node.get_pointer(index + 1));
-            let size = sibling.size() + updated_node.size() - HEADER;
-            if BTREE_PAGE_SIZE >= size as usize {
-                return (1, sibling);
+            let sibling = self.get_node(old_node.get_pointer(index + 1));
+            let right = self.under_separator(&sibling, old_node.get_key(index + 1));
+            if let Some(right) = right.filter(|right| {
+                return self.page_size >= merged_size(updated_node, right);
+            }) {
+                return (1, right);
             }
         }
         return (0, BNode::new());
     }
-    pub fn search(&mut self, key: &Vec<u8>) -> (bool, u16, BNode) {
-        let root_node = get(self.root);
+    // an error where a page of the file can not be read, as in a damaged file
+    pub fn get(&self, key: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        return Ok(self.try_get_entry(key)?.map(|(_, value)| value));
+    }
+    // the other reads panic where a page can not be read, their try_ versions return
+    // the error instead
+    // the leaf flags and value stored under the key
+    pub fn get_entry(&self, key: &[u8]) -> Option<(u64, Vec<u8>)> {
+        return self.try_get_entry(key).expect("reading a page");
+    }
+    pub fn try_get_entry(&self, key: &[u8]) -> io::Result<Option<(u64, Vec<u8>)>> {
+        let mut iter = self.try_scan(Bound::Included(key), Bound::Included(key))?;
+        let entry = iter.try_next_entry()?;
+        return Ok(entry.map(|(flags, _, value)| (flags, value)));
+    }
+    // iterates over the keys within the bounds, in the order of the tree's comparator
+    pub fn scan(&self, start: Bound<&[u8]>, end: Bound<&[u8]>) -> BIter<'_> {
+        return self.try_scan(start, end).expect("reading a page");
+    }
+    pub fn try_scan(&self, start: Bound<&[u8]>, end: Bound<&[u8]>) -> io::Result<BIter<'_>> {
+        let mut iter = BIter {
+            tree: self,
+            path: vec![],
+            pos: vec![],
+            end: end.map(|key| key.to_vec()),
+            now: now_millis(),
+            expired: false,
+        };
+        if self.root == 0 {
+            return Ok(iter);
+        }
+        let (key, excluded): (&[u8], bool) = match start {
+            Bound::Included(key) => (key, false),
+            Bound::Excluded(key) => (key, true),
+            Bound::Unbounded => (&[], false),
+        };
+        let mut node = self.read_node(self.root)?;
+        loop {
+            let index = node.lookup_key_with(key, &self.comparator);
+            if node.btype() == BNODE_LEAF {
+                iter.path.push(node);
+                iter.pos.push(index);
+                break;
+            }
+            let kptr = node.get_pointer(index);
+            iter.path.push(node);
+            iter.pos.push(index);
+            node = self.read_node(kptr)?;
+        }
+        // lookup_key lands on the last key <= start, step past what is below the start
+        while iter.valid() {
+            let current = iter.key();
+            let order = self.comparator.cmp(&current, key);
+            if !current.is_empty()
+                && (order == Ordering::Greater || (order == Ordering::Equal && !excluded))
+            {
+                break;
+            }
+            iter.advance(iter.path.len() - 1)?;
+        }
+        return Ok(iter);
+    }
+    // roughly how many keys lie within the bounds, from two root-to-leaf descents instead
+    // of a scan. Exact within a single leaf, close for evenly filled trees.
+    pub fn estimate_count(&self, start: Bound<&[u8]>, end: Bound<&[u8]>) -> u64 {
+        if self.root == 0 {
+            return 0;
+        }
+        // the sentinel sorts first and is not counted
+        let (low, count) = match start {
+            Bound::Included(key) if !key.is_empty() => self.locate(key, false),
+            Bound::Excluded(key) => self.locate(key, true),
+            _ => self.locate(&[], true),
+        };
+        let high = match end {
+            Bound::Included(key) => self.locate(key, true).0,
+            Bound::Excluded(key) => self.locate(key, false).0,
+            Bound::Unbounded => 1.0,
+        };
+        return ((high - low).max(0.0) * count).round() as u64;
+    }
+    // the share of all keys that sort before `key` (or up to it when `inclusive`) and the
+    // number of keys, both read off the path from the root to `key`: every node is taken
+    // to have as many children as the one on the path at its level
+    fn locate(&self, key: &[u8], inclusive: bool) -> (f64, f64) {
+        let mut node = self.get_node(self.root);
+        let mut share = 0.0;
+        let mut width = 1.0;
+        let mut count = 1.0;
+        loop {
+            if node.nkeys() == 0 {
+                return (share, 0.0);
+            }
+            let nkeys = node.nkeys() as f64;
+            let index = node.lookup_key_with(key, &self.comparator);
+            if node.btype() == BNODE_LEAF {
+                let order = self.comparator.cmp(&node.get_key(index), key);
+                let mut before = index as f64;
+                if order == Ordering::Less || (order == Ordering::Equal && inclusive) {
+                    before += 1.0;
+                }
+                return (share + width * before / nkeys, count * nkeys);
+            }
+            share += width * index as f64 / nkeys;
+            width /= nkeys;
+            count *= nkeys;
+            node = self.get_node(node.get_pointer(index));
+        }
+    }
+    pub fn search(&mut self, key: &[u8]) -> (bool, u16, BNode) {
+        let now = now_millis();
+        let root_node = self.get_node(self.root);
         let mut found = false;
-        let index = root_node.lookup_key(&key);
-        println!("indexo:{index}");
+        let index = root_node.lookup_key_with(key, &self.comparator);
         match root_node.btype() {
             BNODE_LEAF => {
-                let index = root_node.lookup_key(key);
-                if root_node.get_key(index).cmp(&key) == Ordering::Equal {
+                if self.comparator.cmp(&root_node.get_key(index), key) == Ordering::Equal
+                    && !is_expired(
+                        root_node.get_pointer(index),
+                        &root_node.get_value(index),
+                        now,
+                    )
+                {
                     found = true;
                 }
                 return (found, index, root_node);
             }
             BNODE_NODE => {
-                let mut node = get(root_node.get_pointer(index));
+                let mut node = self.get_node(root_node.get_pointer(index));
                 while node.btype() != BNODE_LEAF {
-                    let index = node.lookup_key(&key);
-                    node = get(node.get_pointer(index));
+                    let index = node.lookup_key_with(key, &self.comparator);
+                    node = self.get_node(node.get_pointer(index));
                 }
-                let index = node.lookup_key(&key);
-                if node.get_key(index).cmp(&key) == Ordering::Equal {
+                let index = node.lookup_key_with(key, &self.comparator);
+                if self.comparator.cmp(&node.get_key(index), key) == Ordering::Equal
+                    && !is_expired(node.get_pointer(index), &node.get_value(index), now)
+                {
                     found = true;
                 }
                 return (found, index, node);
@@ -572,65 +1625,320 @@ impl BTree {
         }
     }
 }
-pub fn node_split2(old_node: &BNode, left_node: &mut BNode, right_node: &mut BNode) {
-    // println!("konichiwa");
-    let split_index = old_node.nkeys() / 2;
-    // println!("{split_index}");
+// the leaf flags, key and value of an entry
+pub type Entry = (u64, Vec<u8>, Vec<u8>);
+// walks the leaves in key order. It keeps the path from the root, so moving on to the
+// next leaf only climbs as far up as needed instead of searching from the root again.
+pub struct BIter<'a> {
+    tree: &'a BTree,
+    path: Vec<BNode>,
+    pos: Vec<u16>,
+    end: Bound<Vec<u8>>,
+    // expiry is judged against the time the scan started
+    now: u64,
+    // hand out expired entries too, with their expiry still in front of the value
+    expired: bool,
+}
+impl BIter<'_> {
+    pub fn valid(&self) -> bool {
+        match (self.path.last(), self.pos.last()) {
+            (Some(leaf), Some(pos)) => *pos < leaf.nkeys(),
+            _ => false,
+        }
+    }
+    pub fn key(&self) -> Vec<u8> {
+        return self.path.last().unwrap().get_key(*self.pos.last().unwrap());
+    }
+    pub fn value(&self) -> Vec<u8> {
+        return self
+            .path
+            .last()
+            .unwrap()
+            .get_value(*self.pos.last().unwrap());
+    }
+    // hands out expired entries too, with their expiry still in front of the value
+    pub fn include_expired(mut self) -> Self {
+        self.expired = true;
+        return self;
+    }
+    // the next entry with its leaf flags
+    pub fn next_entry(&mut self) -> Option<Entry> {
+        return self.try_next_entry().expect("reading a page");
+    }
+    pub fn try_next_entry(&mut self) -> io::Result<Option<Entry>> {
+        while self.valid() {
+            let key = self.key();
+            let past_end = match &self.end {
+                Bound::Included(end) => self.tree.comparator.cmp(&key, end) == Ordering::Greater,
+                Bound::Excluded(end) => self.tree.comparator.cmp(&key, end) != Ordering::Less,
+                Bound::Unbounded => false,
+            };
+            if past_end {
+                self.path.clear();
+                self.pos.clear();
+                return Ok(None);
+            }
+            let leaf = self.path.last().unwrap();
+            let flags = leaf.get_pointer(*self.pos.last().unwrap());
+            let mut value = self.value();
+            self.advance(self.path.len() - 1)?;
+            if flags == LEAF_TTL && !self.expired {
+                if is_expired(flags, &value, self.now) {
+                    continue;
+                }
+                value.drain(..value.len().min(8));
+            }
+            return Ok(Some((flags, key, value)));
+        }
+        return Ok(None);
+    }
+    // moves the position at `level` one step right, climbing up when the node runs out
+    fn advance(&mut self, level: usize) -> io::Result<bool> {
+        if self.pos[level] + 1 < self.path[level].nkeys() {
+            self.pos[level] += 1;
+            return Ok(true);
+        }
+        if level == 0 || !self.advance(level - 1)? {
+            self.pos[level] = self.path[level].nkeys();
+            return Ok(false);
+        }
+        let parent = &self.path[level - 1];
+        self.path[level] = self
+            .tree
+            .read_node(parent.get_pointer(self.pos[level - 1]))?;
+        self.pos[level] = 0;
+        if self.path[level].nkeys() == 0 {
+            return self.advance(level);
+        }
+        return Ok(true);
+    }
+}
+impl Iterator for BIter<'_> {
+    type Item = (Vec<u8>, Vec<u8>);
+    fn next(&mut self) -> Option<Self::Item> {
+        return self.next_entry().map(|(_, key, value)| (key, value));
+    }
+}
+// the size of a node being filled one entry at a time. Its prefix is what all the keys
+// so far have in common, so it can only get shorter as entries are added.
+pub struct NodeSize {
+    nkeys: usize,
+    // pointers, offsets and key-value pairs with whole keys
+    bytes: usize,
+    // what the keys but the sentinel share, `None` before the first such key
+    prefix: Option<Vec<u8>>,
+    sentinel: bool,
+}
+impl Default for NodeSize {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl NodeSize {
+    pub fn new() -> NodeSize {
+        return NodeSize {
+            nkeys: 0,
+            bytes: 0,
+            prefix: None,
+            sentinel: false,
+        };
+    }
+    // the prefix once `key` is in
+    fn prefix_len_with(&self, key: &[u8]) -> usize {
+        match &self.prefix {
+            _ if key.is_empty() => return self.prefix().len(),
+            Some(prefix) => return common_prefix_len(prefix, key),
+            None => return key.len().min(BTREE_MAX_PREFIX),
+        }
+    }
+    fn total(&self, nkeys: usize, bytes: usize, prefix_len: usize, sentinel: bool) -> usize {
+        let prefixed = nkeys - sentinel as usize;
+        return HEADER as usize + 2 + prefix_len + bytes - prefixed * prefix_len;
+    }
+    // the size of the node if the entry was added
+    pub fn with(&self, key: &[u8], value: &[u8]) -> usize {
+        // pointer + offset + |key_length|value_length|key|value|
+        let bytes = self.bytes + 8 + 4 + KV_HEADER as usize + key.len() + value.len();
+        let sentinel = self.sentinel || key.is_empty();
+        return self.total(self.nkeys + 1, bytes, self.prefix_len_with(key), sentinel);
+    }
+    pub fn add(&mut self, key: &[u8], value: &[u8]) {
+        self.bytes += 8 + 4 + KV_HEADER as usize + key.len() + value.len();
+        self.nkeys += 1;
+        if key.is_empty() {
+            self.sentinel = true;
+        } else {
+            self.prefix = Some(key[..self.prefix_len_with(key)].to_vec());
+        }
+    }
+    pub fn size(&self) -> usize {
+        return self.total(self.nkeys, self.bytes, self.prefix().len(), self.sentinel);
+    }
+    pub fn prefix(&self) -> &[u8] {
+        return self.prefix.as_deref().unwrap_or(&[]);
+    }
+}
+// what the node merging `left` and `right` takes, with the prefix the two have in common
+pub fn merged_size(left: &BNode, right: &BNode) -> usize {
+    let prefix_len = common_prefix_len(&left.prefix(), &right.prefix());
+    let mut size = HEADER as usize + 2 + prefix_len;
+    for node in [left, right] {
+        // every key gets back the part of the node's prefix the merged node does not keep
+        let own = node.prefix_len() as usize;
+        let prefixed = node.nkeys() as usize - node.starts_with_sentinel() as usize;
+        size += node.size() - (HEADER as usize + 2 + own) + prefixed * (own - prefix_len);
+    }
+    return size;
+}
+// packs sorted (pointer, key, value) entries into as few pages of `btype` as they fit in
+pub fn build_nodes(
+    btype: u16,
+    entries: Vec<(u64, Vec<u8>, Vec<u8>)>,
+    page_size: usize,
+) -> Vec<BNode> {
+    let mut nodes = vec![];
+    let mut entries = entries.into_iter().peekable();
+    while entries.peek().is_some() {
+        let mut page = vec![];
+        let mut size = NodeSize::new();
+        while let Some((_, key, value)) = entries.peek() {
+            if !page.is_empty() && size.with(key, value) > page_size {
+                break;
+            }
+            size.add(key, value);
+            page.push(entries.next().unwrap());
+        }
+        let mut node = BNode::new();
+        node.set_header(btype, page.len() as u16);
+        node.set_prefix(size.prefix());
+        for (i, (pointer, key, value)) in (0_u16..).zip(page) {
+            node.node_append_kv_pair(pointer, i, key, value);
+        }
+        nodes.push(node);
+    }
+    return nodes;
+}
+// the entries of two neighbouring nodes spread over two nodes of about the same size.
+// `None` when that would not move anything or one of the halves would not fit a page.
+pub fn redistribute(left: &BNode, right: &BNode, page_size: usize) -> Option<(BNode, BNode)> {
+    let mut entries = vec![];
+    for node in [left, right] {
+        for i in 0..node.nkeys() {
+            entries.push((node.get_pointer(i), node.get_key(i), node.get_value(i)));
+        }
+    }
+    // pointer + offset + |key_length|value_length|key|value|, as in NodeSize
+    let sizes: Vec<usize> = entries
+        .iter()
+        .map(|(_, key, value)| 8 + 4 + KV_HEADER as usize + key.len() + value.len())
+        .collect();
+    let total: usize = sizes.iter().sum();
+    let (mut split, mut before) = (0, 0);
+    while split < entries.len() && (before + sizes[split]) * 2 <= total {
+        before += sizes[split];
+        split += 1;
+    }
+    // the entry across the middle goes to the side it leaves closer to half
+    if split < entries.len() && (before + sizes[split]) * 2 - total < total - before * 2 {
+        split += 1;
+    }
+    let split = split.clamp(1, entries.len() - 1);
+    let fits = |entries: &[(u64, Vec<u8>, Vec<u8>)]| {
+        let mut size = NodeSize::new();
+        for (_, key, value) in entries {
+            size.add(key, value);
+        }
+        return size.size() <= page_size;
+    };
+    if split == left.nkeys() as usize || !fits(&entries[..split]) || !fits(&entries[split..]) {
+        return None;
+    }
+    let right_entries = entries.split_off(split);
+    let btype = left.btype();
+    let left = build_nodes(btype, entries, page_size).remove(0);
+    let right = build_nodes(btype, right_entries, page_size).remove(0);
+    return Some((left, right));
+}
+// moves the entries of `old_node` into `left_node` and `right_node`, split where the
+// larger of the two is smallest but with no more on the right than fits a page. Each
+// half keeps the prefix its own keys share, which may be longer than the one of
+// `old_node`, so the sizes are those of the halves once their prefixes are taken out.
+pub fn node_split2(
+    old_node: &BNode,
+    left_node: &mut BNode,
+    right_node: &mut BNode,
+    page_size: usize,
+) {
+    let nkeys = old_node.nkeys() as usize;
+    let entries: Vec<(Vec<u8>, Vec<u8>)> = (0..old_node.nkeys())
+        .map(|i| (old_node.get_key(i), old_node.get_value(i)))
+        .collect();
+    // sizes of the first j entries and of the entries from j on
+    let mut left_sizes = vec![0; nkeys + 1];
+    let mut size = NodeSize::new();
+    for (j, (key, value)) in entries.iter().enumerate() {
+        size.add(key, value);
+        left_sizes[j + 1] = size.size();
+    }
+    let mut right_sizes = vec![0; nkeys + 1];
+    let mut size = NodeSize::new();
+    for (j, (key, value)) in entries.iter().enumerate().rev() {
+        size.add(key, value);
+        right_sizes[j] = size.size();
+    }
+    let split_index = (1..nkeys)
+        .filter(|j| right_sizes[*j] <= page_size)
+        .min_by_key(|j| left_sizes[*j].max(right_sizes[*j]))
+        .unwrap_or(nkeys - 1);
+    let prefix = |keys: &[(Vec<u8>, Vec<u8>)]| {
+        let mut size = NodeSize::new();
+        for (key, _) in keys {
+            size.add(key, &[]);
+        }
+        return size.prefix().to_vec();
+    };
 
-    left_node.set_header(old_node.btype(), split_index);
-    right_node.set_header(old_node.btype(), old_node.nkeys() - split_index);
-    node_append_range(old_node, left_node, 0, 0, split_index);
-    // println!("append to left_child done");
-    // println!("{:?}", left_node.data);
+    left_node.set_header(old_node.btype(), split_index as u16);
+    left_node.set_prefix(&prefix(&entries[..split_index]));
+    right_node.set_header(old_node.btype(), (nkeys - split_index) as u16);
+    right_node.set_prefix(&prefix(&entries[split_index..]));
+    node_append_range(old_node, left_node, 0, 0, split_index as u16);
     node_append_range(
         old_node,
         right_node,
         0,
-        split_index,
-        old_node.nkeys() - split_index,
+        split_index as u16,
+        (nkeys - split_index) as u16,
     );
 }
-pub fn node_split3(old_node: BNode) -> Vec<BNode> {
-    if BTREE_PAGE_SIZE > old_node.size() as usize {
-        // println!("{:?}", old_node.size());
-        // println!("here");
-        // println!("{:?}", old_node.data);
-        let mut modified_node = BNode::new();
-        // println!("size less than page size");
-        // println!("size-{}", old_node.size());
-        modified_node
-            .data
-            .copy_from_slice(&old_node.data[..BTREE_PAGE_SIZE]);
-        // println!("{}", modified_node.size());
-        return vec![modified_node];
-    } else {
-        let mut left_node = BNode {
-            data: vec![0; 2 * BTREE_PAGE_SIZE],
-        };
-        let mut right_node = BNode::new();
-
-        node_split2(&old_node, &mut left_node, &mut right_node);
-        // println!("size-{}", right_node.size());
-        // println!("size-{}", left_node.size());
-        if BTREE_PAGE_SIZE > left_node.size() as usize {
-            let mut modified_node = BNode::new();
-            modified_node
-                .data
-                .copy_from_slice(&left_node.data[..BTREE_PAGE_SIZE]);
-            return vec![modified_node, right_node];
-        } else {
-            let mut extreme_left_node = BNode::new();
-            let mut middle_node = BNode::new();
-            node_split2(&left_node, &mut extreme_left_node, &mut middle_node);
-            return vec![extreme_left_node, middle_node, right_node];
-        }
+// the node as it goes into pages: itself when it fits, or else split into the fewest
+// nodes that do, usually two or three
+pub fn node_split3(mut old_node: BNode, page_size: usize) -> Vec<BNode> {
+    if page_size > old_node.size() {
+        old_node.data.resize(page_size, 0);
+        return vec![old_node];
     }
+    let mut left_node = BNode::new();
+    let mut right_node = BNode::new();
+    node_split2(&old_node, &mut left_node, &mut right_node, page_size);
+    right_node.data.resize(page_size, 0);
+    let mut nodes = node_split3(left_node, page_size);
+    nodes.push(right_node);
+    return nodes;
 }
 // the test covers insertion,deletion,merging,splitting in the b+ tree
 #[cfg(test)]
+// the first tests are kept as they were written, unused bindings and all
+#[allow(
+    unused_imports,
+    unused_variables,
+    clippy::clone_on_copy,
+    clippy::useless_vec
+)]
 mod test {
 
     use std::clone;
+    use std::sync::Arc;
 
     use super::*;
 
@@ -690,6 +1998,15 @@ mod test {
         assert_eq!(node.data.len(), BTREE_PAGE_SIZE);
     }
     #[test]
+    fn size_counts_the_last_pair() {
+        let mut node = BNode::new();
+        node.set_header(BNODE_LEAF, 2);
+        node.node_append_kv_pair(0, 0, vec![], vec![]);
+        node.node_append_kv_pair(0, 1, b"key".to_vec(), b"value".to_vec());
+        // the last pair is its two lengths, 2 bytes each, then the key and the value
+        assert_eq!(node.size(), node.kvpos(1) + 4 + 3 + 5);
+    }
+    #[test]
     fn looking_up_index_to_insert_key() {
         // println!("here");
         let mut node = BNode::new();
@@ -842,4 +2159,599 @@ mod test {
         // even after deleting all the keys we still have the vec![] which was inserted at the start of the insert operation
         assert!(root_node.nkeys() == 1);
     }
+    #[test]
+    fn checking_conditional_writes() {
+        let mut tree = BTree::new();
+        assert!(!tree.update_existing(vec![1, 2, 3], vec![4]));
+        assert_eq!(tree.root, 0);
+        assert!(tree.insert_new(vec![1, 2, 3], vec![4]));
+        assert!(!tree.insert_new(vec![1, 2, 3], vec![5]));
+        assert!(tree.insert_new(vec![7, 8], vec![9]));
+        let (found, index, node) = tree.search(&[1, 2, 3]);
+        assert!(found);
+        assert_eq!(node.get_value(index), vec![4]);
+        assert!(tree.update_existing(vec![1, 2, 3], vec![6]));
+        assert!(!tree.update_existing(vec![2], vec![6]));
+        // updating a key must leave the keys after it alone
+        let (found, index, node) = tree.search(&[7, 8]);
+        assert!(found);
+        assert_eq!(node.get_value(index), vec![9]);
+        assert_eq!(node.nkeys(), 3);
+        assert_eq!(tree.upsert(vec![1, 2, 3], vec![10]), Some(vec![6]));
+        assert_eq!(tree.upsert(vec![5], vec![11]), None);
+        let (found, index, node) = tree.search(&[5]);
+        assert!(found);
+        assert_eq!(node.get_value(index), vec![11]);
+    }
+    #[test]
+    fn checking_compare_and_swap() {
+        let mut tree = BTree::new();
+        let lock = b"lock".to_vec();
+        assert_eq!(
+            tree.compare_and_swap(lock.clone(), None, b"owner-a".to_vec()),
+            Ok(())
+        );
+        assert_eq!(
+            tree.compare_and_swap(lock.clone(), None, b"owner-b".to_vec()),
+            Err(Some(b"owner-a".to_vec()))
+        );
+        assert_eq!(
+            tree.compare_and_swap(lock.clone(), Some(b"owner-b".to_vec()), vec![]),
+            Err(Some(b"owner-a".to_vec()))
+        );
+        assert_eq!(
+            tree.compare_and_swap(lock.clone(), Some(b"owner-a".to_vec()), b"owner-b".to_vec()),
+            Ok(())
+        );
+        assert_eq!(
+            tree.compare_and_swap(b"other".to_vec(), Some(vec![1]), vec![2]),
+            Err(None)
+        );
+        let (found, index, node) = tree.search(&lock);
+        assert!(found);
+        assert_eq!(node.get_value(index), b"owner-b".to_vec());
+        let (found, _, _) = tree.search(b"other");
+        assert!(!found);
+    }
+    #[test]
+    fn checking_merge_operators() {
+        let mut counters = BTree::new();
+        counters.set_merge_operator(MergeOperator::Add);
+        let key = b"hits".to_vec();
+        assert_eq!(
+            counters.merge(key.clone(), 5i64.to_le_bytes().to_vec()),
+            Some(5i64.to_le_bytes().to_vec())
+        );
+        counters.merge(key.clone(), (-2i64).to_le_bytes().to_vec());
+        let (found, index, node) = counters.search(&key);
+        assert!(found);
+        assert_eq!(node.get_value(index), 3i64.to_le_bytes().to_vec());
+        // values that are not 8-byte i64 are left as they are
+        assert_eq!(counters.merge(key.clone(), vec![1, 2]), None);
+        counters.insert(b"text".to_vec(), b"abc".to_vec());
+        assert_eq!(
+            counters.merge(b"text".to_vec(), 1i64.to_le_bytes().to_vec()),
+            None
+        );
+        assert_eq!(
+            counters.get(&key).unwrap(),
+            Some(3i64.to_le_bytes().to_vec())
+        );
+        assert_eq!(counters.get(b"text").unwrap(), Some(b"abc".to_vec()));
+        assert_eq!(BTree::new().merge(key.clone(), vec![1]), None);
+
+        let mut lists = BTree::new();
+        lists.set_merge_operator(MergeOperator::Append);
+        lists.merge(b"log".to_vec(), vec![1, 2]);
+        assert_eq!(lists.merge(b"log".to_vec(), vec![3]), Some(vec![1, 2, 3]));
+        assert_eq!(lists.merge(b"log".to_vec(), vec![0; 2998]), None);
+
+        let mut highs = BTree::new();
+        highs.set_merge_operator(MergeOperator::Max);
+        highs.merge(b"temp".to_vec(), vec![20]);
+        assert_eq!(highs.merge(b"temp".to_vec(), vec![12]), Some(vec![20]));
+        assert_eq!(highs.merge(b"temp".to_vec(), vec![31]), Some(vec![31]));
+        highs.set_merge_operator(MergeOperator::Min);
+        assert_eq!(highs.merge(b"temp".to_vec(), vec![12]), Some(vec![12]));
+
+        let mut custom = BTree::new();
+        custom.set_merge_operator(MergeOperator::Custom(Box::new(|old, operand| {
+            let count = old.map(|v| v[0]).unwrap_or(0);
+            return vec![count + operand.len() as u8];
+        })));
+        custom.merge(b"k".to_vec(), vec![9, 9]);
+        assert_eq!(custom.merge(b"k".to_vec(), vec![9]), Some(vec![3]));
+    }
+    #[test]
+    fn checking_write_batch() {
+        let mut tree = BTree::new();
+        let mut batch = WriteBatch::new();
+        for i in (0..1000u32).rev() {
+            batch.put(format!("key{:05}", i).into_bytes(), vec![7; 40]);
+        }
+        batch.put(b"key00003".to_vec(), vec![1]);
+        tree.write(batch).unwrap();
+        assert!(get(tree.root).btype() == BNODE_NODE);
+        for i in 0..1000u32 {
+            let key = format!("key{:05}", i).into_bytes();
+            let (found, index, node) = tree.search(&key);
+            assert!(found);
+            let expected = if i == 3 { vec![1] } else { vec![7; 40] };
+            assert_eq!(node.get_value(index), expected);
+        }
+
+        let mut batch = WriteBatch::new();
+        for i in 0..1000u32 {
+            if i % 3 == 0 {
+                batch.delete(format!("key{:05}", i).into_bytes());
+            }
+        }
+        batch.put(b"key01500".to_vec(), vec![2]);
+        batch.delete(b"missing".to_vec());
+        assert_eq!(batch.len(), 336);
+        tree.write(batch).unwrap();
+        for i in 0..1000u32 {
+            let key = format!("key{:05}", i).into_bytes();
+            let (found, _, _) = tree.search(&key);
+            assert_eq!(found, i % 3 != 0);
+        }
+        let (found, index, node) = tree.search(b"key01500");
+        assert!(found);
+        assert_eq!(node.get_value(index), vec![2]);
+
+        let mut batch = WriteBatch::new();
+        for i in 0..1000u32 {
+            batch.delete(format!("key{:05}", i).into_bytes());
+        }
+        batch.delete(b"key01500".to_vec());
+        tree.write(batch).unwrap();
+        let root_node = get(tree.root);
+        assert!(root_node.btype() == BNODE_LEAF);
+        assert!(root_node.nkeys() == 1);
+    }
+    #[test]
+    fn write_batch_rebalances_and_skips_buckets() {
+        // every node but the root, the size of the smallest
+        fn smallest(tree: &BTree, pointer: u64, root: bool) -> usize {
+            let node = tree.get_node(pointer);
+            let mut size = if root { usize::MAX } else { node.size() };
+            if node.btype() == BNODE_NODE {
+                for i in 0..node.nkeys() {
+                    size = size.min(smallest(tree, node.get_pointer(i), false));
+                }
+            }
+            return size;
+        }
+        let mut tree = BTree::new();
+        let mut batch = WriteBatch::new();
+        for i in 0..3000u32 {
+            batch.put(format!("key{:05}", i).into_bytes(), vec![7; 40]);
+        }
+        tree.write(batch).unwrap();
+        // deletes that leave every leaf with a few keys but never empty one
+        let mut batch = WriteBatch::new();
+        for i in 0..3000u32 {
+            if i % 10 != 0 {
+                batch.delete(format!("key{:05}", i).into_bytes());
+            }
+        }
+        assert_eq!(tree.write(batch).unwrap(), 0);
+        // at least half full but for the one pair a split may leave on the other side
+        let pair = 8 + 4 + KV_HEADER as usize + 8 + 40;
+        assert!(smallest(&tree, tree.root, true) + pair >= tree.page_size / 2);
+        for i in 0..3000u32 {
+            assert_eq!(
+                tree.get(format!("key{:05}", i).as_bytes())
+                    .unwrap()
+                    .is_some(),
+                i % 10 == 0
+            );
+        }
+
+        let mut bucket = UpdateReq::new(b"key00011".to_vec(), vec![1; 8], MODE_UPSERT);
+        bucket.flags = LEAF_BUCKET;
+        tree.update(&mut bucket);
+        let mut batch = WriteBatch::new();
+        batch.put(b"key00011".to_vec(), vec![2]);
+        batch.put(b"key00020".to_vec(), vec![2]);
+        assert_eq!(tree.write(batch).unwrap(), 1);
+        let mut batch = WriteBatch::new();
+        batch.delete(b"key00011".to_vec());
+        assert_eq!(tree.write(batch).unwrap(), 1);
+        assert_eq!(tree.get_entry(b"key00011"), Some((LEAF_BUCKET, vec![1; 8])));
+        assert_eq!(tree.get(b"key00020").unwrap(), Some(vec![2]));
+
+        // a key or value too long for the tree is an error and nothing of the batch is
+        // written
+        let too_long = [
+            (vec![1; tree.max_key_size() + 1], vec![]),
+            (vec![], vec![]),
+            (b"key00030".to_vec(), vec![1; tree.max_val_size() + 1]),
+        ];
+        for (key, value) in too_long {
+            let mut batch = WriteBatch::new();
+            batch.put(b"key00021".to_vec(), vec![2]);
+            batch.put(key, value);
+            let err = tree.write(batch).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+            assert_eq!(tree.get(b"key00021").unwrap(), None);
+        }
+        let value = vec![1; tree.max_val_size() - 7];
+        let err = tree
+            .insert_with_ttl(b"key00021".to_vec(), value, Duration::from_secs(1))
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let value = vec![1; tree.max_val_size() - 8];
+        tree.insert_with_ttl(b"key00021".to_vec(), value.clone(), Duration::from_secs(60))
+            .unwrap();
+        assert_eq!(tree.get(b"key00021").unwrap(), Some(value));
+    }
+    #[test]
+    fn checking_comparators() {
+        let leaf_keys = |tree: &BTree| -> Vec<Vec<u8>> {
+            let node = get(tree.root);
+            return (1..node.nkeys()).map(|i| node.get_key(i)).collect();
+        };
+        let mut tree = BTree::with_comparator(Comparator::big_endian_int());
+        for key in [vec![1, 0], vec![2], vec![0, 0, 3], vec![0xff]] {
+            tree.insert(key, vec![1]);
+        }
+        assert_eq!(
+            leaf_keys(&tree),
+            vec![vec![2], vec![0, 0, 3], vec![0xff], vec![1, 0]]
+        );
+
+        let mut tree = BTree::with_comparator(Comparator::case_insensitive());
+        tree.insert(b"Apple".to_vec(), vec![1]);
+        tree.insert(b"banana".to_vec(), vec![2]);
+        assert_eq!(tree.upsert(b"APPLE".to_vec(), vec![3]), Some(vec![1]));
+        let (found, index, node) = tree.search(b"apple");
+        assert!(found);
+        assert_eq!(node.get_value(index), vec![3]);
+        assert_eq!(node.nkeys(), 3);
+
+        let mut tree = BTree::with_comparator(Comparator::reversed(Comparator::bytewise()));
+        let mut batch = WriteBatch::new();
+        for key in [b"a", b"c", b"b"] {
+            batch.put(key.to_vec(), vec![]);
+        }
+        tree.write(batch).unwrap();
+        assert_eq!(
+            leaf_keys(&tree),
+            vec![b"c".to_vec(), b"b".to_vec(), b"a".to_vec()]
+        );
+        assert_eq!(tree.comparator.name, "reversed(bytewise)");
+
+        let mut tree = BTree::with_comparator(Comparator::new(
+            "by_length",
+            Arc::new(|a: &[u8], b: &[u8]| a.len().cmp(&b.len())),
+        ));
+        for i in (1..=300usize).rev() {
+            tree.insert(vec![b'x'; i], vec![0; 8]);
+        }
+        for i in 1..=300usize {
+            let (found, _, _) = tree.search(&vec![b'y'; i]);
+            assert!(found);
+        }
+    }
+    #[test]
+    fn checking_range_scan() {
+        let mut tree = BTree::new();
+        assert_eq!(tree.scan(Bound::Unbounded, Bound::Unbounded).count(), 0);
+        let mut batch = WriteBatch::new();
+        for i in 0..500u32 {
+            batch.put(
+                format!("key{:05}", i * 2).into_bytes(),
+                i.to_ne_bytes().to_vec(),
+            );
+        }
+        tree.write(batch).unwrap();
+        let all: Vec<_> = tree.scan(Bound::Unbounded, Bound::Unbounded).collect();
+        assert_eq!(all.len(), 500);
+        assert!(all.windows(2).all(|pair| pair[0].0 < pair[1].0));
+
+        let start = b"key00100".to_vec();
+        let end = b"key00200".to_vec();
+        let keys: Vec<_> = tree
+            .scan(Bound::Excluded(&start), Bound::Included(&end))
+            .map(|(key, _)| key)
+            .collect();
+        assert_eq!(keys.len(), 50);
+        assert_eq!(keys[0], b"key00102".to_vec());
+        assert_eq!(keys[49], end);
+        let from_gap = tree
+            .scan(Bound::Included(b"key00101"), Bound::Excluded(&end))
+            .next();
+        assert_eq!(from_gap.unwrap().0, b"key00102".to_vec());
+
+        assert_eq!(
+            tree.get(b"key00998").unwrap(),
+            Some(499u32.to_ne_bytes().to_vec())
+        );
+        assert_eq!(tree.get(b"key00999").unwrap(), None);
+        assert!(tree.delete(b"key00998".to_vec()));
+        assert_eq!(tree.get(b"key00998").unwrap(), None);
+    }
+
+    #[test]
+    fn checking_range_estimates() {
+        let mut tree = BTree::new();
+        assert_eq!(tree.estimate_count(Bound::Unbounded, Bound::Unbounded), 0);
+        for i in 0..50u32 {
+            tree.insert(i.to_be_bytes().to_vec(), vec![1; 10]);
+        }
+        // a single leaf is counted exactly
+        assert_eq!(tree.estimate_count(Bound::Unbounded, Bound::Unbounded), 50);
+        assert_eq!(
+            tree.estimate_count(
+                Bound::Excluded(&10u32.to_be_bytes()),
+                Bound::Included(&20u32.to_be_bytes())
+            ),
+            10
+        );
+
+        let mut batch = WriteBatch::new();
+        for i in 0..20000u32 {
+            batch.put(i.to_be_bytes().to_vec(), vec![1; 10]);
+        }
+        tree.write(batch).unwrap();
+        let close = |estimate: u64, actual: u64| -> bool {
+            return estimate.abs_diff(actual) * 10 <= actual;
+        };
+        let all = tree.estimate_count(Bound::Unbounded, Bound::Unbounded);
+        assert!(close(all, 20000), "{all}");
+        let some = tree.estimate_count(
+            Bound::Included(&5000u32.to_be_bytes()),
+            Bound::Excluded(&9000u32.to_be_bytes()),
+        );
+        assert!(close(some, 4000), "{some}");
+        let past_end =
+            tree.estimate_count(Bound::Included(&30000u32.to_be_bytes()), Bound::Unbounded);
+        assert_eq!(past_end, 0);
+    }
+
+    #[test]
+    fn checking_ttl_expiry() {
+        let mut tree = BTree::new();
+        for i in 0..600u32 {
+            let key = format!("key{i:04}").into_bytes();
+            // every third key is gone already, every third lives for an hour
+            match i % 3 {
+                0 => tree
+                    .insert_with_ttl(key, vec![1; 20], Duration::ZERO)
+                    .unwrap(),
+                1 => tree
+                    .insert_with_ttl(key, vec![2; 20], Duration::from_secs(3600))
+                    .unwrap(),
+                _ => tree.insert(key, vec![3; 20]),
+            }
+        }
+        assert_eq!(tree.get(b"key0000").unwrap(), None);
+        assert_eq!(tree.get(b"key0001").unwrap(), Some(vec![2; 20]));
+        assert!(!tree.search(b"key0003").0);
+        assert!(tree.search(b"key0004").0);
+        assert_eq!(tree.scan(Bound::Unbounded, Bound::Unbounded).count(), 400);
+        // an expired key is absent to conditional writes, a plain write clears the ttl
+        assert!(tree.insert_new(b"key0006".to_vec(), vec![4]));
+        assert!(!tree.insert_new(b"key0007".to_vec(), vec![4]));
+        tree.insert(b"key0001".to_vec(), vec![5]);
+        assert_eq!(tree.get_entry(b"key0001"), Some((0, vec![5])));
+
+        assert_eq!(tree.sweep_expired(), 199);
+        assert_eq!(tree.sweep_expired(), 0);
+        assert_eq!(tree.scan(Bound::Unbounded, Bound::Unbounded).count(), 401);
+        assert_eq!(tree.get(b"key0004").unwrap(), Some(vec![2; 20]));
+        assert_eq!(
+            tree.delete_range(Bound::Included(b"key0100"), Bound::Excluded(b"key0200")),
+            67
+        );
+        assert_eq!(tree.scan(Bound::Unbounded, Bound::Unbounded).count(), 334);
+
+        // a value too short to hold its expiry, as in a damaged file
+        let mut leaf = BNode::new();
+        leaf.set_header(BNODE_LEAF, 2);
+        leaf.node_append_kv_pair(0, 0, vec![], vec![]);
+        leaf.node_append_kv_pair(LEAF_TTL, 1, b"short".to_vec(), vec![1, 2, 3]);
+        let mut tree = BTree::new();
+        tree.root = tree.new_node(leaf);
+        assert!(!is_expired(LEAF_TTL, &[1, 2, 3], now_millis()));
+        assert_eq!(tree.get(b"short").unwrap(), Some(vec![]));
+        assert_eq!(tree.sweep_expired(), 0);
+        assert!(!tree.insert_new(b"short".to_vec(), vec![4]));
+        tree.insert(b"short".to_vec(), vec![5]);
+        assert_eq!(tree.get_entry(b"short"), Some((0, vec![5])));
+    }
+
+    #[test]
+    fn checking_borrow_from_siblings() {
+        fn leaves(tree: &BTree, pointer: u64, out: &mut Vec<BNode>) {
+            let node = tree.get_node(pointer);
+            if node.btype() == BNODE_LEAF {
+                out.push(node);
+                return;
+            }
+            for i in 0..node.nkeys() {
+                leaves(tree, node.get_pointer(i), out);
+            }
+        }
+        // full leaves, then most of every other leaf deleted: no two neighbours fit in
+        // one page, so only borrowing can fill the emptied ones up again
+        let mut tree = BTree::new();
+        let mut batch = WriteBatch::new();
+        for i in 0..3000u32 {
+            batch.put(format!("key{i:05}").into_bytes(), vec![1; 200]);
+        }
+        tree.write(batch).unwrap();
+        let mut before = vec![];
+        leaves(&tree, tree.root, &mut before);
+        for i in 0..3000u32 {
+            if (i / 18) % 2 == 1 && i % 18 != 0 {
+                assert!(tree.delete(format!("key{i:05}").into_bytes()));
+            }
+        }
+        let mut after = vec![];
+        leaves(&tree, tree.root, &mut after);
+        assert!(after.len() < before.len());
+        // siblings split on a pair boundary, so one may be short of half by one pair:
+        // pointer, offset, length header, key and value
+        let pair = 8 + 4 + KV_HEADER as usize + 8 + 200;
+        for leaf in &after {
+            assert!(leaf.size() + pair >= BTREE_PAGE_SIZE / 2, "{}", leaf.size());
+        }
+        let keys: Vec<Vec<u8>> = tree
+            .scan(Bound::Unbounded, Bound::Unbounded)
+            .map(|(key, _)| key)
+            .collect();
+        assert_eq!(keys.len(), 3000 - 83 * 17);
+        assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(tree.get(b"key02987").unwrap(), None);
+        assert_eq!(tree.get(b"key02970").unwrap(), Some(vec![1; 200]));
+    }
+    #[test]
+    fn checking_prefix_compression() {
+        fn depth(tree: &BTree, pointer: u64, leaves: &mut Vec<BNode>) -> usize {
+            let node = tree.get_node(pointer);
+            if node.btype() == BNODE_LEAF {
+                leaves.push(node);
+                return 1;
+            }
+            let mut deepest = 0;
+            for i in 0..node.nkeys() {
+                deepest = deepest.max(depth(tree, node.get_pointer(i), leaves));
+            }
+            return deepest + 1;
+        }
+        let key = |i: u32| format!("tenant-0042/orders/2026-01-01/{:0>60}", i * 7919 % 5000);
+        let mut tree = BTree::new();
+        for i in 0..5000u32 {
+            tree.insert(key(i).into_bytes(), vec![9; 8]);
+        }
+        // a key outside the shared prefix shortens the prefix of the leaf it lands in
+        tree.insert(b"tenant-0042/orders/2025".to_vec(), vec![1]);
+        let mut leaves = vec![];
+        assert_eq!(depth(&tree, tree.root, &mut leaves), 2);
+        // whole keys would take 114 bytes each, 35 a page at most
+        assert!(leaves.len() * 35 < 5000);
+        assert!(leaves[1..].iter().all(|leaf| leaf.prefix_len() >= 60));
+        for i in (0..5000u32).step_by(2) {
+            assert!(tree.delete(key(i).into_bytes()));
+        }
+        let keys: Vec<Vec<u8>> = tree
+            .scan(Bound::Unbounded, Bound::Unbounded)
+            .map(|(key, _)| key)
+            .collect();
+        assert_eq!(keys.len(), 2501);
+        assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(keys[0], b"tenant-0042/orders/2025".to_vec());
+        assert_eq!(tree.get(key(4999).as_bytes()).unwrap(), Some(vec![9; 8]));
+        assert_eq!(tree.get(key(4998).as_bytes()).unwrap(), None);
+    }
+    #[test]
+    fn checking_separator_truncation() {
+        for comparator in [
+            Comparator::bytewise(),
+            Comparator::reversed(Comparator::bytewise()),
+        ] {
+            let reversed = comparator.name != "bytewise";
+            let mut tree = BTree::with_comparator(comparator);
+            // four keys to a leaf, a parent with whole keys would take four leaves too
+            let key = |i: u32| format!("{i:05}{}", "x".repeat(900)).into_bytes();
+            for i in 0..300u32 {
+                tree.insert(key(i * 37 % 300), vec![]);
+            }
+            let root = tree.get_node(tree.root);
+            assert_eq!(root.btype(), BNODE_NODE);
+            for i in 0..root.nkeys() {
+                assert!(root.get_key(i).len() <= 5 || reversed);
+            }
+            // keys between a separator and the first key of the leaf after it
+            for i in 0..300u32 {
+                tree.insert(format!("{i:05}").into_bytes(), vec![1]);
+            }
+            let keys: Vec<Vec<u8>> = tree
+                .scan(Bound::Unbounded, Bound::Unbounded)
+                .map(|(key, _)| key)
+                .collect();
+            assert_eq!(keys.len(), 600);
+            let order = |pair: &[Vec<u8>]| (pair[0] < pair[1]) != reversed;
+            assert!(keys.windows(2).all(order));
+            assert_eq!(tree.get(b"00123").unwrap(), Some(vec![1]));
+            assert_eq!(tree.get(&key(123)).unwrap(), Some(vec![]));
+        }
+    }
+    #[test]
+    fn random_batches_and_range_deletes() {
+        use std::collections::BTreeMap;
+        // xorshift, the same run every time
+        let mut state = 0x9e37_79b9_7f4a_7c15u64;
+        let mut random = move |below: u64| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            return state % below;
+        };
+        // runs of x in front make neighbouring keys share long prefixes, so separators
+        // are long and leaves may start well above them
+        let key = |random: &mut dyn FnMut(u64) -> u64| {
+            let run = "x".repeat(100 * random(9) as usize);
+            return format!("{run}{:04}", random(3000)).into_bytes();
+        };
+        let mut tree = BTree::new();
+        let mut model = BTreeMap::new();
+        for round in 0..200 {
+            match random(8) {
+                0..=4 => {
+                    let mut batch = WriteBatch::new();
+                    for _ in 0..1 + random(200) {
+                        let key = key(&mut random);
+                        if random(3) == 0 {
+                            batch.delete(key.clone());
+                            model.remove(&key);
+                        } else {
+                            let value = vec![round as u8; random(200) as usize];
+                            batch.put(key.clone(), value.clone());
+                            model.insert(key, value);
+                        }
+                    }
+                    tree.write(batch).unwrap();
+                }
+                5 | 6 => {
+                    let (a, b) = (key(&mut random), key(&mut random));
+                    let (start, end) = if a <= b { (a, b) } else { (b, a) };
+                    let count = tree.delete_range(Bound::Included(&start), Bound::Included(&end));
+                    let gone: Vec<Vec<u8>> = model
+                        .range(start..=end)
+                        .map(|(key, _)| key.clone())
+                        .collect();
+                    assert_eq!(count, gone.len());
+                    for key in gone {
+                        model.remove(&key);
+                    }
+                }
+                _ => {
+                    for _ in 0..20 {
+                        let key = key(&mut random);
+                        if random(2) == 0 {
+                            assert_eq!(tree.delete(key.clone()), model.remove(&key).is_some());
+                        } else {
+                            tree.insert(key.clone(), vec![1]);
+                            model.insert(key, vec![1]);
+                        }
+                    }
+                }
+            }
+            let entries: Vec<(Vec<u8>, Vec<u8>)> =
+                tree.scan(Bound::Unbounded, Bound::Unbounded).collect();
+            let expected: Vec<(Vec<u8>, Vec<u8>)> =
+                model.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+            assert!(entries == expected, "scan differs after round {round}");
+            for (key, value) in &model {
+                assert_eq!(
+                    tree.get(key).unwrap().as_ref(),
+                    Some(value),
+                    "round {round}"
+                );
+            }
+        }
+    }
 }
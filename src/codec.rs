@@ -0,0 +1,282 @@
+#![allow(clippy::needless_return)]
+// order-preserving encodings: for every type here, comparing the encoded bytes byte-wise
+// gives the same order as comparing the values themselves. Every encoding is also
+// self-delimiting, so values can be concatenated into composite (tuple) keys.
+pub trait Codec: Sized {
+    fn encode(&self, out: &mut Vec<u8>);
+    // reads one value off the front of `input` and moves `input` past it
+    fn decode(input: &mut &[u8]) -> Option<Self>;
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut out = vec![];
+        self.encode(&mut out);
+        return out;
+    }
+    // decodes a whole buffer, trailing bytes are an error
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let mut input = bytes;
+        let value = Self::decode(&mut input)?;
+        if !input.is_empty() {
+            return None;
+        }
+        return Some(value);
+    }
+}
+
+fn take<'a>(input: &mut &'a [u8], n: usize) -> Option<&'a [u8]> {
+    if input.len() < n {
+        return None;
+    }
+    let (head, rest) = input.split_at(n);
+    *input = rest;
+    return Some(head);
+}
+
+// byte strings are null terminated, with 0x00 escaped as 0x01 0x01 and 0x01 as 0x01 0x02
+// so the terminator still sorts before any byte a longer string could continue with
+pub fn encode_bytes(bytes: &[u8], out: &mut Vec<u8>) {
+    for byte in bytes {
+        match byte {
+            0 => out.extend_from_slice(&[1, 1]),
+            1 => out.extend_from_slice(&[1, 2]),
+            _ => out.push(*byte),
+        }
+    }
+    out.push(0);
+}
+pub fn decode_bytes(input: &mut &[u8]) -> Option<Vec<u8>> {
+    let mut bytes = vec![];
+    let mut i = 0;
+    loop {
+        match input.get(i)? {
+            0 => break,
+            1 => {
+                match input.get(i + 1)? {
+                    1 => bytes.push(0),
+                    2 => bytes.push(1),
+                    _ => return None,
+                }
+                i += 2;
+            }
+            byte => {
+                bytes.push(*byte);
+                i += 1;
+            }
+        }
+    }
+    *input = &input[i + 1..];
+    return Some(bytes);
+}
+
+macro_rules! unsigned_codec {
+    ($($t:ty),*) => {$(
+        impl Codec for $t {
+            fn encode(&self, out: &mut Vec<u8>) {
+                out.extend_from_slice(&self.to_be_bytes());
+            }
+            fn decode(input: &mut &[u8]) -> Option<Self> {
+                let bytes = take(input, std::mem::size_of::<$t>())?;
+                return Some(<$t>::from_be_bytes(bytes.try_into().unwrap()));
+            }
+        }
+    )*};
+}
+unsigned_codec!(u8, u16, u32, u64, u128);
+
+// signed integers are stored big-endian with the sign bit flipped, negatives first
+macro_rules! signed_codec {
+    ($($t:ty => $u:ty),*) => {$(
+        impl Codec for $t {
+            fn encode(&self, out: &mut Vec<u8>) {
+                let flipped = (*self as $u) ^ (1 << (<$u>::BITS - 1));
+                out.extend_from_slice(&flipped.to_be_bytes());
+            }
+            fn decode(input: &mut &[u8]) -> Option<Self> {
+                let bytes = take(input, std::mem::size_of::<$t>())?;
+                let flipped = <$u>::from_be_bytes(bytes.try_into().unwrap());
+                return Some((flipped ^ (1 << (<$u>::BITS - 1))) as $t);
+            }
+        }
+    )*};
+}
+signed_codec!(i8 => u8, i16 => u16, i32 => u32, i64 => u64, i128 => u128);
+
+// floats flip the sign bit when positive and every bit when negative, which turns the
+// IEEE 754 layout into one that sorts as unsigned integers. -0.0 is encoded as 0.0, the
+// two are equal and have to be the same key.
+macro_rules! float_codec {
+    ($($t:ty => $u:ty),*) => {$(
+        impl Codec for $t {
+            fn encode(&self, out: &mut Vec<u8>) {
+                let bits = if *self == 0.0 { 0.0 as $t } else { *self }.to_bits();
+                let sign = 1 << (<$u>::BITS - 1);
+                let flipped = if bits & sign != 0 { !bits } else { bits | sign };
+                out.extend_from_slice(&flipped.to_be_bytes());
+            }
+            fn decode(input: &mut &[u8]) -> Option<Self> {
+                let bytes = take(input, std::mem::size_of::<$t>())?;
+                let flipped = <$u>::from_be_bytes(bytes.try_into().unwrap());
+                let sign = 1 << (<$u>::BITS - 1);
+                let bits = if flipped & sign != 0 { flipped ^ sign } else { !flipped };
+                return Some(<$t>::from_bits(bits));
+            }
+        }
+    )*};
+}
+float_codec!(f32 => u32, f64 => u64);
+
+impl Codec for bool {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(*self as u8);
+    }
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        match take(input, 1)?[0] {
+            0 => Some(false),
+            1 => Some(true),
+            _ => None,
+        }
+    }
+}
+
+impl Codec for Vec<u8> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        encode_bytes(self, out);
+    }
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        return decode_bytes(input);
+    }
+}
+
+impl Codec for String {
+    fn encode(&self, out: &mut Vec<u8>) {
+        encode_bytes(self.as_bytes(), out);
+    }
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        return String::from_utf8(decode_bytes(input)?).ok();
+    }
+}
+
+// `None` sorts before every `Some`
+impl<T: Codec> Codec for Option<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            None => out.push(0),
+            Some(value) => {
+                out.push(1);
+                value.encode(out);
+            }
+        }
+    }
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        match take(input, 1)?[0] {
+            0 => Some(None),
+            1 => Some(Some(T::decode(input)?)),
+            _ => None,
+        }
+    }
+}
+
+// tuples are the concatenation of their fields, compared field by field
+macro_rules! tuple_codec {
+    ($($name:ident),+) => {
+        impl<$($name: Codec),+> Codec for ($($name,)+) {
+            #[allow(non_snake_case)]
+            fn encode(&self, out: &mut Vec<u8>) {
+                let ($($name,)+) = self;
+                $($name.encode(out);)+
+            }
+            fn decode(input: &mut &[u8]) -> Option<Self> {
+                return Some(($($name::decode(input)?,)+));
+            }
+        }
+    };
+}
+tuple_codec!(A);
+tuple_codec!(A, B);
+tuple_codec!(A, B, C);
+tuple_codec!(A, B, C, D);
+tuple_codec!(A, B, C, D, E);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn assert_order_preserved<T: Codec + PartialOrd + std::fmt::Debug>(values: Vec<T>) {
+        for a in &values {
+            assert_eq!(T::from_bytes(&a.to_bytes()).as_ref(), Some(a));
+            for b in &values {
+                assert_eq!(
+                    a.partial_cmp(b),
+                    Some(a.to_bytes().cmp(&b.to_bytes())),
+                    "{a:?} vs {b:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn integers_keep_their_order() {
+        assert_order_preserved(vec![0u32, 1, 255, 256, 70000, u32::MAX]);
+        assert_order_preserved(vec![i64::MIN, -70000, -256, -1, 0, 1, 255, i64::MAX]);
+        assert_order_preserved(vec![i8::MIN, -1, 0, 1, i8::MAX]);
+    }
+
+    #[test]
+    fn floats_keep_their_order() {
+        assert_order_preserved(vec![
+            f64::NEG_INFINITY,
+            -1e300,
+            -2.5,
+            -0.0001,
+            0.0,
+            0.0001,
+            2.5,
+            1e300,
+            f64::INFINITY,
+        ]);
+        assert_order_preserved(vec![-3.5f32, -1.0, 0.0, 0.25, 8.0]);
+        assert_eq!((-0.0f64).to_bytes(), 0.0f64.to_bytes());
+        assert_eq!((-0.0f32).to_bytes(), 0.0f32.to_bytes());
+    }
+
+    #[test]
+    fn strings_and_bytes_keep_their_order() {
+        assert_order_preserved(vec![
+            String::new(),
+            "a".to_string(),
+            "a\0".to_string(),
+            "a\u{1}".to_string(),
+            "ab".to_string(),
+            "b".to_string(),
+        ]);
+        assert_order_preserved(vec![
+            vec![],
+            vec![0u8],
+            vec![0, 0],
+            vec![1],
+            vec![1, 0],
+            vec![2],
+        ]);
+        assert_eq!(Vec::<u8>::from_bytes(&[1, 3, 0]), None);
+        assert_eq!(String::from_bytes(b"a"), None);
+    }
+
+    #[test]
+    fn tuples_compare_field_by_field() {
+        assert_order_preserved(vec![
+            (1u32, -5i64, "b".to_string()),
+            (1, -5, "ba".to_string()),
+            (1, 3, "a".to_string()),
+            (2, i64::MIN, String::new()),
+        ]);
+        assert_order_preserved(vec![
+            (None, 1u8),
+            (None, 2),
+            (Some(false), 0),
+            (Some(true), 0),
+        ]);
+        let mut input: &[u8] = &(7u16, "x".to_string()).to_bytes();
+        assert_eq!(u16::decode(&mut input), Some(7));
+        assert_eq!(String::decode(&mut input), Some("x".to_string()));
+        assert!(input.is_empty());
+    }
+}
@@ -0,0 +1,83 @@
+#![allow(clippy::needless_return)]
+use std::{cmp::Ordering, sync::Arc};
+
+pub type CompareFn = Arc<dyn Fn(&[u8], &[u8]) -> Ordering + Send + Sync>;
+
+// decides the order of keys in a tree. The name identifies the ordering, a tree written
+// with one comparator must never be read back with another.
+#[derive(Clone)]
+pub struct Comparator {
+    pub name: String,
+    pub compare: CompareFn,
+}
+impl Comparator {
+    pub fn new(name: &str, compare: CompareFn) -> Comparator {
+        return Comparator {
+            name: name.to_string(),
+            compare,
+        };
+    }
+    // plain byte-wise order, what `Vec<u8>` comparison gives
+    pub fn bytewise() -> Comparator {
+        return Comparator::new("bytewise", Arc::new(|a, b| a.cmp(b)));
+    }
+    // keys are unsigned big-endian integers of any width, leading zero bytes are ignored
+    pub fn big_endian_int() -> Comparator {
+        return Comparator::new(
+            "big_endian_int",
+            Arc::new(|a, b| {
+                let a = &a[a.iter().take_while(|byte| **byte == 0).count()..];
+                let b = &b[b.iter().take_while(|byte| **byte == 0).count()..];
+                return a.len().cmp(&b.len()).then_with(|| a.cmp(b));
+            }),
+        );
+    }
+    // ASCII case-insensitive, "Key" and "key" are the same key
+    pub fn case_insensitive() -> Comparator {
+        return Comparator::new(
+            "case_insensitive",
+            Arc::new(|a, b| {
+                let a = a.iter().map(|byte| byte.to_ascii_lowercase());
+                let b = b.iter().map(|byte| byte.to_ascii_lowercase());
+                return a.cmp(b);
+            }),
+        );
+    }
+    pub fn reversed(inner: Comparator) -> Comparator {
+        let compare = inner.compare.clone();
+        return Comparator::new(
+            &format!("reversed({})", inner.name),
+            Arc::new(move |a, b| compare(b, a)),
+        );
+    }
+    // the built-in comparator going by `name`, for reopening a tree from its stored name
+    pub fn by_name(name: &str) -> Option<Comparator> {
+        if let Some(inner) = name
+            .strip_prefix("reversed(")
+            .and_then(|name| name.strip_suffix(')'))
+        {
+            return Comparator::by_name(inner).map(Comparator::reversed);
+        }
+        match name {
+            "bytewise" => return Some(Comparator::bytewise()),
+            "big_endian_int" => return Some(Comparator::big_endian_int()),
+            "case_insensitive" => return Some(Comparator::case_insensitive()),
+            _ => return None,
+        }
+    }
+    // the empty key is the sentinel at the start of the leftmost leaf, it sorts before
+    // every other key whatever the ordering
+    pub fn cmp(&self, a: &[u8], b: &[u8]) -> Ordering {
+        match (a.is_empty(), b.is_empty()) {
+            (true, true) => Ordering::Equal,
+            (true, false) => Ordering::Less,
+            (false, true) => Ordering::Greater,
+            (false, false) => (self.compare)(a, b),
+        }
+    }
+}
+impl Default for Comparator {
+    fn default() -> Self {
+        Self::bytewise()
+    }
+}
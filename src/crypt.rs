@@ -0,0 +1,57 @@
+#![allow(clippy::needless_return)]
+use std::{io, sync::Arc};
+
+use aes_gcm::{
+    aead::{
+        consts::{U12, U16},
+        AeadInPlace, KeyInit,
+    },
+    Aes256Gcm,
+};
+use chacha20poly1305::ChaCha20Poly1305;
+
+use crate::pager::{PageCipher, CIPHER_AES_256_GCM, CIPHER_CHACHA20_POLY1305};
+
+// the AEADs pages can be encrypted with, both with 256-bit keys
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Cipher {
+    Aes256Gcm,
+    ChaCha20Poly1305,
+}
+impl Cipher {
+    pub fn with_key(self, key: &[u8; 32]) -> Arc<dyn PageCipher + Send + Sync> {
+        return match self {
+            Cipher::Aes256Gcm => Arc::new(Aead {
+                id: CIPHER_AES_256_GCM,
+                aead: Aes256Gcm::new(key.into()),
+            }),
+            Cipher::ChaCha20Poly1305 => Arc::new(Aead {
+                id: CIPHER_CHACHA20_POLY1305,
+                aead: ChaCha20Poly1305::new(key.into()),
+            }),
+        };
+    }
+}
+
+struct Aead<A> {
+    id: u64,
+    aead: A,
+}
+impl<A: AeadInPlace<NonceSize = U12, TagSize = U16>> PageCipher for Aead<A> {
+    fn id(&self) -> u64 {
+        return self.id;
+    }
+    fn seal(&self, nonce: &[u8; 12], aad: &[u8], data: &mut [u8]) -> io::Result<[u8; 16]> {
+        let tag = self
+            .aead
+            .encrypt_in_place_detached(nonce.into(), aad, data)
+            .map_err(|_| io::Error::other("page can not be encrypted"))?;
+        return Ok(tag.into());
+    }
+    fn open(&self, nonce: &[u8; 12], aad: &[u8], data: &mut [u8], tag: &[u8; 16]) -> bool {
+        let result = self
+            .aead
+            .decrypt_in_place_detached(nonce.into(), aad, data, tag.into());
+        return result.is_ok();
+    }
+}
@@ -0,0 +1,454 @@
+#![allow(clippy::needless_return)]
+use std::{
+    cmp::Ordering,
+    collections::HashSet,
+    io::{self, BufRead, Write},
+    ops::Bound,
+};
+
+use crate::{
+    comparator::Comparator,
+    kv::{KVError, KV},
+    B_tree::{is_expired, now_millis, LEAF_BUCKET, LEAF_TTL},
+};
+
+// a logical dump: every bucket of a database, with every key and value in it, as lines
+// of text that do not depend on how pages are laid out. It starts with the header
+//     rustdb-dump <DUMP_VERSION> <hex|base64>
+// then each bucket, the catalog first and every bucket before the ones nested in it, is
+//     bucket <comparator> <name>...
+// followed by its entries in key order, the buckets nested in it left out:
+//     <key> <value> [<leaf flags>]
+// Names, keys and values are in the encoding of the header. Flags are only there when
+// they are not 0, a value flagged LEAF_TTL keeps its expiry in front.
+pub const DUMP_VERSION: u32 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Encoding {
+    Hex,
+    Base64,
+}
+impl Encoding {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Encoding::Hex => return "hex",
+            Encoding::Base64 => return "base64",
+        }
+    }
+    pub fn by_name(name: &str) -> Option<Encoding> {
+        match name {
+            "hex" => return Some(Encoding::Hex),
+            "base64" => return Some(Encoding::Base64),
+            _ => return None,
+        }
+    }
+    pub fn encode(&self, bytes: &[u8]) -> String {
+        match self {
+            Encoding::Hex => return hex_encode(bytes),
+            Encoding::Base64 => return base64_encode(bytes),
+        }
+    }
+    pub fn decode(&self, text: &str) -> Option<Vec<u8>> {
+        match self {
+            Encoding::Hex => return hex_decode(text),
+            Encoding::Base64 => return base64_decode(text),
+        }
+    }
+}
+
+pub fn hex_encode(bytes: &[u8]) -> String {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+    let mut text = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        text.push(DIGITS[(byte >> 4) as usize] as char);
+        text.push(DIGITS[(byte & 15) as usize] as char);
+    }
+    return text;
+}
+pub fn hex_decode(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    let digit = |c: u8| (c as char).to_digit(16).map(|digit| digit as u8);
+    let mut bytes = Vec::with_capacity(text.len() / 2);
+    for pair in text.as_bytes().chunks(2) {
+        bytes.push(digit(pair[0])? << 4 | digit(pair[1])?);
+    }
+    return Some(bytes);
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+// the standard alphabet, padded with '='
+pub fn base64_encode(bytes: &[u8]) -> String {
+    let mut text = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let mut group = [0; 3];
+        group[..chunk.len()].copy_from_slice(chunk);
+        let bits = u32::from_be_bytes([0, group[0], group[1], group[2]]);
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => text.push(BASE64[(bits >> (18 - 6 * i) & 63) as usize] as char),
+                false => text.push('='),
+            }
+        }
+    }
+    return text;
+}
+pub fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(4) {
+        return None;
+    }
+    let mut bytes = Vec::with_capacity(text.len() / 4 * 3);
+    let groups = text.as_bytes().chunks(4);
+    let count = groups.len();
+    for (i, group) in groups.enumerate() {
+        let padding = group.iter().rev().take_while(|c| **c == b'=').count();
+        if padding > 2 || (padding > 0 && i + 1 < count) {
+            return None;
+        }
+        let mut bits = 0u32;
+        for c in &group[..4 - padding] {
+            let digit = BASE64.iter().position(|digit| digit == c)?;
+            bits = bits << 6 | digit as u32;
+        }
+        bits <<= 6 * padding;
+        bytes.extend_from_slice(&bits.to_be_bytes()[1..4 - padding]);
+    }
+    return Some(bytes);
+}
+
+fn bad(line: usize, message: &str) -> KVError {
+    let message = format!("dump line {line}: {message}");
+    return io::Error::new(io::ErrorKind::InvalidData, message).into();
+}
+
+// writes every bucket of the database to `out`, see DUMP_VERSION for the format. Expired
+// entries are left out.
+pub fn dump(db: &mut KV, out: &mut dyn Write, encoding: Encoding) -> Result<(), KVError> {
+    writeln!(out, "rustdb-dump {DUMP_VERSION} {}", encoding.name())?;
+    dump_bucket(db, &mut vec![], out, encoding)?;
+    out.flush()?;
+    return Ok(());
+}
+fn dump_bucket(
+    db: &mut KV,
+    path: &mut Vec<String>,
+    out: &mut dyn Write,
+    encoding: Encoding,
+) -> Result<(), KVError> {
+    let names: Vec<&str> = path.iter().map(String::as_str).collect();
+    let tree = db.open_bucket(&names)?;
+    write!(out, "bucket {}", tree.comparator.name)?;
+    for name in &names {
+        write!(out, " {}", encoding.encode(name.as_bytes()))?;
+    }
+    writeln!(out)?;
+    let now = now_millis();
+    let mut nested = vec![];
+    let mut iter = tree
+        .scan(Bound::Unbounded, Bound::Unbounded)
+        .include_expired();
+    while let Some((flags, key, value)) = iter.next_entry() {
+        if flags == LEAF_BUCKET {
+            nested.push(String::from_utf8_lossy(&key).into_owned());
+            continue;
+        }
+        if key.is_empty() || is_expired(flags, &value, now) {
+            continue;
+        }
+        write!(out, "{} {}", encoding.encode(&key), encoding.encode(&value))?;
+        if flags != 0 {
+            write!(out, " {flags}")?;
+        }
+        writeln!(out)?;
+    }
+    for name in nested {
+        path.push(name);
+        dump_bucket(db, path, out, encoding)?;
+        path.pop();
+    }
+    return Ok(());
+}
+
+// rebuilds the buckets of a dump in `db`, which has to be empty, each with the bulk
+// loader, and commits them
+pub fn restore(db: &mut KV, input: &mut dyn BufRead) -> Result<(), KVError> {
+    let mut lines = input.lines().enumerate().map(|(i, line)| (i + 1, line));
+    let Some((_, header)) = lines.next() else {
+        return Err(bad(1, "empty dump"));
+    };
+    let header = header?;
+    let fields: Vec<&str> = header.split(' ').collect();
+    let encoding = match fields.as_slice() {
+        ["rustdb-dump", version, encoding] if *version == DUMP_VERSION.to_string() => {
+            Encoding::by_name(encoding).ok_or_else(|| bad(1, "unknown encoding"))?
+        }
+        ["rustdb-dump", version, _] => {
+            return Err(bad(
+                1,
+                &format!("format version {version}, not {DUMP_VERSION}"),
+            ));
+        }
+        _ => return Err(bad(1, "not a rustdb dump")),
+    };
+    if !db.tree_names()?.is_empty() {
+        return Err(bad(1, "the database to restore into is not empty"));
+    }
+    if let Err(err) = restore_buckets(db, &mut lines, encoding) {
+        db.rollback();
+        return Err(err);
+    }
+    db.commit()?;
+    return Ok(());
+}
+// the bucket lines after the header with the entries below each of them. Leaves what it
+// wrote uncommitted, also when it fails.
+fn restore_buckets(
+    db: &mut KV,
+    lines: &mut impl Iterator<Item = (usize, io::Result<String>)>,
+    encoding: Encoding,
+) -> Result<(), KVError> {
+    let mut seen = HashSet::new();
+    // the bucket line the entries of the last bucket ran into
+    let mut next = lines.next();
+    while let Some((number, line)) = next.take() {
+        let line = line?;
+        let fields: Vec<&str> = line.split(' ').collect();
+        let ["bucket", comparator, names @ ..] = fields.as_slice() else {
+            return Err(bad(number, "expected a bucket line"));
+        };
+        let comparator = Comparator::by_name(comparator)
+            .ok_or_else(|| bad(number, &format!("unknown comparator {comparator}")))?;
+        let mut path = vec![];
+        for name in names {
+            let name = encoding
+                .decode(name)
+                .and_then(|name| String::from_utf8(name).ok());
+            path.push(name.ok_or_else(|| bad(number, "bad bucket name"))?);
+        }
+        if !seen.insert(path.clone()) {
+            return Err(bad(number, "repeated bucket"));
+        }
+        if seen.len() == 1 && !path.is_empty() {
+            return Err(bad(number, "the catalog has to come first"));
+        }
+        let path: Vec<&str> = path.iter().map(String::as_str).collect();
+        let tree = match path.is_empty() {
+            true => db.open_bucket(&[])?,
+            false => db.create_bucket_with(&path, comparator.clone())?,
+        };
+        let (max_key, max_val) = (tree.max_key_size(), tree.max_val_size());
+        let mut failed = None;
+        let mut last: Option<Vec<u8>> = None;
+        let entries = std::iter::from_fn(|| {
+            let (number, line) = lines.next()?;
+            let line = match line {
+                Ok(line) if line.starts_with("bucket ") => {
+                    next = Some((number, Ok(line)));
+                    return None;
+                }
+                Ok(line) => line,
+                Err(err) => {
+                    failed = Some(err.into());
+                    return None;
+                }
+            };
+            let entry = parse_entry(&line, encoding, max_key, max_val)
+                .map_err(|message| bad(number, message));
+            let entry = entry.and_then(|entry| match &last {
+                Some(last) if comparator.cmp(last, &entry.1) != Ordering::Less => {
+                    Err(bad(number, "keys out of order"))
+                }
+                _ => Ok(entry),
+            });
+            match entry {
+                Ok(entry) => {
+                    last = Some(entry.1.clone());
+                    return Some(entry);
+                }
+                Err(err) => {
+                    failed = Some(err);
+                    return None;
+                }
+            }
+        });
+        tree.bulk_load(entries);
+        if let Some(err) = failed {
+            return Err(err);
+        }
+    }
+    return Ok(());
+}
+// a line of entries, with a key and value that fit the tree they go into
+fn parse_entry(
+    line: &str,
+    encoding: Encoding,
+    max_key: usize,
+    max_val: usize,
+) -> Result<(u64, Vec<u8>, Vec<u8>), &'static str> {
+    let fields: Vec<&str> = line.split(' ').collect();
+    let (key, value, flags) = match fields.as_slice() {
+        [key, value] => (key, value, "0"),
+        [key, value, flags] => (key, value, *flags),
+        _ => return Err("bad entry"),
+    };
+    let flags = match flags.parse() {
+        Ok(flags) if flags == 0 || flags == LEAF_TTL => flags,
+        _ => return Err("bad leaf flags"),
+    };
+    let key = encoding.decode(key).ok_or("bad key")?;
+    let value = encoding.decode(value).ok_or("bad value")?;
+    if key.is_empty() {
+        return Err("empty key");
+    }
+    if key.len() > max_key {
+        return Err("key too large");
+    }
+    if value.len() > max_val {
+        return Err("value too large");
+    }
+    if flags == LEAF_TTL && value.len() < 8 {
+        return Err("value without its expiry");
+    }
+    return Ok((flags, key, value));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::kv::test::TempFile;
+    use std::time::Duration;
+
+    #[test]
+    fn hex_and_base64() {
+        for len in 0..8 {
+            let bytes: Vec<u8> = (0..len).map(|i| (i * 97 + 251) as u8).collect();
+            for encoding in [Encoding::Hex, Encoding::Base64] {
+                assert_eq!(
+                    encoding.decode(&encoding.encode(&bytes)),
+                    Some(bytes.clone())
+                );
+            }
+        }
+        assert_eq!(base64_encode(b"rustdb"), "cnVzdGRi");
+        assert_eq!(base64_encode(b"dump"), "ZHVtcA==");
+        assert_eq!(hex_encode(&[0, 171, 255]), "00abff");
+        for bad in ["ZHVtcA=", "ZH=tcA==", "ZHVt*A==", "Z==="] {
+            assert_eq!(base64_decode(bad), None, "{bad}");
+        }
+        assert_eq!(hex_decode("0g"), None);
+        assert_eq!(hex_decode("abc"), None);
+    }
+
+    #[test]
+    fn dump_and_restore() {
+        let file = TempFile::new("dumped");
+        let mut db = KV::open(&file.0).unwrap();
+        let users = db.create_tree("users").unwrap();
+        for i in 0..2000u32 {
+            users.insert(
+                format!("user {i:05}").into_bytes(),
+                i.to_le_bytes().to_vec(),
+            );
+        }
+        users.insert(b"binary\x00\n".to_vec(), vec![0, 10, 13, 255]);
+        users
+            .insert_with_ttl(
+                b"session".to_vec(),
+                b"token".to_vec(),
+                Duration::from_secs(600),
+            )
+            .unwrap();
+        users.insert(b"empty".to_vec(), vec![]);
+        let reversed = Comparator::reversed(Comparator::bytewise());
+        let scores = db
+            .create_bucket_with(&["users", "scores"], reversed)
+            .unwrap();
+        for i in 0..500u32 {
+            scores.insert(format!("score{i:03}").into_bytes(), vec![b's'; 40]);
+        }
+        db.create_tree("empty tree").unwrap();
+        db.commit().unwrap();
+
+        for encoding in [Encoding::Hex, Encoding::Base64] {
+            let mut dumped = vec![];
+            dump(&mut db, &mut dumped, encoding).unwrap();
+            let text = String::from_utf8(dumped.clone()).unwrap();
+            assert!(text.starts_with(&format!("rustdb-dump 1 {}\n", encoding.name())));
+            assert!(text.contains("bucket reversed(bytewise) "));
+
+            let copy = TempFile::new("restored");
+            let mut restored = KV::open(&copy.0).unwrap();
+            restore(&mut restored, &mut dumped.as_slice()).unwrap();
+            drop(restored);
+            let mut restored = KV::open(&copy.0).unwrap();
+            let mut again = vec![];
+            dump(&mut restored, &mut again, encoding).unwrap();
+            assert_eq!(again, dumped);
+            let users = restored.open_tree("users").unwrap();
+            assert_eq!(users.get(b"session").unwrap().unwrap(), b"token");
+            assert_eq!(
+                users.get(b"user 01999").unwrap().unwrap(),
+                1999u32.to_le_bytes()
+            );
+            let scores = restored.open_bucket(&["users", "scores"]).unwrap();
+            assert_eq!(scores.comparator.name, "reversed(bytewise)");
+            assert_eq!(scores.scan(Bound::Unbounded, Bound::Unbounded).count(), 500);
+
+            // nothing is left behind by a dump that does not restore
+            assert!(restore(&mut restored, &mut dumped.as_slice()).is_err());
+            let copy = TempFile::new("restored");
+            let mut restored = KV::open(&copy.0).unwrap();
+            let mut lines: Vec<&str> = text.lines().collect();
+            let users = format!("bucket bytewise {}", encoding.encode(b"users"));
+            let at = lines.iter().position(|line| *line == users).unwrap();
+            lines.swap(at + 1, at + 2);
+            let unsorted = lines.join("\n");
+            assert!(restore(&mut restored, &mut unsorted.as_bytes()).is_err());
+            assert!(restored.tree_names().unwrap().is_empty());
+        }
+
+        // malformed dumps are errors, with nothing written by the buckets before
+        let (users, key) = (hex_encode(b"users"), hex_encode(b"key"));
+        let catalog = format!("bucket bytewise\nbucket bytewise {users}");
+        let cases = [
+            (
+                format!("bucket bytewise {users}"),
+                "catalog has to come first",
+            ),
+            (
+                "bucket bytewise\nbucket bytewise".to_string(),
+                "repeated bucket",
+            ),
+            (
+                format!("{catalog}\nbucket bytewise {users}"),
+                "repeated bucket",
+            ),
+            (
+                format!("{catalog}\nbucket nope {key}"),
+                "unknown comparator",
+            ),
+            (format!("{catalog}\nbucket bytewise zz"), "bad bucket name"),
+            (format!("{catalog}\n{key} 00 1"), "bad leaf flags"),
+            (format!("{catalog}\n{key} 00 7"), "bad leaf flags"),
+            (format!("{catalog}\n{key} 00 2"), "value without its expiry"),
+            (
+                format!("{catalog}\n{} 00", "61".repeat(1001)),
+                "key too large",
+            ),
+            (
+                format!("{catalog}\n{key} {}", "00".repeat(3001)),
+                "value too large",
+            ),
+        ];
+        for (body, message) in cases {
+            let text = format!("rustdb-dump 1 hex\n{body}\n");
+            let copy = TempFile::new("restored");
+            let mut restored = KV::open(&copy.0).unwrap();
+            let err = restore(&mut restored, &mut text.as_bytes()).unwrap_err();
+            assert!(err.to_string().contains(message), "{err}");
+            assert!(restored.tree_names().unwrap().is_empty());
+        }
+    }
+}
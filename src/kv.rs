@@ -0,0 +1,1403 @@
+#![allow(clippy::needless_return)]
+use std::{
+    collections::{HashMap, HashSet},
+    fmt, fs,
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    ops::Bound,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    comparator::Comparator,
+    pager::{page_number, Compression, Meta, PageCipher, Pager},
+    B_tree::{
+        is_expired, now_millis, BTree, UpdateReq, BNODE_LEAF, BNODE_NODE, BTREE_PAGE_SIZE,
+        LEAF_BUCKET, MODE_INSERT_ONLY, MODE_UPDATE_ONLY, PAGE_SIZES,
+    },
+};
+
+#[derive(Debug)]
+pub enum KVError {
+    Io(io::Error),
+    TreeExists(String),
+    NoSuchTree(String),
+    // the tree was created with a comparator that is not the one it is opened with
+    WrongComparator(String),
+    BadCatalog(String),
+}
+impl fmt::Display for KVError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KVError::Io(err) => write!(f, "{err}"),
+            KVError::TreeExists(name) => write!(f, "tree {name} already exists"),
+            KVError::NoSuchTree(name) => write!(f, "no such tree: {name}"),
+            KVError::WrongComparator(name) => {
+                write!(f, "tree {name} was created with another comparator")
+            }
+            KVError::BadCatalog(name) => write!(f, "bad catalog entry for tree {name}"),
+        }
+    }
+}
+impl From<io::Error> for KVError {
+    fn from(err: io::Error) -> Self {
+        return KVError::Io(err);
+    }
+}
+
+// a catalog entry: the root page of the tree, then the name of its comparator
+fn encode_entry(root: u64, comparator: &str) -> Vec<u8> {
+    let mut value = root.to_le_bytes().to_vec();
+    value.extend_from_slice(comparator.as_bytes());
+    return value;
+}
+fn decode_entry(name: &str, value: &[u8]) -> Result<(u64, String), KVError> {
+    let bad = || KVError::BadCatalog(name.to_string());
+    if value.len() < 8 {
+        return Err(bad());
+    }
+    let root = u64::from_le_bytes(value[..8].try_into().unwrap());
+    let comparator = String::from_utf8(value[8..].to_vec()).map_err(|_| bad())?;
+    return Ok((root, comparator));
+}
+
+// called with the path of a bucket and one of the keys and values in it
+pub type Visitor<'a> = dyn FnMut(&[String], &[u8], &[u8]) + 'a;
+
+fn names_in(tree: &BTree) -> io::Result<Vec<String>> {
+    let mut names = vec![];
+    let mut iter = tree.try_scan(Bound::Unbounded, Bound::Unbounded)?;
+    while let Some((flags, name, _)) = iter.try_next_entry()? {
+        if flags == LEAF_BUCKET {
+            names.push(String::from_utf8_lossy(&name).into_owned());
+        }
+    }
+    return Ok(names);
+}
+
+// a database file holding any number of named trees, which can hold buckets of their
+// own: a bucket is an entry flagged LEAF_BUCKET whose value is the root of a nested tree
+// (and the name of its comparator), so namespaces nest to any depth. The catalog tree is
+// the outermost bucket and the meta page of the file points at it. Changes to any of the
+// trees are kept back until `commit`, which makes all of them durable at once by
+// writing the meta page, so a transaction can span several trees.
+pub struct KV {
+    pub path: PathBuf,
+    pub pager: Arc<Mutex<Pager>>,
+    catalog: BTree,
+    // buckets opened since the last commit by path, their roots are written into the
+    // buckets holding them on commit
+    trees: HashMap<Vec<String>, BTree>,
+}
+impl KV {
+    pub fn open(path: &Path) -> Result<KV, KVError> {
+        return KV::open_with_page_size(path, BTREE_PAGE_SIZE);
+    }
+    // `page_size`, one of PAGE_SIZES, only counts when the file is created
+    pub fn open_with_page_size(path: &Path, page_size: usize) -> Result<KV, KVError> {
+        return KV::open_with(path, page_size, None);
+    }
+    // with a cipher for an encrypted file, see crypt::Cipher for making one from a key.
+    // Whether a file is encrypted is settled when it is created.
+    pub fn open_with(
+        path: &Path,
+        page_size: usize,
+        cipher: Option<Arc<dyn PageCipher + Send + Sync>>,
+    ) -> Result<KV, KVError> {
+        let pager = Arc::new(Mutex::new(Pager::open_with(path, page_size, cipher)?));
+        let mut catalog = BTree::new();
+        catalog.root = pager.lock().unwrap().meta.root;
+        catalog.page_size = pager.lock().unwrap().node_size();
+        catalog.pager = Some(pager.clone());
+        return Ok(KV {
+            path: path.to_path_buf(),
+            pager,
+            catalog,
+            trees: HashMap::new(),
+        });
+    }
+    // how leaf pages are written from now on. Not kept in the file: pages written
+    // compressed are read back whatever is set, given the build has the codec. An error
+    // where compression saves nothing, see Pager::set_compression.
+    pub fn set_compression(&mut self, compression: Compression) -> Result<(), KVError> {
+        self.pager.lock().unwrap().set_compression(compression)?;
+        return Ok(());
+    }
+    fn tree(&self, root: u64, comparator: Comparator) -> BTree {
+        let mut tree = BTree::with_comparator(comparator);
+        tree.root = root;
+        tree.page_size = self.catalog.page_size;
+        tree.pager = Some(self.pager.clone());
+        return tree;
+    }
+    // the bucket at `path` with the buckets above it opened along the way, the catalog
+    // for the empty path. Without a comparator the bucket has to use a built-in one.
+    fn open_path(
+        &mut self,
+        path: &[String],
+        comparator: Option<Comparator>,
+    ) -> Result<&mut BTree, KVError> {
+        let Some((name, above)) = path.split_last() else {
+            return Ok(&mut self.catalog);
+        };
+        if self.trees.contains_key(path) {
+            let tree = self.trees.get_mut(path).unwrap();
+            if comparator.is_some_and(|comparator| comparator.name != tree.comparator.name) {
+                return Err(KVError::WrongComparator(path.join("/")));
+            }
+            return Ok(tree);
+        }
+        let entry = self
+            .open_path(above, None)?
+            .try_get_entry(name.as_bytes())?;
+        let (root, stored) = match entry {
+            Some((LEAF_BUCKET, value)) => decode_entry(&path.join("/"), &value)?,
+            _ => return Err(KVError::NoSuchTree(path.join("/"))),
+        };
+        let comparator = match comparator {
+            Some(comparator) if comparator.name == stored => comparator,
+            Some(_) => return Err(KVError::WrongComparator(path.join("/"))),
+            None => Comparator::by_name(&stored)
+                .ok_or_else(|| KVError::WrongComparator(path.join("/")))?,
+        };
+        let tree = self.tree(root, comparator);
+        return Ok(self.trees.entry(path.to_vec()).or_insert(tree));
+    }
+    pub fn create_tree(&mut self, name: &str) -> Result<&mut BTree, KVError> {
+        return self.create_bucket_with(&[name], Comparator::bytewise());
+    }
+    pub fn create_tree_with(
+        &mut self,
+        name: &str,
+        comparator: Comparator,
+    ) -> Result<&mut BTree, KVError> {
+        return self.create_bucket_with(&[name], comparator);
+    }
+    // opens a tree with the built-in comparator it was created with
+    pub fn open_tree(&mut self, name: &str) -> Result<&mut BTree, KVError> {
+        return self.open_bucket(&[name]);
+    }
+    // opens a tree created with a comparator of the same name as `comparator`
+    pub fn open_tree_with(
+        &mut self,
+        name: &str,
+        comparator: Comparator,
+    ) -> Result<&mut BTree, KVError> {
+        return self.open_bucket_with(&[name], comparator);
+    }
+    pub fn drop_tree(&mut self, name: &str) -> Result<(), KVError> {
+        return self.drop_bucket(&[name]);
+    }
+    pub fn tree_names(&self) -> Result<Vec<String>, KVError> {
+        return Ok(names_in(&self.catalog)?);
+    }
+    // a bucket inside the bucket at all but the last element of `path`, with the last
+    // element as its name. A one element path is a named tree.
+    pub fn create_bucket(&mut self, path: &[&str]) -> Result<&mut BTree, KVError> {
+        return self.create_bucket_with(path, Comparator::bytewise());
+    }
+    pub fn create_bucket_with(
+        &mut self,
+        path: &[&str],
+        comparator: Comparator,
+    ) -> Result<&mut BTree, KVError> {
+        let path: Vec<String> = path.iter().map(|name| name.to_string()).collect();
+        let Some((name, above)) = path.split_last() else {
+            panic!("a bucket needs a name");
+        };
+        assert!(!name.is_empty());
+        let entry = encode_entry(0, &comparator.name);
+        let mut req = UpdateReq::new(name.as_bytes().to_vec(), entry, MODE_INSERT_ONLY);
+        req.flags = LEAF_BUCKET;
+        if !self.open_path(above, None)?.update(&mut req) {
+            return Err(KVError::TreeExists(path.join("/")));
+        }
+        let tree = self.tree(0, comparator);
+        return Ok(self.trees.entry(path).or_insert(tree));
+    }
+    pub fn open_bucket(&mut self, path: &[&str]) -> Result<&mut BTree, KVError> {
+        let path: Vec<String> = path.iter().map(|name| name.to_string()).collect();
+        return self.open_path(&path, None);
+    }
+    pub fn open_bucket_with(
+        &mut self,
+        path: &[&str],
+        comparator: Comparator,
+    ) -> Result<&mut BTree, KVError> {
+        let path: Vec<String> = path.iter().map(|name| name.to_string()).collect();
+        return self.open_path(&path, Some(comparator));
+    }
+    // removes the bucket with everything in it, nested buckets included, and frees all
+    // of their pages
+    pub fn drop_bucket(&mut self, path: &[&str]) -> Result<(), KVError> {
+        self.flush()?;
+        let path: Vec<String> = path.iter().map(|name| name.to_string()).collect();
+        let Some((name, above)) = path.split_last() else {
+            panic!("the catalog can not be dropped");
+        };
+        self.trees.retain(|open, _| !open.starts_with(&path));
+        let (root, _) = match self
+            .open_path(above, None)?
+            .try_get_entry(name.as_bytes())?
+        {
+            Some((LEAF_BUCKET, value)) => decode_entry(&path.join("/"), &value)?,
+            _ => return Err(KVError::NoSuchTree(path.join("/"))),
+        };
+        self.tree(root, Comparator::bytewise()).destroy();
+        self.open_path(above, None)?
+            .delete_flagged(name.as_bytes().to_vec(), LEAF_BUCKET);
+        return Ok(());
+    }
+    // the names of the buckets directly inside the bucket at `path`
+    pub fn bucket_names(&mut self, path: &[&str]) -> Result<Vec<String>, KVError> {
+        return Ok(names_in(self.open_bucket(path)?)?);
+    }
+    fn visit(&self, tree: &BTree, path: &mut Vec<String>, f: &mut Visitor) -> Result<(), KVError> {
+        let mut iter = tree.try_scan(Bound::Unbounded, Bound::Unbounded)?;
+        while let Some((flags, key, value)) = iter.try_next_entry()? {
+            if flags != LEAF_BUCKET {
+                f(path, &key, &value);
+                continue;
+            }
+            path.push(String::from_utf8_lossy(&key).into_owned());
+            let (root, comparator) = decode_entry(&path.join("/"), &value)?;
+            let comparator = Comparator::by_name(&comparator)
+                .ok_or_else(|| KVError::WrongComparator(path.join("/")))?;
+            self.visit(&self.tree(root, comparator), path, f)?;
+            path.pop();
+        }
+        return Ok(());
+    }
+    // calls `f` with every key and value in the bucket and in the buckets nested in it,
+    // depth first and in key order, along with the path of the bucket holding them
+    pub fn walk(&mut self, path: &[&str], f: &mut Visitor) -> Result<(), KVError> {
+        self.flush()?;
+        let mut path: Vec<String> = path.iter().map(|name| name.to_string()).collect();
+        let tree = self.open_path(&path, None)?;
+        let (root, comparator) = (tree.root, tree.comparator.clone());
+        let tree = self.tree(root, comparator);
+        return self.visit(&tree, &mut path, f);
+    }
+    // writes the roots of the open buckets into the buckets holding them, the innermost
+    // first so their roots are current when it is their turn
+    fn flush(&mut self) -> Result<(), KVError> {
+        let depth = self.trees.keys().map(Vec::len).max().unwrap_or(0);
+        for depth in (1..=depth).rev() {
+            let paths: Vec<Vec<String>> = self
+                .trees
+                .keys()
+                .filter(|path| path.len() == depth)
+                .cloned()
+                .collect();
+            for path in paths {
+                let root = self.trees[&path].root;
+                let (name, above) = path.split_last().unwrap();
+                let parent = self.open_path(above, None)?;
+                let value = parent
+                    .try_get_entry(name.as_bytes())?
+                    .expect("open bucket in its parent")
+                    .1;
+                let (old, comparator) = decode_entry(&path.join("/"), &value)?;
+                if old != root {
+                    let entry = encode_entry(root, &comparator);
+                    let mut req = UpdateReq::new(name.as_bytes().to_vec(), entry, MODE_UPDATE_ONLY);
+                    req.flags = LEAF_BUCKET;
+                    parent.update(&mut req);
+                }
+            }
+        }
+        return Ok(());
+    }
+    // makes every change since the last commit durable, in all trees or in none
+    pub fn commit(&mut self) -> Result<(), KVError> {
+        self.flush()?;
+        let mut pager = self.pager.lock().unwrap();
+        let result = pager.commit(self.catalog.root);
+        drop(pager);
+        if let Err(err) = result {
+            self.rollback();
+            return Err(err.into());
+        }
+        return Ok(());
+    }
+    // the copy of the bucket `value` points at, in the pager of `to`, as the value for
+    // the bucket entry in the copy
+    fn copy_bucket(
+        &self,
+        value: &[u8],
+        to: &Arc<Mutex<Pager>>,
+        path: &mut Vec<String>,
+    ) -> Result<Vec<u8>, KVError> {
+        let (root, name) = decode_entry(&path.join("/"), value)?;
+        // a bucket opened with its own comparator still has it at hand
+        let comparator = match self.trees.get(path.as_slice()) {
+            Some(tree) => tree.comparator.clone(),
+            None => Comparator::by_name(&name)
+                .ok_or_else(|| KVError::WrongComparator(path.join("/")))?,
+        };
+        let mut copy = BTree::with_comparator(comparator.clone());
+        copy.page_size = self.catalog.page_size;
+        copy.pager = Some(to.clone());
+        self.copy_tree(&self.tree(root, comparator), &mut copy, path)?;
+        return Ok(encode_entry(copy.root, &name));
+    }
+    // loads the live entries of `from` into the empty tree `to`, nested buckets included
+    fn copy_tree(
+        &self,
+        from: &BTree,
+        to: &mut BTree,
+        path: &mut Vec<String>,
+    ) -> Result<(), KVError> {
+        let pager = to.pager.clone().unwrap();
+        let now = now_millis();
+        let mut failed = None;
+        let mut iter = from
+            .try_scan(Bound::Unbounded, Bound::Unbounded)?
+            .include_expired();
+        let entries = std::iter::from_fn(|| loop {
+            let (flags, key, value) = match iter.try_next_entry() {
+                Ok(entry) => entry?,
+                Err(err) => {
+                    failed = Some(err.into());
+                    return None;
+                }
+            };
+            if is_expired(flags, &value, now) {
+                continue;
+            }
+            if flags != LEAF_BUCKET {
+                return Some((flags, key, value));
+            }
+            path.push(String::from_utf8_lossy(&key).into_owned());
+            let copied = self.copy_bucket(&value, &pager, path);
+            path.pop();
+            match copied {
+                Ok(value) => return Some((flags, key, value)),
+                Err(err) => {
+                    failed = Some(err);
+                    return None;
+                }
+            }
+        });
+        to.bulk_load(entries);
+        return failed.map_or(Ok(()), Err);
+    }
+    // rewrites the database into a new file holding only the live data, densely packed,
+    // then renames it over the old file. Anyone still reading the old file keeps reading
+    // the old contents until they open it again, snapshots taken before keep the old
+    // pager and go on reading through it. Commits any pending changes first.
+    pub fn compact(&mut self) -> Result<(), KVError> {
+        self.commit()?;
+        let temp = PathBuf::from(format!("{}.compact", self.path.display()));
+        let _ = fs::remove_file(&temp);
+        let (page_size, compression, cipher, txid) = {
+            let pager = self.pager.lock().unwrap();
+            (
+                pager.page_size,
+                pager.compression,
+                pager.cipher(),
+                pager.meta.txid,
+            )
+        };
+        let mut fresh = KV::open_with(&temp, page_size, cipher.clone())?;
+        fresh.set_compression(compression)?;
+        // the copy goes on from the last commit, so the backups taken before it still
+        // have an increment that brings them up to it
+        fresh.pager.lock().unwrap().meta.txid = txid;
+        let result = self.copy_tree(&self.catalog, &mut fresh.catalog, &mut vec![]);
+        if let Err(err) = result.and_then(|_| fresh.commit()) {
+            drop(fresh);
+            let _ = fs::remove_file(&temp);
+            return Err(err);
+        }
+        drop(fresh);
+        fs::rename(&temp, &self.path)?;
+        let mut pager = Pager::open_with(&self.path, page_size, cipher)?;
+        pager.compression = compression;
+        self.catalog.root = pager.meta.root;
+        self.pager = Arc::new(Mutex::new(pager));
+        self.catalog.pager = Some(self.pager.clone());
+        self.trees.clear();
+        return Ok(());
+    }
+    // shrinks the file without a second copy: the pages near the end are moved into free
+    // pages further down, then the free pages at the end are cut off. Pages stay as full
+    // as they are. Commits any pending changes first.
+    pub fn compact_in_place(&mut self) -> Result<(), KVError> {
+        self.commit()?;
+        self.trees.clear();
+        let boundary = {
+            let pager = self.pager.lock().unwrap();
+            pager.npages() - pager.free_pages() as u64
+        };
+        self.catalog.relocate(boundary);
+        self.commit()?;
+        let mut pager = self.pager.lock().unwrap();
+        pager.truncate(self.catalog.root)?;
+        return Ok(());
+    }
+    // the database as of the last commit, which stays readable while this goes on
+    // committing. It can be sent to another thread.
+    pub fn snapshot(&self) -> Snapshot {
+        let mut pager = self.pager.lock().unwrap();
+        pager.pin();
+        return Snapshot {
+            pager: self.pager.clone(),
+            meta: pager.meta,
+            node_size: self.catalog.page_size,
+        };
+    }
+    // see Snapshot::backup_to
+    pub fn backup_to(&self, path: &Path) -> Result<(), KVError> {
+        return self.snapshot().backup_to(path);
+    }
+    // see Snapshot::backup_incremental
+    pub fn backup_incremental(&self, since: u64, path: &Path) -> Result<(), KVError> {
+        return self.snapshot().backup_incremental(since, path);
+    }
+    // the last commit, as the nodes it wrote are stamped
+    pub fn txid(&self) -> u64 {
+        return self.pager.lock().unwrap().meta.txid;
+    }
+    // throws away every change since the last commit
+    pub fn rollback(&mut self) {
+        let mut pager = self.pager.lock().unwrap();
+        pager.rollback();
+        self.catalog.root = pager.meta.root;
+        drop(pager);
+        self.trees.clear();
+    }
+    // runs `f` as one transaction: committed when it returns Ok, rolled back otherwise
+    pub fn transaction<T>(
+        &mut self,
+        f: impl FnOnce(&mut KV) -> Result<T, KVError>,
+    ) -> Result<T, KVError> {
+        match f(self) {
+            Ok(value) => {
+                self.commit()?;
+                return Ok(value);
+            }
+            Err(err) => {
+                self.rollback();
+                return Err(err);
+            }
+        }
+    }
+}
+
+// the committed state a KV was in when the snapshot was taken. Its pages are not
+// reused by later commits until it is dropped.
+pub struct Snapshot {
+    pager: Arc<Mutex<Pager>>,
+    pub meta: Meta,
+    node_size: usize,
+}
+impl Snapshot {
+    // copies the snapshot into a new database file at `path`. Only the pages its trees
+    // reach are copied, as they are and at the same page numbers, the others are free
+    // in the copy, so it opens like the original did at the time, with the same key if
+    // it is encrypted.
+    pub fn backup_to(&self, path: &Path) -> Result<(), KVError> {
+        return create_new(path, || self.copy_to(path));
+    }
+    // writes the pages changed since commit `since` into a new file at `path`, from
+    // which restore_backup brings a backup taken at that commit up to this snapshot.
+    // |INCREMENT_SIG|since 8B|meta page|then (pointer 8B|page as in the file) per page|
+    pub fn backup_incremental(&self, since: u64, path: &Path) -> Result<(), KVError> {
+        return create_new(path, || {
+            let pages = self.tree().pages_since(Some(since))?;
+            let mut out = BufWriter::new(fs::File::create(path)?);
+            out.write_all(INCREMENT_SIG)?;
+            out.write_all(&since.to_le_bytes())?;
+            out.write_all(&self.meta.encode())?;
+            for pointer in pages {
+                let data = self.pager.lock().unwrap().raw_page(pointer)?;
+                out.write_all(&pointer.to_le_bytes())?;
+                out.write_all(&data)?;
+            }
+            out.into_inner()
+                .map_err(|err| err.into_error())?
+                .sync_all()?;
+            return Ok(());
+        });
+    }
+    // the catalog as of the snapshot, for reading its pages
+    fn tree(&self) -> BTree {
+        let mut tree = BTree::new();
+        tree.root = self.meta.root;
+        tree.page_size = self.node_size;
+        tree.pager = Some(self.pager.clone());
+        return tree;
+    }
+    fn copy_to(&self, path: &Path) -> Result<(), KVError> {
+        let (page_size, cipher) = {
+            let pager = self.pager.lock().unwrap();
+            (pager.page_size, pager.cipher())
+        };
+        let pages = self.tree().pages()?;
+        let mut backup = Pager::open_with(path, page_size, cipher)?;
+        for pointer in &pages {
+            let data = self.pager.lock().unwrap().raw_page(*pointer)?;
+            backup.put_raw(*pointer, &data)?;
+        }
+        backup.finish_raw(&self.meta, &pages)?;
+        return Ok(());
+    }
+}
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        if let Ok(mut pager) = self.pager.lock() {
+            pager.unpin();
+        }
+    }
+}
+
+pub const INCREMENT_SIG: &[u8; 16] = b"rustdb incr v001";
+
+// runs `write` to fill the file at `path`, which must not exist yet, and takes away what
+// it wrote when it fails
+fn create_new(path: &Path, write: impl FnOnce() -> Result<(), KVError>) -> Result<(), KVError> {
+    if fs::metadata(path).is_ok() {
+        let message = format!("{} already exists", path.display());
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, message).into());
+    }
+    let result = write();
+    if result.is_err() {
+        let _ = fs::remove_file(path);
+    }
+    return result;
+}
+
+// rebuilds a database at `path`, a new file, from a full backup and the incremental
+// backups taken after it, in the order they were taken. Each incremental backup has to
+// start at or before the commit the one before it ends at. The cipher is the one of the
+// backed up file, if it is encrypted.
+pub fn restore_backup(
+    path: &Path,
+    full: &Path,
+    increments: &[&Path],
+    cipher: Option<Arc<dyn PageCipher + Send + Sync>>,
+) -> Result<(), KVError> {
+    return create_new(path, || {
+        fs::copy(full, path)?;
+        for increment in increments {
+            apply_increment(path, increment, cipher.clone())?;
+        }
+        return Ok(());
+    });
+}
+fn apply_increment(
+    path: &Path,
+    increment: &Path,
+    cipher: Option<Arc<dyn PageCipher + Send + Sync>>,
+) -> Result<(), KVError> {
+    let bad = |message: String| -> KVError {
+        let message = format!("{}: {message}", increment.display());
+        return io::Error::new(io::ErrorKind::InvalidData, message).into();
+    };
+    let mut file = BufReader::new(fs::File::open(increment)?);
+    let mut head = [0; 24];
+    file.read_exact(&mut head)?;
+    if &head[..16] != INCREMENT_SIG {
+        return Err(bad("not an incremental backup".to_string()));
+    }
+    let since = u64::from_le_bytes(head[16..].try_into().unwrap());
+    // the meta page is at least as large as the smallest page size
+    let mut page = vec![0; PAGE_SIZES[0]];
+    file.read_exact(&mut page)?;
+    let meta = Meta::decode(&page).ok_or_else(|| bad("bad meta page".to_string()))?;
+    let page_size = meta.page_size as usize;
+    let mut page = vec![0; page_size - PAGE_SIZES[0]];
+    file.read_exact(&mut page)?;
+
+    let pager = Pager::open_with(path, page_size, cipher)?;
+    if pager.page_size != page_size {
+        return Err(bad(format!(
+            "pages of {page_size} bytes, not {}",
+            pager.page_size
+        )));
+    }
+    let txid = pager.meta.txid;
+    if txid < since || txid > meta.txid {
+        let message = format!("goes from txid {since} to {}, not from {txid}", meta.txid);
+        return Err(bad(message));
+    }
+    let pager = Arc::new(Mutex::new(pager));
+    let mut pointer = [0; 8];
+    let mut data = vec![0; page_size];
+    let mut put = HashSet::new();
+    while !file.fill_buf()?.is_empty() {
+        file.read_exact(&mut pointer)?;
+        file.read_exact(&mut data)?;
+        let pointer = u64::from_le_bytes(pointer);
+        if page_number(pointer) == 0 || page_number(pointer) >= meta.npages {
+            return Err(bad(format!("page {} past the end", page_number(pointer))));
+        }
+        pager.lock().unwrap().put_raw(pointer, &data)?;
+        put.insert(page_number(pointer));
+    }
+    let mut tree = BTree::new();
+    tree.root = meta.root;
+    tree.page_size = pager.lock().unwrap().node_size();
+    tree.pager = Some(pager.clone());
+    let pages = restored_pages(&tree, &put, since, meta.npages).map_err(bad)?;
+    pager.lock().unwrap().finish_raw(&meta, &pages)?;
+    return Ok(());
+}
+// every page the trees of a restored file reach. Each one is in the increment, `put`, or
+// is a node the base file had unchanged since `since`. A page reached twice would be a
+// cycle, or a page shared by two trees, and neither is followed.
+fn restored_pages(
+    tree: &BTree,
+    put: &HashSet<u64>,
+    since: u64,
+    npages: u64,
+) -> Result<Vec<u64>, String> {
+    let mut pages = vec![];
+    let mut seen = HashSet::new();
+    let mut stack = match tree.root {
+        0 => vec![],
+        root => vec![root],
+    };
+    while let Some(pointer) = stack.pop() {
+        let page = page_number(pointer);
+        if page == 0 || page >= npages {
+            return Err(format!("page {page} past the end"));
+        }
+        if !seen.insert(page) {
+            return Err(format!("page {page} reached twice"));
+        }
+        let node = tree
+            .read_node(pointer)
+            .map_err(|err| format!("page {page}: {err}"))?;
+        if node.btype() != BNODE_LEAF && node.btype() != BNODE_NODE {
+            return Err(format!("page {page} is not a node"));
+        }
+        if !put.contains(&page) && node.txid() > since {
+            return Err(format!(
+                "page {page} is neither in the backup nor the increment"
+            ));
+        }
+        pages.push(pointer);
+        for i in 0..node.nkeys() {
+            if node.btype() == BNODE_NODE {
+                stack.push(node.get_pointer(i));
+            } else if node.get_pointer(i) == LEAF_BUCKET {
+                let value = node.get_value(i);
+                if value.len() < 8 {
+                    return Err(format!("bad bucket in page {page}"));
+                }
+                let root = u64::from_le_bytes(value[..8].try_into().unwrap());
+                if root != 0 {
+                    stack.push(root);
+                }
+            }
+        }
+    }
+    return Ok(pages);
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use std::{
+        fs,
+        path::PathBuf,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    // a fresh file under the temp dir, removed again when the test is done with it
+    pub struct TempFile(pub PathBuf);
+    impl TempFile {
+        pub fn new(name: &str) -> TempFile {
+            static COUNT: AtomicUsize = AtomicUsize::new(0);
+            let count = COUNT.fetch_add(1, Ordering::Relaxed);
+            let file = format!("rustdb-{name}-{}-{count}.db", std::process::id());
+            let path = std::env::temp_dir().join(file);
+            let _ = fs::remove_file(&path);
+            return TempFile(path);
+        }
+    }
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn named_trees_commit_together() {
+        let file = TempFile::new("named");
+        let mut db = KV::open(&file.0).unwrap();
+        let users = db.create_tree("users").unwrap();
+        for i in 0..2000u32 {
+            users.insert(format!("user{i:05}").into_bytes(), vec![7; 100]);
+        }
+        db.create_tree_with("scores", Comparator::big_endian_int())
+            .unwrap();
+        assert!(matches!(
+            db.create_tree("users"),
+            Err(KVError::TreeExists(_))
+        ));
+        db.commit().unwrap();
+
+        // one transaction over two trees: all of it or none of it
+        let result = db.transaction(|db| {
+            db.open_tree("users")?.delete(b"user00001".to_vec());
+            db.open_tree("scores")?.insert(vec![1, 0], b"256".to_vec());
+            return Err::<(), _>(KVError::NoSuchTree("stop".to_string()));
+        });
+        assert!(result.is_err());
+        assert!(db
+            .open_tree("users")
+            .unwrap()
+            .get(b"user00001")
+            .unwrap()
+            .is_some());
+        assert_eq!(db.open_tree("scores").unwrap().get(&[1, 0]).unwrap(), None);
+        db.transaction(|db| {
+            db.open_tree("users")?.delete(b"user00001".to_vec());
+            db.open_tree("scores")?.insert(vec![1, 0], b"256".to_vec());
+            db.open_tree("scores")?.insert(vec![9], b"9".to_vec());
+            return Ok(());
+        })
+        .unwrap();
+        drop(db);
+
+        let mut db = KV::open(&file.0).unwrap();
+        assert_eq!(db.tree_names().unwrap(), vec!["scores", "users"]);
+        let users = db.open_tree("users").unwrap();
+        assert_eq!(users.get(b"user00001").unwrap(), None);
+        assert_eq!(users.get(b"user01999").unwrap(), Some(vec![7; 100]));
+        let scores = db.open_tree("scores").unwrap();
+        assert_eq!(scores.comparator.name, "big_endian_int");
+        let keys: Vec<Vec<u8>> = scores
+            .scan(Bound::Unbounded, Bound::Unbounded)
+            .map(|(key, _)| key)
+            .collect();
+        assert_eq!(keys, vec![vec![9], vec![1, 0]]);
+        assert!(matches!(
+            db.open_tree_with("scores", Comparator::bytewise()),
+            Err(KVError::WrongComparator(_))
+        ));
+
+        // the pages of a dropped tree are reused instead of growing the file
+        let npages = db.pager.lock().unwrap().npages();
+        db.drop_tree("users").unwrap();
+        db.commit().unwrap();
+        assert!(db.pager.lock().unwrap().free_pages() > 50);
+        let logs = db.create_tree("logs").unwrap();
+        for i in 0..2000u32 {
+            logs.insert(format!("log{i:05}").into_bytes(), vec![1; 100]);
+        }
+        db.commit().unwrap();
+        assert!(db.pager.lock().unwrap().npages() <= npages + 5);
+        drop(db);
+
+        let mut db = KV::open(&file.0).unwrap();
+        assert_eq!(db.tree_names().unwrap(), vec!["logs", "scores"]);
+        assert!(matches!(db.open_tree("users"), Err(KVError::NoSuchTree(_))));
+        assert_eq!(
+            db.open_tree("logs")
+                .unwrap()
+                .scan(Bound::Unbounded, Bound::Unbounded)
+                .count(),
+            2000
+        );
+    }
+
+    #[test]
+    fn buckets_nest_and_drop_as_a_unit() {
+        let file = TempFile::new("buckets");
+        let mut db = KV::open(&file.0).unwrap();
+        db.create_tree("tenants").unwrap();
+        for tenant in ["t1", "t2"] {
+            db.create_bucket(&["tenants", tenant]).unwrap();
+            let orders = db.create_bucket(&["tenants", tenant, "orders"]).unwrap();
+            for i in 0..500u32 {
+                orders.insert(format!("order{i:04}").into_bytes(), vec![3; 200]);
+            }
+            let tenant = db.open_bucket(&["tenants", tenant]).unwrap();
+            tenant.insert(b"name".to_vec(), b"acme".to_vec());
+        }
+        assert!(matches!(
+            db.create_bucket(&["tenants", "t1"]),
+            Err(KVError::TreeExists(_))
+        ));
+        assert!(matches!(
+            db.open_bucket(&["tenants", "t3", "orders"]),
+            Err(KVError::NoSuchTree(_))
+        ));
+        db.commit().unwrap();
+        drop(db);
+
+        let mut db = KV::open(&file.0).unwrap();
+        assert_eq!(db.bucket_names(&["tenants"]).unwrap(), vec!["t1", "t2"]);
+        // a bucket entry is not a plain value, plain writes and deletes pass it by
+        let tenants = db.open_tree("tenants").unwrap();
+        assert!(!tenants.delete(b"t1".to_vec()));
+        assert!(!tenants.update_existing(b"t1".to_vec(), vec![]));
+        let mut seen = vec![];
+        db.walk(&["tenants", "t1"], &mut |path, key, _| {
+            seen.push((path.join("/"), String::from_utf8_lossy(key).into_owned()));
+        })
+        .unwrap();
+        assert_eq!(seen.len(), 501);
+        assert_eq!(seen[0], ("tenants/t1".to_string(), "name".to_string()));
+        assert_eq!(
+            seen[1],
+            ("tenants/t1/orders".to_string(), "order0000".to_string())
+        );
+
+        let before = db.pager.lock().unwrap().free_pages();
+        db.drop_bucket(&["tenants", "t1"]).unwrap();
+        db.commit().unwrap();
+        assert!(db.pager.lock().unwrap().free_pages() > before + 20);
+        assert_eq!(db.bucket_names(&["tenants"]).unwrap(), vec!["t2"]);
+        let orders = db.open_bucket(&["tenants", "t2", "orders"]).unwrap();
+        assert_eq!(orders.get(b"order0499").unwrap(), Some(vec![3; 200]));
+    }
+
+    #[test]
+    fn compaction_shrinks_the_file() {
+        let size = |file: &TempFile| fs::metadata(&file.0).unwrap().len();
+        let file = TempFile::new("compact");
+        let mut db = KV::open(&file.0).unwrap();
+        for name in ["a", "b"] {
+            let tree = db.create_tree(name).unwrap();
+            for i in 0..3000u32 {
+                tree.insert(format!("key{i:05}").into_bytes(), vec![1; 100]);
+            }
+        }
+        let nested = db.create_bucket(&["a", "nested"]).unwrap();
+        nested.insert(b"x".to_vec(), b"y".to_vec());
+        db.commit().unwrap();
+        // leaves at the end of "b" hold the only live data near the end of the file
+        let b = db.open_tree("b").unwrap();
+        for i in 0..2900u32 {
+            b.delete(format!("key{i:05}").into_bytes());
+        }
+        db.drop_tree("a").unwrap();
+        db.create_tree("a")
+            .unwrap()
+            .insert(b"k".to_vec(), b"v".to_vec());
+        db.commit().unwrap();
+        let full = size(&file);
+
+        db.compact_in_place().unwrap();
+        let in_place = size(&file);
+        assert!(in_place * 2 < full, "{in_place} {full}");
+        drop(db);
+        let mut db = KV::open(&file.0).unwrap();
+        let b = db.open_tree("b").unwrap();
+        assert_eq!(b.scan(Bound::Unbounded, Bound::Unbounded).count(), 100);
+        assert_eq!(b.get(b"key02999").unwrap(), Some(vec![1; 100]));
+
+        let nested = db.create_bucket(&["b", "nested"]).unwrap();
+        nested
+            .insert_with_ttl(b"gone".to_vec(), vec![], std::time::Duration::ZERO)
+            .unwrap();
+        nested.insert(b"kept".to_vec(), vec![2]);
+        db.compact().unwrap();
+        assert!(size(&file) < in_place, "{} {in_place}", size(&file));
+        assert!(!PathBuf::from(format!("{}.compact", file.0.display())).exists());
+        drop(db);
+        let mut db = KV::open(&file.0).unwrap();
+        assert_eq!(db.tree_names().unwrap(), vec!["a", "b"]);
+        assert_eq!(
+            db.open_tree("a").unwrap().get(b"k").unwrap(),
+            Some(b"v".to_vec())
+        );
+        // the bucket entry of "nested" is among the entries of "b"
+        let b = db.open_tree("b").unwrap();
+        assert_eq!(b.scan(Bound::Unbounded, Bound::Unbounded).count(), 101);
+        let mut seen = vec![];
+        db.walk(&["b", "nested"], &mut |_, key, _| seen.push(key.to_vec()))
+            .unwrap();
+        assert_eq!(seen, vec![b"kept".to_vec()]);
+        assert_eq!(db.pager.lock().unwrap().free_pages(), 0);
+    }
+
+    #[test]
+    fn page_size_is_fixed_at_creation() {
+        for page_size in [16384, 65536] {
+            let file = TempFile::new("pagesize");
+            let mut db = KV::open_with_page_size(&file.0, page_size).unwrap();
+            let blobs = db.create_tree("blobs").unwrap();
+            assert_eq!(blobs.max_val_size(), page_size / 4 * 3 - 72);
+            // values no 4 KiB page could hold
+            for i in 0..40u32 {
+                let value = vec![i as u8; blobs.max_val_size() - i as usize];
+                blobs.insert(format!("blob{i:03}").into_bytes(), value);
+            }
+            db.commit().unwrap();
+            drop(db);
+            assert_eq!(fs::metadata(&file.0).unwrap().len() % page_size as u64, 0);
+
+            // the file keeps its page size whatever is asked for on opening it
+            let mut db = KV::open(&file.0).unwrap();
+            assert_eq!(db.pager.lock().unwrap().page_size, page_size);
+            let blobs = db.open_tree("blobs").unwrap();
+            assert_eq!(blobs.page_size, page_size);
+            for i in 0..40u32 {
+                let value = blobs
+                    .get(format!("blob{i:03}").as_bytes())
+                    .unwrap()
+                    .unwrap();
+                assert_eq!(value, vec![i as u8; blobs.max_val_size() - i as usize]);
+            }
+        }
+        let file = TempFile::new("pagesize");
+        assert!(KV::open_with_page_size(&file.0, 5000).is_err());
+    }
+    #[test]
+    fn backup_while_committing() {
+        let file = TempFile::new("live");
+        let fill = |db: &mut KV, round: u32| {
+            let users = db.open_bucket(&["app", "users"]).unwrap();
+            for i in 0..1500u32 {
+                let value = format!("user {i} round {round}").into_bytes();
+                users.insert(format!("user{i:05}").into_bytes(), value);
+            }
+            db.commit().unwrap();
+        };
+        let check = |path: &Path, round: u32| {
+            let mut db = KV::open(path).unwrap();
+            let users = db.open_bucket(&["app", "users"]).unwrap();
+            for i in 0..1500u32 {
+                let value = users
+                    .get(format!("user{i:05}").as_bytes())
+                    .unwrap()
+                    .unwrap();
+                assert_eq!(value, format!("user {i} round {round}").into_bytes());
+            }
+            // the copy takes writes of its own
+            fill(&mut db, round + 10);
+        };
+        let mut db = KV::open(&file.0).unwrap();
+        db.create_tree("app").unwrap();
+        db.create_bucket(&["app", "users"]).unwrap();
+        fill(&mut db, 0);
+
+        // the writes after the snapshot reuse no page of it, in this thread or while
+        // another one copies it, and compacting leaves it reading the old file
+        let snapshot = db.snapshot();
+        for round in 1..4 {
+            fill(&mut db, round);
+        }
+        db.compact().unwrap();
+        fill(&mut db, 3);
+        let first = TempFile::new("backup");
+        snapshot.backup_to(&first.0).unwrap();
+        assert!(snapshot.backup_to(&first.0).is_err());
+        drop(snapshot);
+        let snapshot = db.snapshot();
+        let second = TempFile::new("backup");
+        let copy = {
+            let path = second.0.clone();
+            std::thread::spawn(move || snapshot.backup_to(&path))
+        };
+        for round in 4..8 {
+            fill(&mut db, round);
+        }
+        copy.join().unwrap().unwrap();
+        check(&first.0, 0);
+        check(&second.0, 3);
+
+        // the pages held for the snapshots are free again once they are gone
+        let npages = db.pager.lock().unwrap().npages();
+        fill(&mut db, 8);
+        assert_eq!(db.pager.lock().unwrap().npages(), npages);
+        db.compact().unwrap();
+        db.backup_to(&TempFile::new("backup").0).unwrap();
+
+        // a backup in another process copies under a shared lock on the file, commits
+        // wait until it lets go
+        let lock = fs::File::open(&file.0).unwrap();
+        lock.lock_shared().unwrap();
+        let (done, committed) = std::sync::mpsc::channel();
+        let writer = std::thread::spawn(move || {
+            fill(&mut db, 9);
+            done.send(()).unwrap();
+        });
+        let wait = std::time::Duration::from_millis(300);
+        assert!(committed.recv_timeout(wait).is_err());
+        lock.unlock().unwrap();
+        committed.recv().unwrap();
+        writer.join().unwrap();
+    }
+    #[test]
+    fn pages_that_look_compressed() {
+        // a free list page starts with the pointer to the next one, which has the bit of
+        // a compressed page set in its first two bytes from page 0x8000 on
+        let file = TempFile::new("list");
+        let page = BTREE_PAGE_SIZE;
+        let next: u64 = 0x8001;
+        let meta = Meta {
+            root: 0,
+            npages: next + 1,
+            free_head: 1,
+            page_size: page as u64,
+            txid: 1,
+            ..Meta::default()
+        };
+        let mut bytes = meta.encode();
+        bytes.resize(3 * page, 0);
+        bytes[page..page + 8].copy_from_slice(&next.to_le_bytes());
+        bytes[page + 8..page + 16].copy_from_slice(&1u64.to_le_bytes());
+        bytes[page + 16..page + 24].copy_from_slice(&2u64.to_le_bytes());
+        let write = |bytes: &[u8]| {
+            fs::write(&file.0, bytes).unwrap();
+            let out = fs::File::options().write(true).open(&file.0).unwrap();
+            out.set_len((next + 1) * page as u64).unwrap();
+        };
+        write(&bytes);
+        let db = KV::open(&file.0).unwrap();
+        assert_eq!(db.pager.lock().unwrap().free_pages(), 1);
+        drop(db);
+
+        // a node page flagged compressed that does not decompress is an error
+        let meta = Meta {
+            root: 2,
+            free_head: 0,
+            ..meta
+        };
+        bytes[..page].copy_from_slice(&meta.encode());
+        bytes[2 * page..2 * page + 2].copy_from_slice(&0x8001u16.to_ne_bytes());
+        bytes[2 * page + 2..2 * page + 6].copy_from_slice(&u32::MAX.to_ne_bytes());
+        write(&bytes);
+        let db = KV::open(&file.0).unwrap();
+        assert!(matches!(db.tree_names(), Err(KVError::Io(_))));
+    }
+    #[test]
+    fn pointers_out_of_the_file() {
+        let file = TempFile::new("pointers");
+        let mut db = KV::open(&file.0).unwrap();
+        let users = db.create_tree("users").unwrap();
+        for i in 0..2000u32 {
+            users.insert(format!("user{i:05}").into_bytes(), vec![7; 100]);
+        }
+        let root = page_number(users.root) as usize;
+        db.commit().unwrap();
+        drop(db);
+
+        // the first kid of the root points far past the end of the file
+        let mut bytes = fs::read(&file.0).unwrap();
+        let node = root * BTREE_PAGE_SIZE;
+        assert_eq!(
+            u16::from_ne_bytes([bytes[node], bytes[node + 1]]),
+            BNODE_NODE
+        );
+        let pointer = node + crate::B_tree::HEADER as usize;
+        bytes[pointer..pointer + 8].copy_from_slice(&(1u64 << 30).to_ne_bytes());
+        fs::write(&file.0, &bytes).unwrap();
+        let mut db = KV::open(&file.0).unwrap();
+        let users = db.open_tree("users").unwrap();
+        let err = users.get(b"user00000").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(users.get(b"user01999").unwrap(), Some(vec![7; 100]));
+    }
+    #[test]
+    fn incremental_backups() {
+        type Contents = Vec<(Vec<String>, Vec<u8>, Vec<u8>)>;
+        let contents = |path: &Path| -> Contents {
+            let mut db = KV::open(path).unwrap();
+            let mut contents = vec![];
+            db.walk(&[], &mut |path, key, value| {
+                contents.push((path.to_vec(), key.to_vec(), value.to_vec()));
+            })
+            .unwrap();
+            return contents;
+        };
+        let file = TempFile::new("source");
+        let mut db = KV::open(&file.0).unwrap();
+        db.create_tree("logs").unwrap();
+        let orders = db.create_tree("orders").unwrap();
+        for i in 0..3000u32 {
+            orders.insert(format!("order{i:05}").into_bytes(), vec![b'o'; 100]);
+        }
+        db.commit().unwrap();
+        let full = TempFile::new("full");
+        db.backup_to(&full.0).unwrap();
+        let base = db.txid();
+
+        // a few changes touch a few pages
+        let orders = db.open_tree("orders").unwrap();
+        for i in (0..3000u32).step_by(500) {
+            orders.insert(format!("order{i:05}").into_bytes(), b"changed".to_vec());
+        }
+        db.commit().unwrap();
+        let first = TempFile::new("incr");
+        db.backup_incremental(base, &first.0).unwrap();
+        let at_first = contents(&file.0);
+        let middle = db.txid();
+
+        let logs = db.open_tree("logs").unwrap();
+        for i in 0..200u32 {
+            logs.insert(format!("log{i:05}").into_bytes(), vec![b'l'; 50]);
+        }
+        db.commit().unwrap();
+        let orders = db.open_tree("orders").unwrap();
+        for i in 1000..2000u32 {
+            orders.delete(format!("order{i:05}").into_bytes());
+        }
+        db.commit().unwrap();
+        let second = TempFile::new("incr");
+        db.backup_incremental(middle, &second.0).unwrap();
+        let size = |path: &Path| fs::metadata(path).unwrap().len();
+        assert!(size(&first.0) * 10 < size(&full.0));
+
+        let restored = TempFile::new("restored");
+        restore_backup(&restored.0, &full.0, &[&first.0], None).unwrap();
+        assert_eq!(contents(&restored.0), at_first);
+        let restored = TempFile::new("restored");
+        restore_backup(&restored.0, &full.0, &[&first.0, &second.0], None).unwrap();
+        assert_eq!(contents(&restored.0), contents(&file.0));
+        // every page but the meta page and the one of the free list is reachable or free
+        let mut db = KV::open(&restored.0).unwrap();
+        let reachable = db.catalog.pages().unwrap().len() as u64;
+        let pager = db.pager.lock().unwrap();
+        assert_eq!(reachable + pager.free_pages() as u64 + 2, pager.npages());
+        drop(pager);
+        let logs = db.open_tree("logs").unwrap();
+        logs.insert(b"after".to_vec(), b"restore".to_vec());
+        db.commit().unwrap();
+        db.compact().unwrap();
+
+        // the chain has to line up
+        let restored = TempFile::new("restored");
+        assert!(restore_backup(&restored.0, &full.0, &[&second.0], None).is_err());
+        assert!(fs::metadata(&restored.0).is_err());
+        assert!(restore_backup(&restored.0, &full.0, &[&full.0], None).is_err());
+
+        // compacting moves every page and goes on from the txid it had, so the increment
+        // since a backup from before then holds all the file reaches
+        let file = TempFile::new("source");
+        let mut db = KV::open(&file.0).unwrap();
+        let orders = db.create_tree("orders").unwrap();
+        for i in 0..3000u32 {
+            orders.insert(format!("order{i:05}").into_bytes(), vec![b'o'; 100]);
+        }
+        db.commit().unwrap();
+        let full = TempFile::new("full");
+        db.backup_to(&full.0).unwrap();
+        let base = db.txid();
+        let orders = db.open_tree("orders").unwrap();
+        for i in 0..1500u32 {
+            orders.delete(format!("order{i:05}").into_bytes());
+        }
+        db.compact().unwrap();
+        assert!(db.txid() > base);
+        let increment = TempFile::new("incr");
+        db.backup_incremental(base, &increment.0).unwrap();
+        let restored = TempFile::new("restored");
+        restore_backup(&restored.0, &full.0, &[&increment.0], None).unwrap();
+        assert_eq!(contents(&restored.0), contents(&file.0));
+
+        // a page of the increment that leads back to the root is not followed around
+        let mut bytes = fs::read(&increment.0).unwrap();
+        let page = BTREE_PAGE_SIZE;
+        let root = db.pager.lock().unwrap().meta.root;
+        let records: Vec<usize> = (16 + 8 + page..bytes.len()).step_by(8 + page).collect();
+        let pointer = |at: usize| u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap());
+        let from = *records.iter().find(|at| pointer(**at) == root).unwrap();
+        let to = *records.iter().find(|at| pointer(**at) != root).unwrap();
+        let data = bytes[from + 8..from + 8 + page].to_vec();
+        bytes[to + 8..to + 8 + page].copy_from_slice(&data);
+        fs::write(&increment.0, &bytes).unwrap();
+        let restored = TempFile::new("restored");
+        let err = restore_backup(&restored.0, &full.0, &[&increment.0], None).unwrap_err();
+        assert!(err.to_string().contains("reached twice"), "{err}");
+    }
+    #[cfg(all(target_os = "linux", any(feature = "lz4", feature = "zstd")))]
+    #[test]
+    fn compressed_leaf_pages() {
+        use std::os::unix::fs::MetadataExt;
+        let codecs = [
+            #[cfg(feature = "lz4")]
+            Compression::Lz4,
+            #[cfg(feature = "zstd")]
+            Compression::Zstd(3),
+        ];
+        let fill = |db: &mut KV| {
+            let orders = db.create_tree("orders").unwrap();
+            for i in 0..3000u32 {
+                let value = format!("status=shipped;carrier=post;{i:0>80}").into_bytes();
+                orders.insert(format!("order{i:06}").into_bytes(), value);
+            }
+            db.commit().unwrap();
+        };
+        let plain = TempFile::new("plain");
+        let mut db = KV::open_with_page_size(&plain.0, 16384).unwrap();
+        fill(&mut db);
+        drop(db);
+        let plain_blocks = fs::metadata(&plain.0).unwrap().blocks();
+        for compression in codecs {
+            let file = TempFile::new("compressed");
+            let mut db = KV::open_with_page_size(&file.0, 16384).unwrap();
+            db.set_compression(compression).unwrap();
+            fill(&mut db);
+            drop(db);
+            // same pages, fewer blocks behind them
+            let metadata = fs::metadata(&file.0).unwrap();
+            assert_eq!(metadata.len(), fs::metadata(&plain.0).unwrap().len());
+            assert!(metadata.blocks() * 2 < plain_blocks, "{compression:?}");
+
+            // read back without setting anything, then changed and compacted
+            let mut db = KV::open(&file.0).unwrap();
+            let orders = db.open_tree("orders").unwrap();
+            for i in 0..3000u32 {
+                let value = orders
+                    .get(format!("order{i:06}").as_bytes())
+                    .unwrap()
+                    .unwrap();
+                assert_eq!(
+                    value,
+                    format!("status=shipped;carrier=post;{i:0>80}").into_bytes()
+                );
+            }
+            for i in (0..3000u32).step_by(2) {
+                orders.delete(format!("order{i:06}").into_bytes());
+            }
+            db.set_compression(compression).unwrap();
+            db.compact().unwrap();
+            let orders = db.open_tree("orders").unwrap();
+            assert_eq!(orders.get(b"order000000").unwrap(), None);
+            assert!(orders.get(b"order002999").unwrap().is_some());
+
+            // pages of a block or less never save one, encrypted pages do not compress
+            let small = TempFile::new("small");
+            let mut db = KV::open(&small.0).unwrap();
+            let block = fs::metadata(&small.0).unwrap().blksize();
+            if block >= BTREE_PAGE_SIZE as u64 {
+                assert!(db.set_compression(compression).is_err());
+            }
+            db.set_compression(Compression::None).unwrap();
+            // the smallest page size larger than a block is taken and saves blocks
+            if let Some(&size) = PAGE_SIZES.iter().find(|&&size| size as u64 > block) {
+                let file = TempFile::new("smallest");
+                let mut db = KV::open_with_page_size(&file.0, size).unwrap();
+                db.set_compression(compression).unwrap();
+                fill(&mut db);
+                drop(db);
+                let plain = TempFile::new("smallest-plain");
+                let mut db = KV::open_with_page_size(&plain.0, size).unwrap();
+                fill(&mut db);
+                drop(db);
+                let blocks = fs::metadata(&file.0).unwrap().blocks();
+                assert!(blocks < fs::metadata(&plain.0).unwrap().blocks(), "{size}");
+                let mut db = KV::open(&file.0).unwrap();
+                let orders = db.open_tree("orders").unwrap();
+                assert_eq!(
+                    orders.scan(Bound::Unbounded, Bound::Unbounded).count(),
+                    3000
+                );
+            }
+            #[cfg(feature = "encryption")]
+            {
+                let cipher = crate::crypt::Cipher::Aes256Gcm.with_key(&[7; 32]);
+                let sealed = TempFile::new("sealed");
+                let mut db = KV::open_with(&sealed.0, 16384, Some(cipher)).unwrap();
+                assert!(db.set_compression(compression).is_err());
+            }
+        }
+    }
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn encrypted_pages() {
+        use crate::{crypt::Cipher, pager::page_number};
+        let key = [7u8; 32];
+        let page = BTREE_PAGE_SIZE as u64;
+        let fill = |db: &mut KV, round: u32| {
+            let accounts = match round {
+                0 => db.create_tree("accounts").unwrap(),
+                _ => db.open_tree("accounts").unwrap(),
+            };
+            for i in 0..2000u32 {
+                let value = format!("secret-balance-{i}-{round}").into_bytes();
+                accounts.insert(format!("account{i:05}").into_bytes(), value);
+            }
+            db.commit().unwrap();
+        };
+        for cipher in [Cipher::Aes256Gcm, Cipher::ChaCha20Poly1305] {
+            // backups are copied as they are and restored with the key
+            let source = TempFile::new("source");
+            let mut db =
+                KV::open_with(&source.0, BTREE_PAGE_SIZE, Some(cipher.with_key(&key))).unwrap();
+            fill(&mut db, 0);
+            let full = TempFile::new("full");
+            db.backup_to(&full.0).unwrap();
+            let since = db.txid();
+            let accounts = db.open_tree("accounts").unwrap();
+            accounts.insert(b"account00000".to_vec(), b"moved".to_vec());
+            db.commit().unwrap();
+            let increment = TempFile::new("incr");
+            db.backup_incremental(since, &increment.0).unwrap();
+            let restored = TempFile::new("restored");
+            let with_key = Some(cipher.with_key(&key));
+            restore_backup(&restored.0, &full.0, &[&increment.0], with_key.clone()).unwrap();
+            let mut copy = KV::open_with(&restored.0, BTREE_PAGE_SIZE, with_key).unwrap();
+            let accounts = copy.open_tree("accounts").unwrap();
+            assert_eq!(accounts.get(b"account00000").unwrap().unwrap(), b"moved");
+
+            let file = TempFile::new("encrypted");
+            let open = || KV::open_with(&file.0, BTREE_PAGE_SIZE, Some(cipher.with_key(&key)));
+            let mut db = open().unwrap();
+            fill(&mut db, 0);
+            db.compact().unwrap();
+            drop(db);
+            let old = fs::read(&file.0).unwrap();
+            assert!(!old.windows(14).any(|window| window == b"secret-balance"));
+
+            // only the same cipher and key open it
+            assert!(KV::open(&file.0).is_err());
+            assert!(
+                KV::open_with(&file.0, BTREE_PAGE_SIZE, Some(cipher.with_key(&[8; 32]))).is_err()
+            );
+            let other = match cipher {
+                Cipher::Aes256Gcm => Cipher::ChaCha20Poly1305,
+                Cipher::ChaCha20Poly1305 => Cipher::Aes256Gcm,
+            };
+            assert!(KV::open_with(&file.0, BTREE_PAGE_SIZE, Some(other.with_key(&key))).is_err());
+
+            // the second round takes the pages the first one freed
+            let mut db = open().unwrap();
+            fill(&mut db, 1);
+            fill(&mut db, 2);
+            drop(db);
+            let mut db = open().unwrap();
+            let accounts = db.open_tree("accounts").unwrap();
+            for i in 0..2000u32 {
+                let value = accounts
+                    .get(format!("account{i:05}").as_bytes())
+                    .unwrap()
+                    .unwrap();
+                assert_eq!(value, format!("secret-balance-{i}-2").into_bytes());
+            }
+
+            drop(db);
+
+            // the root page moved to another slot, or put back as what its slot held
+            // before, does not open
+            let new = fs::read(&file.0).unwrap();
+            let root = page_number(u64::from_le_bytes(new[16..24].try_into().unwrap()));
+            assert!((root + 1) * page <= old.len() as u64);
+            let slot = |pointer: u64| (pointer * page) as usize..((pointer + 1) * page) as usize;
+            let mut swapped = new.clone();
+            let other = if root == 1 { 2 } else { 1 };
+            swapped[slot(other)].copy_from_slice(&new[slot(root)]);
+            swapped[slot(root)].copy_from_slice(&new[slot(other)]);
+            let mut replayed = new.clone();
+            replayed[slot(root)].copy_from_slice(&old[slot(root)]);
+            for bytes in [swapped, replayed] {
+                fs::write(&file.0, &bytes).unwrap();
+                let read = open().and_then(|mut db| db.open_tree("accounts").map(|_| ()));
+                match read {
+                    Err(KVError::Io(err)) => {
+                        assert!(err.to_string().contains("fails authentication"), "{err}")
+                    }
+                    _ => panic!("a moved page was read"),
+                }
+            }
+        }
+    }
+}
@@ -1 +1,13 @@
 pub mod B_tree;
+pub mod codec;
+pub mod comparator;
+#[cfg(feature = "encryption")]
+pub mod crypt;
+pub mod dump;
+pub mod kv;
+pub mod pager;
+pub mod records;
+pub mod resp;
+pub mod sql;
+pub mod table;
+pub mod typed;
@@ -1,39 +1,232 @@
-use rustdb::B_tree::{get, new, node_append_range, BNode, BTree, BNODE_LEAF};
-
-fn main() {
-    // let mut tree = BTree::new();
-    // let key = vec![1, 2, 3];
-    // let value = vec![4, 5, 6];
-    // tree.insert(key.clone(), value.clone());
-    // let new_key = vec![3, 53, 2];
-    // let new_val = vec![242, 55, 2];
-    // tree.insert(new_key.clone(), new_val.clone());
-    // let root_node = get(tree.root);
-
-    // let nkey = vec![1; 999];
-    // let nval = vec![2; 2999];
-    // tree.insert(nkey.clone(), nval.clone());
-    // let root_node = get(tree.root);
-
-    // let index = root_node.lookup_key(&nkey);
-    // let new_key = vec![1; 100];
-    // let new_val = vec![32; 232];
-    // tree.insert(new_key.clone(), new_val.clone());
-    // let _root_node = get(tree.root);
-    // let (found, index, node) = tree.search(&vec![3, 53, 2]);
-    // assert_eq!(vec![3, 53, 2], node.get_key(index));
-    // let new_key = vec![2; 45];
-    // let new_val = vec![3; 49];
-    // tree.insert(new_key.clone(), new_val.clone());
-    // println!("safe_till_here");
-    // tree.delete(nkey.clone());
-    // let (found, index, node) = tree.search(&nkey);
-    // println!("{:?}", node.data);
-
-    // assert_ne!(node.get_key(index), nkey);
-    // let done = tree.delete(new_key.clone());
-    // println!("{done}");
-    // let root_node = get(tree.root);
-    // // println!("{:?}", root_node.data);
-    // let (found, index, node) = tree.search(&new_key);
+#![allow(clippy::needless_return)]
+use std::{
+    env, fs,
+    io::{self, BufReader, BufWriter},
+    ops::Bound,
+    path::Path,
+    process::ExitCode,
+};
+
+use rustdb::{
+    dump::{dump, restore, Encoding},
+    kv::{restore_backup, KVError, KV},
+    records::{export, import, Format, RecordOptions, ValueEncoding},
+    resp::Server,
+};
+
+const USAGE: &str = "usage:
+    rustdb backup [--since <txid>] <database> <backup file>
+    rustdb restore-backup <database> <full backup> [<incremental backup>...]
+    rustdb dump [--base64] <database> [<dump file>]
+    rustdb restore <new database> [<dump file>]
+    rustdb import <csv|jsonl> <database> <tree> --key <name> [--value <name>] [<file>]
+    rustdb export <csv|jsonl> <database> <tree> --key <name> [--value <name>]
+                  [--from <key>] [--to <key>] [<file>]
+    rustdb serve --resp <address> <database>
+";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let result = match args.as_slice() {
+        ["backup", db, to] => backup(Path::new(db), Path::new(to), None),
+        ["backup", "--since", txid, db, to] => match txid.parse() {
+            Ok(txid) => backup(Path::new(db), Path::new(to), Some(txid)),
+            Err(_) => Err(io::Error::other(format!("bad txid {txid}")).into()),
+        },
+        ["restore-backup", db, full, increments @ ..] => {
+            let increments: Vec<&Path> = increments.iter().map(Path::new).collect();
+            restore_backup(Path::new(db), Path::new(full), &increments, None)
+        }
+        ["dump", "--base64", db, to @ ..] if to.len() <= 1 => {
+            dump_to(Path::new(db), to.first(), Encoding::Base64)
+        }
+        ["dump", db, to @ ..] if to.len() <= 1 => dump_to(Path::new(db), to.first(), Encoding::Hex),
+        ["restore", db, from @ ..] if from.len() <= 1 => restore_from(Path::new(db), from.first()),
+        ["serve", "--resp", addr, db] => serve(Path::new(db), addr),
+        [command @ ("import" | "export"), format, db, tree, rest @ ..] => {
+            match (Format::by_name(format), Flags::parse(rest)) {
+                (Some(format), Some(flags)) if flags.key.is_some() && flags.files.len() <= 1 => {
+                    if *command == "import" {
+                        import_from(Path::new(db), tree, format, flags)
+                    } else {
+                        export_to(Path::new(db), tree, format, flags)
+                    }
+                }
+                _ => {
+                    eprint!("{USAGE}");
+                    return ExitCode::from(2);
+                }
+            }
+        }
+        _ => {
+            eprint!("{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+    if let Err(err) = result {
+        eprintln!("rustdb: {err}");
+        return ExitCode::FAILURE;
+    }
+    return ExitCode::SUCCESS;
+}
+
+// an existing database, not a new empty one where there is none
+fn open(path: &Path) -> Result<KV, KVError> {
+    if !path.is_file() {
+        let message = format!("no database at {}", path.display());
+        return Err(io::Error::new(io::ErrorKind::NotFound, message).into());
+    }
+    return KV::open(path);
+}
+
+// a process writing the database does not know about the snapshot taken here and would
+// reuse its pages from its second commit on. A commit locks the file exclusively, so the
+// shared lock held here through the copy makes writers wait until it is done. Prints the
+// txid of the backup, where an incremental one taken later starts from.
+fn backup(db: &Path, to: &Path, since: Option<u64>) -> Result<(), KVError> {
+    // locked before the database is opened, which reads the meta page of the last commit
+    let lock = fs::File::open(db);
+    if let Ok(lock) = &lock {
+        lock.lock_shared()?;
+    }
+    let db = open(db)?;
+    let snapshot = db.snapshot();
+    match since {
+        Some(since) => snapshot.backup_incremental(since, to)?,
+        None => snapshot.backup_to(to)?,
+    }
+    println!("txid {}", snapshot.meta.txid);
+    return Ok(());
+}
+
+// to stdout without a file
+fn dump_to(db: &Path, to: Option<&&str>, encoding: Encoding) -> Result<(), KVError> {
+    let mut db = open(db)?;
+    match to {
+        Some(to) => dump(
+            &mut db,
+            &mut BufWriter::new(fs::File::create(to)?),
+            encoding,
+        ),
+        None => dump(&mut db, &mut BufWriter::new(io::stdout().lock()), encoding),
+    }
+}
+
+// from stdin without a file. The database must be new, it is removed again when the dump
+// does not restore.
+fn restore_from(db: &Path, from: Option<&&str>) -> Result<(), KVError> {
+    if db.exists() {
+        let message = format!("{} already exists", db.display());
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, message).into());
+    }
+    let result = KV::open(db).and_then(|mut kv| match from {
+        Some(from) => restore(&mut kv, &mut BufReader::new(fs::File::open(from)?)),
+        None => restore(&mut kv, &mut io::stdin().lock()),
+    });
+    if result.is_err() {
+        let _ = fs::remove_file(db);
+    }
+    return result;
+}
+
+// the options of import and export, in any order before or after the file
+#[derive(Default)]
+struct Flags<'a> {
+    key: Option<&'a str>,
+    value: Option<&'a str>,
+    from: Option<&'a str>,
+    to: Option<&'a str>,
+    files: Vec<&'a str>,
+}
+impl<'a> Flags<'a> {
+    fn parse(mut args: &[&'a str]) -> Option<Flags<'a>> {
+        let mut flags = Flags::default();
+        while let [arg, rest @ ..] = args {
+            let slot = match *arg {
+                "--key" => &mut flags.key,
+                "--value" => &mut flags.value,
+                "--from" => &mut flags.from,
+                "--to" => &mut flags.to,
+                file if !file.starts_with("--") => {
+                    flags.files.push(file);
+                    args = rest;
+                    continue;
+                }
+                _ => return None,
+            };
+            let [value, rest @ ..] = rest else {
+                return None;
+            };
+            *slot = Some(value);
+            args = rest;
+        }
+        return Some(flags);
+    }
+    fn options(&self, format: Format) -> RecordOptions {
+        return RecordOptions {
+            format,
+            key: self.key.unwrap_or_default().to_string(),
+            value: match self.value {
+                Some(name) => ValueEncoding::Field(name.to_string()),
+                None => ValueEncoding::Json,
+            },
+        };
+    }
+}
+
+// from stdin without a file, into the tree, which is created when there is none yet
+fn import_from(db: &Path, name: &str, format: Format, flags: Flags) -> Result<(), KVError> {
+    let mut db = open(db)?;
+    let tree = match db.tree_names()?.iter().any(|tree| tree == name) {
+        true => db.open_tree(name)?,
+        false => db.create_tree(name)?,
+    };
+    let options = flags.options(format);
+    let result = match flags.files.first() {
+        Some(from) => import(tree, &mut BufReader::new(fs::File::open(from)?), &options),
+        None => import(tree, &mut io::stdin().lock(), &options),
+    };
+    match result {
+        Ok(count) => {
+            db.commit()?;
+            println!("{count} records");
+            return Ok(());
+        }
+        Err(err) => {
+            db.rollback();
+            return Err(err.into());
+        }
+    }
+}
+
+// to stdout without a file, the keys from --from on and before --to
+fn export_to(db: &Path, name: &str, format: Format, flags: Flags) -> Result<(), KVError> {
+    let mut db = open(db)?;
+    let tree = db.open_tree(name)?;
+    let options = flags.options(format);
+    let start = flags
+        .from
+        .map_or(Bound::Unbounded, |key| Bound::Included(key.as_bytes()));
+    let end = flags
+        .to
+        .map_or(Bound::Unbounded, |key| Bound::Excluded(key.as_bytes()));
+    match flags.files.first() {
+        Some(to) => {
+            let mut out = BufWriter::new(fs::File::create(to)?);
+            export(tree, &mut out, &options, start, end)?;
+        }
+        None => {
+            export(tree, &mut io::stdout().lock(), &options, start, end)?;
+        }
+    }
+    return Ok(());
+}
+
+// until the process is stopped, every write is committed before it is answered
+fn serve(db: &Path, addr: &str) -> Result<(), KVError> {
+    let server = Server::bind(open(db)?, addr)?;
+    println!("listening on {}", server.local_addr()?);
+    server.run()?;
+    return Ok(());
 }
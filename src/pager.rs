@@ -0,0 +1,765 @@
+#![allow(clippy::needless_return)]
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::Path,
+    sync::Arc,
+};
+
+use crate::B_tree::{BNode, BTREE_PAGE_SIZE, PAGE_SIZES};
+
+pub const DB_SIG: &[u8; 16] = b"rustdb file v001";
+// page numbers and a count at the start of every free list page, then the free pages
+const FREE_LIST_HEADER: usize = 16;
+// set in the first two bytes of a page written compressed, next to the codec. A node
+// page has its btype there, which never has it set.
+const PAGE_COMPRESSED: u16 = 0x8000;
+#[cfg(feature = "lz4")]
+const CODEC_LZ4: u16 = 1;
+#[cfg(feature = "zstd")]
+const CODEC_ZSTD: u16 = 2;
+// |flag and codec 2B|compressed length 4B|compressed node|
+#[cfg(any(feature = "lz4", feature = "zstd"))]
+const COMPRESSED_HEADER: usize = 6;
+
+// how leaf pages are written to the file, the other pages are always written as they are.
+// A compressed page still takes its whole slot in the file, the blocks of the slot past
+// the compressed bytes are punched out of the file, so compression only saves space
+// with pages larger than a block of the file system.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Compression {
+    #[default]
+    None,
+    #[cfg(feature = "lz4")]
+    Lz4,
+    // with the compression level
+    #[cfg(feature = "zstd")]
+    Zstd(i32),
+}
+
+pub const CIPHER_AES_256_GCM: u64 = 1;
+pub const CIPHER_CHACHA20_POLY1305: u64 = 2;
+// |nonce 12B|tag 16B| at the end of every page of an encrypted file, after the node
+const SEAL_SIZE: usize = 28;
+// where the meta page of an encrypted file keeps its nonce and tag, the bytes before are
+// what they authenticate
+const META_SEAL: usize = 72;
+// pointers to the pages of an encrypted file carry the low bits of the counter the page
+// was written with above the page number, so a page put back as an older version of
+// itself is caught when it is read
+const PAGE_NUMBER_BITS: u32 = 40;
+
+// the page a pointer handed out by the pager is to
+pub fn page_number(pointer: u64) -> u64 {
+    return pointer & ((1 << PAGE_NUMBER_BITS) - 1);
+}
+
+// encrypts pages on their way to the file and back, see crypt.rs. A page is sealed with
+// its page number and a write counter as associated data and the counter in the nonce,
+// so a page moved to another slot or replaced by an older version of itself fails to
+// open.
+pub trait PageCipher {
+    // kept in the meta page, a file is only opened with the cipher it was created with
+    fn id(&self) -> u64;
+    // encrypts `data` in place and returns the tag
+    fn seal(&self, nonce: &[u8; 12], aad: &[u8], data: &mut [u8]) -> io::Result<[u8; 16]>;
+    // decrypts `data` in place, false when it is not what was sealed
+    fn open(&self, nonce: &[u8; 12], aad: &[u8], data: &mut [u8], tag: &[u8; 16]) -> bool;
+}
+
+// the first page of the file. Writing it is what makes a commit visible: the pages it
+// points to are all written and synced before it.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Meta {
+    // root of the catalog tree, 0 when it is empty
+    pub root: u64,
+    // pages in use, the meta page included
+    pub npages: u64,
+    // first page of the free list, 0 when there are no free pages
+    pub free_head: u64,
+    // fixed when the file is created
+    pub page_size: u64,
+    // the PageCipher id of an encrypted file, 0 for a plain one
+    pub cipher: u64,
+    // write counter of an encrypted file as of the commit
+    pub counter: u64,
+    // counts the commits, the nodes written by one carry its txid
+    pub txid: u64,
+}
+impl Meta {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut page = vec![0; self.page_size as usize];
+        page[..16].copy_from_slice(DB_SIG);
+        page[16..24].copy_from_slice(&self.root.to_le_bytes());
+        page[24..32].copy_from_slice(&self.npages.to_le_bytes());
+        page[32..40].copy_from_slice(&self.free_head.to_le_bytes());
+        page[40..48].copy_from_slice(&self.page_size.to_le_bytes());
+        page[48..56].copy_from_slice(&self.cipher.to_le_bytes());
+        page[56..64].copy_from_slice(&self.counter.to_le_bytes());
+        page[64..72].copy_from_slice(&self.txid.to_le_bytes());
+        return page;
+    }
+    pub fn decode(page: &[u8]) -> Option<Meta> {
+        if page.len() < 48 || &page[..16] != DB_SIG {
+            return None;
+        }
+        let read = |at: usize| u64::from_le_bytes(page[at..at + 8].try_into().unwrap());
+        // files from before the page size was stored have 4 KiB pages
+        let page_size = match read(40) {
+            0 => BTREE_PAGE_SIZE as u64,
+            size => size,
+        };
+        if !PAGE_SIZES.contains(&(page_size as usize)) {
+            return None;
+        }
+        return Some(Meta {
+            root: read(16),
+            npages: read(24),
+            free_head: read(32),
+            page_size,
+            cipher: read(48),
+            counter: read(56),
+            txid: read(64),
+        });
+    }
+}
+
+fn corrupt(message: &str) -> io::Error {
+    return io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+}
+
+// reads and writes at an offset through the cursor of the file, the same on every
+// platform. The pager is behind a mutex, nothing moves the cursor in between.
+fn read_exact_at(mut file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    file.seek(SeekFrom::Start(offset))?;
+    return file.read_exact(buf);
+}
+fn write_all_at(mut file: &File, buf: &[u8], offset: u64) -> io::Result<()> {
+    file.seek(SeekFrom::Start(offset))?;
+    return file.write_all(buf);
+}
+
+// pages of a database file, addressed by page number. Pages are never written in place:
+// a changed node goes to a fresh page and the old one is freed, so until the meta page
+// is rewritten by `commit` the file still holds the last committed state untouched.
+pub struct Pager {
+    file: File,
+    pub page_size: usize,
+    // as of the last commit
+    pub meta: Meta,
+    // including the pages appended since the last commit
+    npages: u64,
+    // pages written since the last commit, not in the file yet
+    updates: HashMap<u64, Vec<u8>>,
+    // pages taken since the last commit, nothing committed refers to them
+    allocated: HashSet<u64>,
+    // pages nothing committed refers to, free to take. Kept highest first after a
+    // commit, so the pages near the start of the file are taken first.
+    free: Vec<u64>,
+    // freed since the last commit but still part of the committed state
+    freed: Vec<u64>,
+    // `free` as of the last commit
+    committed_free: Vec<u64>,
+    // pages holding the committed free list
+    list_pages: Vec<u64>,
+    // for the leaf pages written from now on, pages already written stay as they are.
+    // Not for an encrypted file, see `set_compression`.
+    pub compression: Compression,
+    // set for an encrypted file
+    cipher: Option<Arc<dyn PageCipher + Send + Sync>>,
+    // pages written to an encrypted file, never goes back
+    counter: u64,
+    // drawn on opening, in the nonce next to the counter. A crash loses the counters used
+    // since the last commit, the salt keeps them from giving the same nonce again.
+    salt: [u8; 4],
+    // counters of the pages written since the last commit
+    counters: HashMap<u64, u64>,
+    // snapshots being read, see `pin`
+    pins: usize,
+    // pages freed by the commits since the first pin, free in the file but not taken
+    // until the last pin is gone
+    held: Vec<u64>,
+}
+impl Pager {
+    // opens the file, creating an empty database with pages of `page_size` when it is
+    // new. An existing file keeps the page size it was created with.
+    pub fn open(path: &Path, page_size: usize) -> io::Result<Pager> {
+        return Pager::open_with(path, page_size, None);
+    }
+    // a file created with a cipher is encrypted for good and opens only with the same
+    // cipher and key
+    pub fn open_with(
+        path: &Path,
+        page_size: usize,
+        cipher: Option<Arc<dyn PageCipher + Send + Sync>>,
+    ) -> io::Result<Pager> {
+        if !PAGE_SIZES.contains(&page_size) {
+            let message = format!("page size {page_size} is not one of {PAGE_SIZES:?}");
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+        }
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let mut pager = Pager {
+            file,
+            page_size,
+            meta: Meta::default(),
+            npages: 1,
+            updates: HashMap::new(),
+            allocated: HashSet::new(),
+            free: vec![],
+            freed: vec![],
+            committed_free: vec![],
+            list_pages: vec![],
+            compression: Compression::None,
+            cipher,
+            counter: 0,
+            salt: [0; 4],
+            counters: HashMap::new(),
+            pins: 0,
+            held: vec![],
+        };
+        if pager.cipher.is_some() {
+            getrandom::getrandom(&mut pager.salt)?;
+            pager.counter = 1;
+        }
+        if pager.file.metadata()?.len() == 0 {
+            pager.meta.npages = 1;
+            pager.meta.page_size = page_size as u64;
+            pager.meta.cipher = pager.cipher.as_ref().map_or(0, |cipher| cipher.id());
+            pager.meta.counter = pager.counter;
+            write_all_at(&pager.file, &pager.meta_page(&pager.meta)?, 0)?;
+            pager.file.sync_all()?;
+            return Ok(pager);
+        }
+        // the meta page is at least as large as the smallest page size
+        let mut page = vec![0; PAGE_SIZES[0]];
+        read_exact_at(&pager.file, &mut page, 0)?;
+        pager.meta = Meta::decode(&page).ok_or_else(|| corrupt("not a database file"))?;
+        let cipher = pager.cipher.as_ref().map_or(0, |cipher| cipher.id());
+        if pager.meta.cipher != cipher {
+            let message = match pager.meta.cipher {
+                0 => "the file is not encrypted".to_string(),
+                id => format!("the file is encrypted with cipher {id}"),
+            };
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+        }
+        if let Some(cipher) = &pager.cipher {
+            let nonce: [u8; 12] = page[META_SEAL..META_SEAL + 12].try_into().unwrap();
+            let tag: [u8; 16] = page[META_SEAL + 12..META_SEAL + SEAL_SIZE]
+                .try_into()
+                .unwrap();
+            if !cipher.open(&nonce, &page[..META_SEAL], &mut [], &tag) {
+                let message = "wrong key, or the meta page is corrupt";
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+            }
+        }
+        pager.page_size = pager.meta.page_size as usize;
+        pager.npages = pager.meta.npages;
+        pager.counter = pager.meta.counter;
+        let mut next = pager.meta.free_head;
+        while next != 0 {
+            let pointer = next;
+            let next_page = page_number(pointer);
+            if next_page >= pager.npages || pager.list_pages.contains(&next_page) {
+                return Err(corrupt("bad free list"));
+            }
+            pager.list_pages.push(next_page);
+            let page = pager.read_stored(pointer)?;
+            let read = |at: usize| u64::from_le_bytes(page[at..at + 8].try_into().unwrap());
+            let count = read(8) as usize;
+            if count > pager.free_list_cap() {
+                return Err(corrupt("bad free list"));
+            }
+            for i in 0..count {
+                pager.free.push(read(FREE_LIST_HEADER + 8 * i));
+            }
+            next = read(0);
+        }
+        pager.free.sort_unstable_by(|a, b| b.cmp(a));
+        pager.committed_free = pager.free.clone();
+        return Ok(pager);
+    }
+    // free page numbers one free list page holds
+    fn free_list_cap(&self) -> usize {
+        return (self.node_size() - FREE_LIST_HEADER) / 8;
+    }
+    // room for a node in a page, less than the page size in an encrypted file
+    pub fn node_size(&self) -> usize {
+        return match self.cipher {
+            Some(_) => self.page_size - SEAL_SIZE,
+            None => self.page_size,
+        };
+    }
+    // refused where compressing would never save a block: pages no larger than a block of
+    // the file system, as the default 4 KiB pages on the usual 4 KiB blocks are, or a file
+    // system blocks can not be punched out of. So on 4 KiB blocks it takes pages of 8 KiB
+    // or more. Refused for an encrypted file as well, its pages do not compress. Whether
+    // the file system punches holes shows at the first commit, compression is turned off
+    // there if it does not.
+    pub fn set_compression(&mut self, compression: Compression) -> io::Result<()> {
+        #[cfg(any(feature = "lz4", feature = "zstd"))]
+        if compression != Compression::None {
+            let refuse = |message: String| {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+            };
+            if self.cipher.is_some() {
+                return refuse("an encrypted file is not compressed".to_string());
+            }
+            match self.hole_block() {
+                None => return refuse("the file system can not punch holes".to_string()),
+                Some(block) if block >= self.page_size => {
+                    let size = self.page_size;
+                    return refuse(format!(
+                        "pages of {size} bytes on blocks of {block} do not compress"
+                    ));
+                }
+                Some(_) => {}
+            }
+        }
+        self.compression = compression;
+        return Ok(());
+    }
+    pub fn cipher(&self) -> Option<Arc<dyn PageCipher + Send + Sync>> {
+        return self.cipher.clone();
+    }
+    pub fn page_get(&self, pointer: u64) -> io::Result<BNode> {
+        let page = page_number(pointer);
+        if page == 0 || page >= self.npages {
+            return Err(corrupt(&format!("pointer to page {page} out of the file")));
+        }
+        if let Some(data) = self.updates.get(&page) {
+            return Ok(BNode { data: data.clone() });
+        }
+        let data = self.read_page(pointer)?;
+        return Ok(BNode { data });
+    }
+    // the node in a page as it was written, decrypted or decompressed
+    fn read_page(&self, pointer: u64) -> io::Result<Vec<u8>> {
+        let data = self.read_stored(pointer)?;
+        if self.cipher.is_none() && u16::from_ne_bytes([data[0], data[1]]) & PAGE_COMPRESSED != 0 {
+            return self.decompress(&data);
+        }
+        return Ok(data);
+    }
+    // a page as commit wrote it, decrypted but not decompressed. Free list pages are read
+    // this way, they start with the next pointer, which may look like a compressed page.
+    fn read_stored(&self, pointer: u64) -> io::Result<Vec<u8>> {
+        let page = page_number(pointer);
+        let mut data = vec![0; self.page_size];
+        read_exact_at(&self.file, &mut data, page * self.page_size as u64)?;
+        if let Some(cipher) = &self.cipher {
+            let at = self.node_size();
+            let nonce: [u8; 12] = data[at..at + 12].try_into().unwrap();
+            let tag: [u8; 16] = data[at + 12..].try_into().unwrap();
+            let counter = u64::from_le_bytes(nonce[..8].try_into().unwrap());
+            data.truncate(at);
+            let stale = counter << PAGE_NUMBER_BITS != pointer - page;
+            if stale || !cipher.open(&nonce, &seal_aad(page, counter), &mut data, &tag) {
+                return Err(corrupt(&format!("page {page} fails authentication")));
+            }
+        }
+        return Ok(data);
+    }
+    // the pointer to hand out for `page`, written to the file on the next commit
+    fn stamp(&mut self, page: u64) -> u64 {
+        if self.cipher.is_none() {
+            return page;
+        }
+        self.counter += 1;
+        self.counters.insert(page, self.counter);
+        return page | self.counter << PAGE_NUMBER_BITS;
+    }
+    // the page encrypted, as it goes into the file
+    fn seal(&self, cipher: &dyn PageCipher, page: u64, data: &[u8]) -> io::Result<Vec<u8>> {
+        let Some(&counter) = self.counters.get(&page) else {
+            return Err(corrupt(&format!("page {page} has no write counter")));
+        };
+        let mut nonce = [0; 12];
+        nonce[..8].copy_from_slice(&counter.to_le_bytes());
+        nonce[8..].copy_from_slice(&self.salt);
+        let mut sealed = data.to_vec();
+        let tag = cipher.seal(&nonce, &seal_aad(page, counter), &mut sealed)?;
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&tag);
+        return Ok(sealed);
+    }
+    // the meta page as it goes into the file, authenticated in an encrypted one. Its
+    // nonce has the counter of the meta, which has to be a fresh one.
+    fn meta_page(&self, meta: &Meta) -> io::Result<Vec<u8>> {
+        let mut page = meta.encode();
+        if let Some(cipher) = &self.cipher {
+            let mut nonce = [0; 12];
+            nonce[..8].copy_from_slice(&meta.counter.to_le_bytes());
+            nonce[8..].copy_from_slice(&self.salt);
+            let tag = cipher.seal(&nonce, &page[..META_SEAL], &mut [])?;
+            page[META_SEAL..META_SEAL + 12].copy_from_slice(&nonce);
+            page[META_SEAL + 12..META_SEAL + SEAL_SIZE].copy_from_slice(&tag);
+        }
+        return Ok(page);
+    }
+    // the leaf page as it goes into the file when compressing it saves a block of the
+    // file system
+    #[cfg(any(feature = "lz4", feature = "zstd"))]
+    fn compress(&self, data: &[u8]) -> Option<Vec<u8>> {
+        use crate::B_tree::BNODE_LEAF;
+        if u16::from_ne_bytes([data[0], data[1]]) != BNODE_LEAF {
+            return None;
+        }
+        let node = BNode {
+            data: data.to_vec(),
+        };
+        let data = &data[..node.size()];
+        let (codec, bytes) = match self.compression {
+            Compression::None => return None,
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => (CODEC_LZ4, lz4_flex::block::compress_prepend_size(data)),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd(level) => (CODEC_ZSTD, zstd::bulk::compress(data, level).ok()?),
+        };
+        let block = self.hole_block()?;
+        let len = COMPRESSED_HEADER + bytes.len();
+        if len.div_ceil(block) * block >= self.page_size {
+            return None;
+        }
+        let mut page = Vec::with_capacity(len);
+        page.extend_from_slice(&(PAGE_COMPRESSED | codec).to_ne_bytes());
+        page.extend_from_slice(&(bytes.len() as u32).to_ne_bytes());
+        page.extend_from_slice(&bytes);
+        return Some(page);
+    }
+    #[cfg(not(any(feature = "lz4", feature = "zstd")))]
+    fn compress(&self, _data: &[u8]) -> Option<Vec<u8>> {
+        return None;
+    }
+    #[cfg(any(feature = "lz4", feature = "zstd"))]
+    fn decompress(&self, page: &[u8]) -> io::Result<Vec<u8>> {
+        let codec = u16::from_ne_bytes([page[0], page[1]]) & !PAGE_COMPRESSED;
+        let len = u32::from_ne_bytes(page[2..COMPRESSED_HEADER].try_into().unwrap()) as usize;
+        let Some(bytes) = page.get(COMPRESSED_HEADER..COMPRESSED_HEADER.saturating_add(len)) else {
+            return Err(corrupt("bad compressed page"));
+        };
+        let data = match codec {
+            #[cfg(feature = "lz4")]
+            CODEC_LZ4 => {
+                // the size in front is allocated, it has to fit a page first
+                let size = bytes.get(..4).map(|size| {
+                    return u32::from_le_bytes(size.try_into().unwrap()) as usize;
+                });
+                match size.is_some_and(|size| size <= self.page_size) {
+                    true => lz4_flex::block::decompress_size_prepended(bytes).ok(),
+                    false => None,
+                }
+            }
+            #[cfg(feature = "zstd")]
+            CODEC_ZSTD => zstd::bulk::decompress(bytes, self.page_size).ok(),
+            _ => {
+                let message = format!("page compressed with codec {codec}, not in this build");
+                return Err(io::Error::new(io::ErrorKind::Unsupported, message));
+            }
+        };
+        let Some(mut data) = data.filter(|data| data.len() <= self.page_size) else {
+            return Err(corrupt("bad compressed page"));
+        };
+        data.resize(self.page_size, 0);
+        return Ok(data);
+    }
+    #[cfg(not(any(feature = "lz4", feature = "zstd")))]
+    fn decompress(&self, _page: &[u8]) -> io::Result<Vec<u8>> {
+        let message = "compressed page, this build has neither the lz4 nor the zstd feature";
+        return Err(io::Error::new(io::ErrorKind::Unsupported, message));
+    }
+    // the block size of the file system where blocks can be punched out of the file,
+    // compression saves no space anywhere else
+    #[cfg(any(feature = "lz4", feature = "zstd"))]
+    fn hole_block(&self) -> Option<usize> {
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::unix::fs::MetadataExt;
+            return self.file.metadata().ok().map(|m| m.blksize() as usize);
+        }
+        #[cfg(not(target_os = "linux"))]
+        return None;
+    }
+    // gives the blocks of the page past its first `len` bytes back to the file system.
+    // False if the file system can not punch holes, it keeps them then.
+    #[cfg(any(feature = "lz4", feature = "zstd"))]
+    fn punch_hole(&self, pointer: u64, len: usize) -> io::Result<bool> {
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::fd::AsRawFd;
+            let Some(block) = self.hole_block() else {
+                return Ok(false);
+            };
+            let start = len.div_ceil(block) * block;
+            let offset = pointer * self.page_size as u64 + start as u64;
+            let done = unsafe {
+                libc::fallocate(
+                    self.file.as_raw_fd(),
+                    libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+                    offset as libc::off_t,
+                    (self.page_size - start) as libc::off_t,
+                )
+            };
+            if done != 0 {
+                let err = io::Error::last_os_error();
+                if err.raw_os_error() == Some(libc::EOPNOTSUPP) {
+                    return Ok(false);
+                }
+                return Err(err);
+            }
+            return Ok(true);
+        }
+        #[cfg(not(target_os = "linux"))]
+        return Ok(false);
+    }
+    #[cfg(not(any(feature = "lz4", feature = "zstd")))]
+    fn punch_hole(&self, _pointer: u64, _len: usize) -> io::Result<bool> {
+        return Ok(false);
+    }
+    pub fn page_new(&mut self, mut node: BNode) -> u64 {
+        assert!(node.size() <= self.node_size());
+        node.set_txid(self.meta.txid + 1);
+        let mut data = node.data;
+        data.resize(self.node_size(), 0);
+        let pointer = match self.free.pop() {
+            Some(pointer) => pointer,
+            None => {
+                self.npages += 1;
+                self.npages - 1
+            }
+        };
+        self.allocated.insert(pointer);
+        self.updates.insert(pointer, data);
+        return self.stamp(pointer);
+    }
+    pub fn page_del(&mut self, pointer: u64) {
+        let pointer = page_number(pointer);
+        self.counters.remove(&pointer);
+        self.updates.remove(&pointer);
+        if self.allocated.remove(&pointer) {
+            self.free.push(pointer);
+        } else {
+            self.freed.push(pointer);
+        }
+    }
+    // keeps the pages of the last commit as they are until `unpin`, so a snapshot of it
+    // can be read while commits go on
+    pub fn pin(&mut self) {
+        self.pins += 1;
+    }
+    pub fn unpin(&mut self) {
+        self.pins -= 1;
+        if self.pins == 0 {
+            self.free.extend(&self.held);
+            self.committed_free.append(&mut self.held);
+            self.free.sort_unstable_by(|a, b| b.cmp(a));
+            self.committed_free.sort_unstable_by(|a, b| b.cmp(a));
+        }
+    }
+    pub fn pins(&self) -> usize {
+        return self.pins;
+    }
+    // the free list for after the commit, in pages nothing committed refers to
+    fn write_free_list(&mut self) -> u64 {
+        let mut free = self.free.clone();
+        if self.pins > 0 {
+            self.held.extend(&self.freed);
+        } else {
+            free.extend(&self.freed);
+        }
+        free.extend(&self.list_pages);
+        let cap = self.free_list_cap();
+        let mut pages = vec![];
+        while pages.len() * cap < free.len() + self.held.len() {
+            // the committed free list names the pages in `self.free`, but their contents
+            // are not needed by anything
+            let pointer = match self.free.pop() {
+                Some(pointer) => {
+                    free.retain(|page| *page != pointer);
+                    pointer
+                }
+                None => {
+                    self.npages += 1;
+                    self.npages - 1
+                }
+            };
+            pages.push(pointer);
+        }
+        let pointers: Vec<u64> = pages.iter().map(|page| self.stamp(*page)).collect();
+        // a crash forgets the pins, the held pages are free after it
+        let listed: Vec<u64> = free.iter().chain(&self.held).copied().collect();
+        for (i, pointer) in pages.iter().enumerate() {
+            // the last pages may be left empty when taking them shortened the list
+            let begin = (i * cap).min(listed.len());
+            let chunk = &listed[begin..(begin + cap).min(listed.len())];
+            let mut page = vec![0; self.node_size()];
+            let next = pointers.get(i + 1).copied().unwrap_or(0);
+            page[..8].copy_from_slice(&next.to_le_bytes());
+            page[8..16].copy_from_slice(&(chunk.len() as u64).to_le_bytes());
+            for (j, free) in chunk.iter().enumerate() {
+                let at = FREE_LIST_HEADER + 8 * j;
+                page[at..at + 8].copy_from_slice(&free.to_le_bytes());
+            }
+            self.updates.insert(*pointer, page);
+        }
+        free.sort_unstable_by(|a, b| b.cmp(a));
+        self.free = free;
+        self.list_pages = pages;
+        return pointers.first().copied().unwrap_or(0);
+    }
+    // writes out the pages changed since the last commit, then points the meta page at
+    // `root`. A crash before the meta page is written leaves the last commit in place.
+    // The commit holds an exclusive lock on the file, so another process copying it under
+    // a shared lock sees no page change until it is done.
+    pub fn commit(&mut self, root: u64) -> io::Result<()> {
+        self.file.lock()?;
+        let result = self.write_commit(root);
+        self.file.unlock()?;
+        return result;
+    }
+    fn write_commit(&mut self, root: u64) -> io::Result<()> {
+        let free_head = self.write_free_list();
+        let mut pages: Vec<(&u64, &Vec<u8>)> = self.updates.iter().collect();
+        pages.sort_by_key(|(pointer, _)| **pointer);
+        let mut holes = true;
+        for (pointer, data) in pages {
+            let offset = pointer * self.page_size as u64;
+            if let Some(cipher) = &self.cipher {
+                let sealed = self.seal(cipher.as_ref(), *pointer, data)?;
+                write_all_at(&self.file, &sealed, offset)?;
+                continue;
+            }
+            // the free list is read back without decompressing it
+            let compressed = match self.list_pages.contains(pointer) || !holes {
+                true => None,
+                false => self.compress(data),
+            };
+            match compressed {
+                Some(page) => {
+                    write_all_at(&self.file, &page, offset)?;
+                    holes = self.punch_hole(*pointer, page.len())?;
+                }
+                None => write_all_at(&self.file, data, offset)?,
+            }
+        }
+        // a page compressed before the file system refused to punch stays readable, it
+        // just keeps its blocks
+        if !holes {
+            self.compression = Compression::None;
+        }
+        let len = self.npages * self.page_size as u64;
+        if self.file.metadata()?.len() < len {
+            self.file.set_len(len)?;
+        }
+        self.file.sync_all()?;
+        if self.cipher.is_some() {
+            self.counter += 1;
+        }
+        let meta = Meta {
+            root,
+            npages: self.npages,
+            free_head,
+            page_size: self.page_size as u64,
+            cipher: self.meta.cipher,
+            counter: self.counter,
+            txid: self.meta.txid + 1,
+        };
+        write_all_at(&self.file, &self.meta_page(&meta)?, 0)?;
+        self.file.sync_all()?;
+        self.meta = meta;
+        self.updates.clear();
+        self.counters.clear();
+        self.allocated.clear();
+        self.freed.clear();
+        self.committed_free = self.free.clone();
+        return Ok(());
+    }
+    // gives the free pages at the end of the file back to the file system. Only right
+    // after a commit, and the end can only move past the free list pages once they are
+    // free themselves, so this commits until the file stops shrinking.
+    pub fn truncate(&mut self, root: u64) -> io::Result<()> {
+        assert!(self.updates.is_empty() && self.freed.is_empty());
+        loop {
+            let before = self.npages;
+            let free: HashSet<u64> = self.free.iter().copied().collect();
+            while self.npages > 1 && free.contains(&(self.npages - 1)) {
+                self.npages -= 1;
+            }
+            if self.npages == before {
+                return Ok(());
+            }
+            let npages = self.npages;
+            self.free.retain(|pointer| *pointer < npages);
+            self.commit(root)?;
+            self.file.set_len(self.npages * self.page_size as u64)?;
+            self.file.sync_all()?;
+            if self.npages >= before {
+                return Ok(());
+            }
+        }
+    }
+    // a page as it is in the file, still encrypted or compressed
+    pub fn raw_page(&self, pointer: u64) -> io::Result<Vec<u8>> {
+        let mut data = vec![0; self.page_size];
+        read_exact_at(
+            &self.file,
+            &mut data,
+            page_number(pointer) * self.page_size as u64,
+        )?;
+        return Ok(data);
+    }
+    // for filling a file with pages from `raw_page` of another file with the same page
+    // size and cipher, each at the page number it had there. Nothing in the file counts
+    // until `finish_raw`.
+    pub fn put_raw(&mut self, pointer: u64, data: &[u8]) -> io::Result<()> {
+        assert!(self.updates.is_empty());
+        let page = page_number(pointer);
+        write_all_at(&self.file, data, page * self.page_size as u64)?;
+        self.npages = self.npages.max(page + 1);
+        return Ok(());
+    }
+    // commits the pages put with `put_raw` as the state `meta` of the file they came
+    // from, with `pages` all the pages its trees reach and every other page free
+    pub fn finish_raw(&mut self, meta: &Meta, pages: &[u64]) -> io::Result<()> {
+        let pages: HashSet<u64> = pages.iter().map(|pointer| page_number(*pointer)).collect();
+        // the old free list may have been written over
+        self.list_pages.clear();
+        self.meta.txid = meta.txid.saturating_sub(1);
+        self.npages = meta.npages;
+        self.counter = self.counter.max(meta.counter);
+        self.free = (1..meta.npages)
+            .rev()
+            .filter(|page| !pages.contains(page))
+            .collect();
+        return self.commit(meta.root);
+    }
+    // forgets everything since the last commit
+    pub fn rollback(&mut self) {
+        self.npages = self.meta.npages;
+        self.updates.clear();
+        self.counters.clear();
+        self.allocated.clear();
+        self.freed.clear();
+        self.free = self.committed_free.clone();
+    }
+    // pages in the file that hold nothing, for checking that pages are not lost
+    pub fn free_pages(&self) -> usize {
+        return self.free.len();
+    }
+    pub fn npages(&self) -> u64 {
+        return self.npages;
+    }
+}
+
+// binds a sealed page to its slot and to the write it came from
+fn seal_aad(page: u64, counter: u64) -> [u8; 16] {
+    let mut aad = [0; 16];
+    aad[..8].copy_from_slice(&page.to_le_bytes());
+    aad[8..].copy_from_slice(&counter.to_le_bytes());
+    return aad;
+}
@@ -0,0 +1,690 @@
+#![allow(clippy::needless_return)]
+use std::{
+    io::{self, BufRead, Write},
+    ops::Bound,
+};
+
+use crate::B_tree::{BTree, WriteBatch};
+
+// records in and out of a tree: CSV rows under a header row of column names, or JSON
+// Lines with one object per line. One column or field of each record is the key, the
+// value is one other column or field as text, or all of them but the key as a JSON
+// object, which is what lets a record go out again the way it came in.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Csv,
+    JsonLines,
+}
+impl Format {
+    pub fn by_name(name: &str) -> Option<Format> {
+        match name {
+            "csv" => return Some(Format::Csv),
+            "jsonl" => return Some(Format::JsonLines),
+            _ => return None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ValueEncoding {
+    // every column or field of the record but the key, as a JSON object. CSV columns are
+    // strings in it.
+    Json,
+    // the text of one column or field. A JSON string is stored without its quotes, any
+    // other JSON value as JSON.
+    Field(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecordOptions {
+    pub format: Format,
+    // name of the key column or field
+    pub key: String,
+    pub value: ValueEncoding,
+}
+
+// a JSON value, objects keep their members in order
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    // as written, checked to be a number
+    Number(String),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+impl Json {
+    pub fn parse(text: &str) -> Result<Json, String> {
+        let mut parser = JsonParser {
+            text: text.as_bytes(),
+            at: 0,
+            depth: 0,
+        };
+        let value = parser.value()?;
+        parser.whitespace();
+        if parser.at != parser.text.len() {
+            return Err(format!("trailing characters at {}", parser.at));
+        }
+        return Ok(value);
+    }
+    pub fn write(&self, out: &mut String) {
+        match self {
+            Json::Null => out.push_str("null"),
+            Json::Bool(value) => out.push_str(if *value { "true" } else { "false" }),
+            Json::Number(number) => out.push_str(number),
+            Json::String(text) => write_json_string(text, out),
+            Json::Array(items) => {
+                out.push('[');
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    item.write(out);
+                }
+                out.push(']');
+            }
+            Json::Object(members) => {
+                out.push('{');
+                for (i, (name, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    write_json_string(name, out);
+                    out.push(':');
+                    value.write(out);
+                }
+                out.push('}');
+            }
+        }
+    }
+    pub fn to_json(&self) -> String {
+        let mut out = String::new();
+        self.write(&mut out);
+        return out;
+    }
+    // a string as its text, anything else as JSON
+    pub fn to_text(&self) -> String {
+        match self {
+            Json::String(text) => return text.clone(),
+            other => return other.to_json(),
+        }
+    }
+}
+
+fn write_json_string(text: &str, out: &mut String) {
+    out.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+// how deep arrays and objects may nest
+const MAX_JSON_DEPTH: usize = 128;
+
+struct JsonParser<'a> {
+    text: &'a [u8],
+    at: usize,
+    // arrays and objects the parser is inside of
+    depth: usize,
+}
+impl JsonParser<'_> {
+    fn whitespace(&mut self) {
+        while self.at < self.text.len() && b" \t\r\n".contains(&self.text[self.at]) {
+            self.at += 1;
+        }
+    }
+    fn peek(&mut self) -> Option<u8> {
+        self.whitespace();
+        return self.text.get(self.at).copied();
+    }
+    fn expect(&mut self, byte: u8) -> Result<(), String> {
+        if self.peek() != Some(byte) {
+            return Err(format!("expected '{}' at {}", byte as char, self.at));
+        }
+        self.at += 1;
+        return Ok(());
+    }
+    fn literal(&mut self, word: &str, value: Json) -> Result<Json, String> {
+        if !self.text[self.at..].starts_with(word.as_bytes()) {
+            return Err(format!("unexpected character at {}", self.at));
+        }
+        self.at += word.len();
+        return Ok(value);
+    }
+    fn value(&mut self) -> Result<Json, String> {
+        if matches!(self.peek(), Some(b'{' | b'[')) {
+            if self.depth >= MAX_JSON_DEPTH {
+                return Err(format!("nested too deep at {}", self.at));
+            }
+            self.depth += 1;
+            let value = self.nested();
+            self.depth -= 1;
+            return value;
+        }
+        match self.peek() {
+            Some(b'"') => return Ok(Json::String(self.string()?)),
+            Some(b't') => return self.literal("true", Json::Bool(true)),
+            Some(b'f') => return self.literal("false", Json::Bool(false)),
+            Some(b'n') => return self.literal("null", Json::Null),
+            Some(b'-' | b'0'..=b'9') => return self.number(),
+            _ => return Err(format!("expected a value at {}", self.at)),
+        }
+    }
+    // an object or an array
+    fn nested(&mut self) -> Result<Json, String> {
+        match self.peek() {
+            Some(b'{') => {
+                self.at += 1;
+                let mut members = vec![];
+                if self.peek() == Some(b'}') {
+                    self.at += 1;
+                    return Ok(Json::Object(members));
+                }
+                loop {
+                    if self.peek() != Some(b'"') {
+                        return Err(format!("expected a member name at {}", self.at));
+                    }
+                    let name = self.string()?;
+                    self.expect(b':')?;
+                    members.push((name, self.value()?));
+                    match self.peek() {
+                        Some(b',') => self.at += 1,
+                        _ => break,
+                    }
+                }
+                self.expect(b'}')?;
+                return Ok(Json::Object(members));
+            }
+            Some(b'[') => {
+                self.at += 1;
+                let mut items = vec![];
+                if self.peek() == Some(b']') {
+                    self.at += 1;
+                    return Ok(Json::Array(items));
+                }
+                loop {
+                    items.push(self.value()?);
+                    match self.peek() {
+                        Some(b',') => self.at += 1,
+                        _ => break,
+                    }
+                }
+                self.expect(b']')?;
+                return Ok(Json::Array(items));
+            }
+            _ => return Err(format!("expected a value at {}", self.at)),
+        }
+    }
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.at;
+        while self.at < self.text.len() && b"+-.eE0123456789".contains(&self.text[self.at]) {
+            self.at += 1;
+        }
+        let number = std::str::from_utf8(&self.text[start..self.at]).unwrap();
+        if number.parse::<f64>().is_err() || number.starts_with('+') {
+            return Err(format!("bad number at {start}"));
+        }
+        return Ok(Json::Number(number.to_string()));
+    }
+    fn hex4(&mut self) -> Result<u32, String> {
+        let digits = self.text.get(self.at..self.at + 4).unwrap_or_default();
+        let digits = std::str::from_utf8(digits).unwrap_or_default();
+        let code =
+            u32::from_str_radix(digits, 16).map_err(|_| format!("bad escape at {}", self.at));
+        self.at += 4;
+        return code;
+    }
+    fn string(&mut self) -> Result<String, String> {
+        self.expect(b'"')?;
+        let mut bytes = vec![];
+        loop {
+            let Some(&byte) = self.text.get(self.at) else {
+                return Err("unterminated string".to_string());
+            };
+            self.at += 1;
+            match byte {
+                b'"' => break,
+                b'\\' => {
+                    let Some(&escape) = self.text.get(self.at) else {
+                        return Err("unterminated string".to_string());
+                    };
+                    self.at += 1;
+                    let c = match escape {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => {
+                            let mut code = self.hex4()?;
+                            // a surrogate pair for a character past the first plane
+                            if (0xd800..0xdc00).contains(&code)
+                                && self.text[self.at..].starts_with(b"\\u")
+                            {
+                                self.at += 2;
+                                let low = self.hex4()?;
+                                if !(0xdc00..0xe000).contains(&low) {
+                                    return Err(format!("bad escape at {}", self.at));
+                                }
+                                code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                            }
+                            char::from_u32(code)
+                                .ok_or_else(|| format!("bad escape at {}", self.at))?
+                        }
+                        _ => return Err(format!("bad escape at {}", self.at)),
+                    };
+                    bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                }
+                byte => bytes.push(byte),
+            }
+        }
+        return String::from_utf8(bytes).map_err(|_| "string is not UTF-8".to_string());
+    }
+}
+
+// the next record, its fields unquoted, or None at the end of the input. A quoted field
+// can run over several lines.
+pub fn read_csv_record(input: &mut dyn BufRead) -> io::Result<Option<Vec<String>>> {
+    let mut fields = vec![];
+    let mut field = String::new();
+    let (mut quoted, mut in_quotes) = (false, false);
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if input.read_line(&mut line)? == 0 {
+            if in_quotes {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "unterminated quote",
+                ));
+            }
+            if fields.is_empty() && field.is_empty() && !quoted {
+                return Ok(None);
+            }
+            fields.push(field);
+            return Ok(Some(fields));
+        }
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            if in_quotes {
+                match c {
+                    '"' if chars.peek() == Some(&'"') => {
+                        chars.next();
+                        field.push('"');
+                    }
+                    '"' => in_quotes = false,
+                    c => field.push(c),
+                }
+                continue;
+            }
+            match c {
+                '"' if field.is_empty() && !quoted => (quoted, in_quotes) = (true, true),
+                ',' => {
+                    fields.push(std::mem::take(&mut field));
+                    quoted = false;
+                }
+                '\r' if chars.peek() == Some(&'\n') => {}
+                '\n' => {
+                    fields.push(field);
+                    return Ok(Some(fields));
+                }
+                c => field.push(c),
+            }
+        }
+        if !in_quotes {
+            fields.push(field);
+            return Ok(Some(fields));
+        }
+    }
+}
+pub fn write_csv_record(fields: &[String], out: &mut dyn Write) -> io::Result<()> {
+    for (i, field) in fields.iter().enumerate() {
+        if i > 0 {
+            out.write_all(b",")?;
+        }
+        if field.contains([',', '"', '\r', '\n']) {
+            write!(out, "\"{}\"", field.replace('"', "\"\""))?;
+        } else {
+            out.write_all(field.as_bytes())?;
+        }
+    }
+    out.write_all(b"\n")?;
+    return Ok(());
+}
+
+fn bad(record: usize, message: &str) -> io::Error {
+    let message = format!("record {record}: {message}");
+    return io::Error::new(io::ErrorKind::InvalidData, message);
+}
+
+// the records of `input` with their fields in order
+fn read_records<'a>(
+    input: &'a mut dyn BufRead,
+    format: Format,
+) -> Box<dyn Iterator<Item = io::Result<Vec<(String, Json)>>> + 'a> {
+    match format {
+        Format::Csv => {
+            let mut header: Option<Vec<String>> = None;
+            let mut number = 0;
+            return Box::new(std::iter::from_fn(move || loop {
+                let fields = match read_csv_record(input) {
+                    Ok(Some(fields)) => fields,
+                    Ok(None) => return None,
+                    Err(err) => return Some(Err(err)),
+                };
+                if fields.len() == 1 && fields[0].is_empty() {
+                    continue;
+                }
+                let Some(names) = &header else {
+                    header = Some(fields);
+                    continue;
+                };
+                number += 1;
+                if fields.len() != names.len() {
+                    let message =
+                        format!("{} columns, the header has {}", fields.len(), names.len());
+                    return Some(Err(bad(number, &message)));
+                }
+                let fields = fields.into_iter().map(Json::String);
+                return Some(Ok(names.iter().cloned().zip(fields).collect()));
+            }));
+        }
+        Format::JsonLines => {
+            let mut number = 0;
+            return Box::new(input.lines().filter_map(move |line| {
+                let line = match line {
+                    Ok(line) if line.trim().is_empty() => return None,
+                    Ok(line) => line,
+                    Err(err) => return Some(Err(err)),
+                };
+                number += 1;
+                match Json::parse(&line) {
+                    Ok(Json::Object(members)) => return Some(Ok(members)),
+                    Ok(_) => return Some(Err(bad(number, "not a JSON object"))),
+                    Err(message) => return Some(Err(bad(number, &message))),
+                }
+            }));
+        }
+    }
+}
+
+// inserts every record of `input` into the tree, a later record with the same key
+// replacing an earlier one. Returns how many records there were. Nothing is written when
+// one of them is bad.
+pub fn import(
+    tree: &mut BTree,
+    input: &mut dyn BufRead,
+    options: &RecordOptions,
+) -> io::Result<usize> {
+    let mut batch = WriteBatch::new();
+    let mut count = 0;
+    for record in read_records(input, options.format) {
+        let record = record?;
+        count += 1;
+        let field = |name: &str| record.iter().find(|(field, _)| field == name);
+        let key = match field(&options.key) {
+            Some((_, value @ (Json::String(_) | Json::Number(_) | Json::Bool(_)))) => {
+                value.to_text()
+            }
+            Some(_) => return Err(bad(count, "the key is not a string, number or bool")),
+            None => return Err(bad(count, &format!("no {} for the key", options.key))),
+        };
+        let value = match &options.value {
+            ValueEncoding::Json => {
+                let mut rest = record.clone();
+                rest.retain(|(name, _)| *name != options.key);
+                Json::Object(rest).to_json()
+            }
+            ValueEncoding::Field(name) => match field(name) {
+                Some((_, value)) => value.to_text(),
+                None => return Err(bad(count, &format!("no {name} for the value"))),
+            },
+        };
+        if key.is_empty() || key.len() > tree.max_key_size() {
+            return Err(bad(count, "the key is empty or too long"));
+        }
+        if value.len() > tree.max_val_size() {
+            return Err(bad(count, "the value is too long"));
+        }
+        batch.put(key.into_bytes(), value.into_bytes());
+    }
+    tree.write(batch)?;
+    return Ok(count);
+}
+
+// writes the keys of the tree within the bounds out as records, in key order, the key
+// under the name of `options.key`. Returns how many there were. Keys and values have to
+// be UTF-8, and with ValueEncoding::Json values JSON objects. The columns of CSV are
+// the key and the fields of the first value, later values may leave some of them out.
+pub fn export(
+    tree: &BTree,
+    out: &mut dyn Write,
+    options: &RecordOptions,
+    start: Bound<&[u8]>,
+    end: Bound<&[u8]>,
+) -> io::Result<usize> {
+    let mut columns: Option<Vec<String>> = None;
+    let mut count = 0;
+    for (key, value) in tree.scan(start, end) {
+        count += 1;
+        let text = |bytes: Vec<u8>, what: &str| {
+            String::from_utf8(bytes).map_err(|_| bad(count, &format!("the {what} is not UTF-8")))
+        };
+        let mut record = vec![(options.key.clone(), Json::String(text(key, "key")?))];
+        let value = text(value, "value")?;
+        match &options.value {
+            ValueEncoding::Json => match Json::parse(&value) {
+                Ok(Json::Object(members)) => record.extend(members),
+                _ => return Err(bad(count, "the value is not a JSON object")),
+            },
+            ValueEncoding::Field(name) => record.push((name.clone(), Json::String(value))),
+        }
+        if options.format == Format::JsonLines {
+            writeln!(out, "{}", Json::Object(record).to_json())?;
+            continue;
+        }
+        let names = columns.get_or_insert_with(|| {
+            let names: Vec<String> = record.iter().map(|(name, _)| name.clone()).collect();
+            names
+        });
+        if count == 1 {
+            write_csv_record(names, out)?;
+        }
+        let mut fields = vec![String::new(); names.len()];
+        for (name, value) in record {
+            let Some(column) = names.iter().position(|column| *column == name) else {
+                return Err(bad(
+                    count,
+                    &format!("{name} is not a column of the first record"),
+                ));
+            };
+            fields[column] = value.to_text();
+        }
+        write_csv_record(&fields, out)?;
+    }
+    if options.format == Format::Csv && count == 0 {
+        let mut names = vec![options.key.clone()];
+        if let ValueEncoding::Field(name) = &options.value {
+            names.push(name.clone());
+        }
+        write_csv_record(&names, out)?;
+    }
+    out.flush()?;
+    return Ok(count);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::kv::{test::TempFile, KV};
+    use std::ops::Bound::{Excluded, Included, Unbounded};
+
+    #[test]
+    fn json_values() {
+        let text = r#"{"a":[1,-2.5e3,true,null],"b":{"c":"x\"\\\n\u00e9\ud83d\ude00"},"d":""}"#;
+        let value = Json::parse(text).unwrap();
+        let Json::Object(members) = &value else {
+            panic!("{value:?}");
+        };
+        assert_eq!(
+            members[1].1,
+            Json::Object(vec![(
+                "c".to_string(),
+                Json::String("x\"\\\né😀".to_string())
+            )])
+        );
+        assert_eq!(Json::parse(&value.to_json()), Ok(value));
+        assert_eq!(Json::parse(" [ ] ").unwrap().to_json(), "[]");
+        for bad in [
+            "",
+            "{",
+            "[1,]",
+            "{\"a\" 1}",
+            "01x",
+            "+1",
+            "\"\\q\"",
+            "nul",
+            "1 2",
+            "\"\\ud800\\u0041\"",
+            "\"\\ud800\"",
+        ] {
+            assert!(Json::parse(bad).is_err(), "{bad}");
+        }
+        // nesting is limited before it can run out of stack
+        let deep = "[".repeat(200000);
+        let err = Json::parse(&deep).unwrap_err();
+        assert!(err.contains("nested too deep"), "{err}");
+        let nested = format!("{}0{}", "[{\"a\":".repeat(60), "}]".repeat(60));
+        assert!(Json::parse(&nested).is_ok());
+    }
+
+    #[test]
+    fn csv_import_and_export() {
+        let file = TempFile::new("csv");
+        let mut db = KV::open(&file.0).unwrap();
+        let tree = db.create_tree("people").unwrap();
+        let csv =
+            "id,name,note\r\n2,\"Smith, Jo\",\"said \"\"hi\"\"\nand left\"\n\n1,Ann,\n3,Bo,x\n";
+        let options = RecordOptions {
+            format: Format::Csv,
+            key: "id".to_string(),
+            value: ValueEncoding::Json,
+        };
+        assert_eq!(import(tree, &mut csv.as_bytes(), &options).unwrap(), 3);
+        assert_eq!(
+            tree.get(b"2").unwrap().unwrap(),
+            br#"{"name":"Smith, Jo","note":"said \"hi\"\nand left"}"#
+        );
+        let mut out = vec![];
+        assert_eq!(
+            export(tree, &mut out, &options, Unbounded, Unbounded).unwrap(),
+            3
+        );
+        let expected =
+            "id,name,note\n1,Ann,\n2,\"Smith, Jo\",\"said \"\"hi\"\"\nand left\"\n3,Bo,x\n";
+        assert_eq!(String::from_utf8(out).unwrap(), expected);
+
+        // one column as the value, and a range of keys out
+        let names = RecordOptions {
+            value: ValueEncoding::Field("name".to_string()),
+            ..options.clone()
+        };
+        let tree = db.create_tree("names").unwrap();
+        import(tree, &mut csv.as_bytes(), &names).unwrap();
+        assert_eq!(tree.get(b"2").unwrap().unwrap(), b"Smith, Jo");
+        let mut out = vec![];
+        let range = export(tree, &mut out, &names, Excluded(b"1"), Included(b"2"));
+        assert_eq!(range.unwrap(), 1);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "id,name\n2,\"Smith, Jo\"\n"
+        );
+        let mut out = vec![];
+        export(tree, &mut out, &names, Excluded(b"3"), Unbounded).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "id,name\n");
+
+        // a bad record leaves the tree as it was
+        let tree = db.create_tree("bad").unwrap();
+        for bad in [
+            "id,name\n1,a\n2\n",
+            "name\nb\n",
+            "id\n\"1\n",
+            "id,name\n,a\n",
+        ] {
+            let err = import(tree, &mut bad.as_bytes(), &options).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{bad}");
+        }
+        assert!(tree.get(b"1").unwrap().is_none());
+        // JSON values of another tree are not CSV records
+        let mut out = vec![];
+        assert!(export(
+            db.open_tree("names").unwrap(),
+            &mut out,
+            &options,
+            Unbounded,
+            Unbounded
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn json_lines_import_and_export() {
+        let file = TempFile::new("jsonl");
+        let mut db = KV::open(&file.0).unwrap();
+        let tree = db.create_tree("events").unwrap();
+        let lines = "{\"id\": 7, \"tags\": [\"a\", \"b\"], \"at\": {\"h\": 1}}\n\n{\"at\": null, \"id\": \"x\"}\n";
+        let options = RecordOptions {
+            format: Format::JsonLines,
+            key: "id".to_string(),
+            value: ValueEncoding::Json,
+        };
+        assert_eq!(import(tree, &mut lines.as_bytes(), &options).unwrap(), 2);
+        assert_eq!(
+            tree.get(b"7").unwrap().unwrap(),
+            br#"{"tags":["a","b"],"at":{"h":1}}"#
+        );
+        let mut out = vec![];
+        export(tree, &mut out, &options, Unbounded, Unbounded).unwrap();
+        let expected =
+            "{\"id\":\"7\",\"tags\":[\"a\",\"b\"],\"at\":{\"h\":1}}\n{\"id\":\"x\",\"at\":null}\n";
+        assert_eq!(String::from_utf8(out).unwrap(), expected);
+
+        // a nested value as the value is stored as JSON, out again it is a string
+        let at = RecordOptions {
+            value: ValueEncoding::Field("at".to_string()),
+            ..options.clone()
+        };
+        import(tree, &mut lines.as_bytes(), &at).unwrap();
+        assert_eq!(tree.get(b"7").unwrap().unwrap(), br#"{"h":1}"#);
+        let mut out = vec![];
+        export(tree, &mut out, &at, Included(b"7"), Excluded(b"8")).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "{\"id\":\"7\",\"at\":\"{\\\"h\\\":1}\"}\n"
+        );
+
+        for bad in [
+            "{\"id\": 1}\n[1]\n",
+            "{\"id\": {}}\n",
+            "{\"id\": 1,}\n",
+            "{\"name\": 1}\n",
+        ] {
+            let err = import(tree, &mut bad.as_bytes(), &at).unwrap_err();
+            assert!(err.to_string().starts_with("record "), "{bad}: {err}");
+        }
+    }
+}
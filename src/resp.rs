@@ -0,0 +1,868 @@
+#![allow(clippy::needless_return)]
+use std::{
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    ops::Bound,
+    sync::{Arc, Mutex, MutexGuard},
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    dump::{hex_decode, hex_encode},
+    kv::{KVError, KV},
+    B_tree::{
+        is_expired, now_millis, UpdateReq, LEAF_TTL, MODE_INSERT_ONLY, MODE_UPDATE_ONLY,
+        MODE_UPSERT,
+    },
+};
+
+// a server speaking the protocol of redis (RESP 2) over one tree of a KV, so redis
+// clients can use it as a durable ordered store. Every command that writes is committed
+// before it is answered, MULTI/EXEC queues commands and runs them as one transaction.
+// Connections share the KV, each command holds it for as long as it runs.
+
+// the tree the keys are in
+pub const RESP_TREE: &str = "resp";
+// the longest bulk string or array read, keys and values are far shorter anyway
+const MAX_LEN: usize = 16 << 20;
+// the longest line, an inline command or the header of a value
+const MAX_LINE: usize = 64 << 10;
+// how deep arrays in a reply may nest, commands do not nest at all
+const MAX_DEPTH: usize = 64;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Simple(String),
+    Error(String),
+    Integer(i64),
+    // None is the null bulk string
+    Bulk(Option<Vec<u8>>),
+    // None is the null array
+    Array(Option<Vec<Value>>),
+}
+impl Value {
+    fn ok() -> Value {
+        return Value::Simple("OK".to_string());
+    }
+    fn error(message: &str) -> Value {
+        return Value::Error(format!("ERR {message}"));
+    }
+    fn bulk(bytes: Vec<u8>) -> Value {
+        return Value::Bulk(Some(bytes));
+    }
+}
+
+fn protocol_error(message: &str) -> io::Error {
+    return io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("protocol error: {message}"),
+    );
+}
+
+// a line without its CRLF, None at the end of the input
+fn read_line(input: &mut dyn BufRead) -> io::Result<Option<Vec<u8>>> {
+    let mut line = vec![];
+    let mut limited = <&mut dyn BufRead as io::Read>::take(&mut *input, MAX_LINE as u64 + 2);
+    if limited.read_until(b'\n', &mut line)? == 0 {
+        return Ok(None);
+    }
+    if line.last() != Some(&b'\n') && line.len() == MAX_LINE + 2 {
+        return Err(protocol_error("line too long"));
+    }
+    if line.pop() != Some(b'\n') {
+        return Err(protocol_error("unterminated line"));
+    }
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    return Ok(Some(line));
+}
+fn read_integer(bytes: &[u8]) -> io::Result<i64> {
+    let text = std::str::from_utf8(bytes).map_err(|_| protocol_error("bad integer"))?;
+    return text.parse().map_err(|_| protocol_error("bad integer"));
+}
+// the length of a bulk string or array, None for -1
+fn read_len(bytes: &[u8]) -> io::Result<Option<usize>> {
+    match read_integer(bytes)? {
+        -1 => return Ok(None),
+        len if (0..=MAX_LEN as i64).contains(&len) => return Ok(Some(len as usize)),
+        _ => return Err(protocol_error("bad length")),
+    }
+}
+// a value of type `kind` whose header line goes on with `rest`, inside `depth` arrays
+fn read_after_type(
+    kind: u8,
+    rest: &[u8],
+    input: &mut dyn BufRead,
+    depth: usize,
+) -> io::Result<Value> {
+    let text = || String::from_utf8_lossy(rest).into_owned();
+    match kind {
+        b'+' => return Ok(Value::Simple(text())),
+        b'-' => return Ok(Value::Error(text())),
+        b':' => return Ok(Value::Integer(read_integer(rest)?)),
+        b'$' => {
+            let Some(len) = read_len(rest)? else {
+                return Ok(Value::Bulk(None));
+            };
+            let mut bytes = vec![0; len + 2];
+            input.read_exact(&mut bytes)?;
+            if !bytes.ends_with(b"\r\n") {
+                return Err(protocol_error("bulk string without CRLF"));
+            }
+            bytes.truncate(len);
+            return Ok(Value::bulk(bytes));
+        }
+        b'*' => {
+            let Some(len) = read_len(rest)? else {
+                return Ok(Value::Array(None));
+            };
+            if depth >= MAX_DEPTH {
+                return Err(protocol_error("arrays nested too deep"));
+            }
+            let mut items = Vec::with_capacity(len.min(1024));
+            for _ in 0..len {
+                match read_nested(input, depth + 1)? {
+                    Some(item) => items.push(item),
+                    None => return Err(io::ErrorKind::UnexpectedEof.into()),
+                }
+            }
+            return Ok(Value::Array(Some(items)));
+        }
+        _ => return Err(protocol_error("unknown type")),
+    }
+}
+// the next value, None at the end of the input
+pub fn read_value(input: &mut dyn BufRead) -> io::Result<Option<Value>> {
+    return read_nested(input, 0);
+}
+fn read_nested(input: &mut dyn BufRead, depth: usize) -> io::Result<Option<Value>> {
+    let Some(line) = read_line(input)? else {
+        return Ok(None);
+    };
+    let Some((&kind, rest)) = line.split_first() else {
+        return Err(protocol_error("empty line"));
+    };
+    return read_after_type(kind, rest, input, depth).map(Some);
+}
+pub fn write_value(value: &Value, out: &mut dyn Write) -> io::Result<()> {
+    match value {
+        Value::Simple(text) => write!(out, "+{text}\r\n")?,
+        Value::Error(text) => write!(out, "-{text}\r\n")?,
+        Value::Integer(n) => write!(out, ":{n}\r\n")?,
+        Value::Bulk(None) => out.write_all(b"$-1\r\n")?,
+        Value::Bulk(Some(bytes)) => {
+            write!(out, "${}\r\n", bytes.len())?;
+            out.write_all(bytes)?;
+            out.write_all(b"\r\n")?;
+        }
+        Value::Array(None) => out.write_all(b"*-1\r\n")?,
+        Value::Array(Some(items)) => {
+            write!(out, "*{}\r\n", items.len())?;
+            for item in items {
+                write_value(item, out)?;
+            }
+        }
+    }
+    return Ok(());
+}
+
+// the next command, an array of bulk strings or an inline command of words separated by
+// spaces as typed into a telnet session. None at the end of the input.
+fn read_command(input: &mut dyn BufRead) -> io::Result<Option<Vec<Vec<u8>>>> {
+    loop {
+        let Some(line) = read_line(input)? else {
+            return Ok(None);
+        };
+        if line.first() != Some(&b'*') {
+            let words = line.split(|byte| byte.is_ascii_whitespace());
+            let words: Vec<Vec<u8>> = words
+                .filter(|w| !w.is_empty())
+                .map(<[u8]>::to_vec)
+                .collect();
+            if words.is_empty() {
+                continue;
+            }
+            return Ok(Some(words));
+        }
+        // a flat array of bulk strings, read without going into anything nested
+        let Some(len) = read_len(&line[1..])? else {
+            return Err(protocol_error("null command"));
+        };
+        let mut args = Vec::with_capacity(len.min(1024));
+        for _ in 0..len {
+            let Some(line) = read_line(input)? else {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            };
+            let Some((b'$', rest)) = line.split_first() else {
+                return Err(protocol_error("commands are arrays of bulk strings"));
+            };
+            let Value::Bulk(Some(arg)) = read_after_type(b'$', rest, input, 0)? else {
+                return Err(protocol_error("commands are arrays of bulk strings"));
+            };
+            args.push(arg);
+        }
+        if !args.is_empty() {
+            return Ok(Some(args));
+        }
+    }
+}
+
+// whether the glob `pattern` of SCAN MATCH matches all of `text`: * is any run of bytes,
+// ? any byte, [abc], [^abc] and [a-z] are sets of bytes and \ escapes the next byte
+pub fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => return text.is_empty(),
+        Some((b'*', rest)) => return (0..=text.len()).any(|i| glob_match(rest, &text[i..])),
+        Some((b'?', rest)) => return !text.is_empty() && glob_match(rest, &text[1..]),
+        Some((b'[', rest)) => {
+            let Some(end) = rest.iter().skip(1).position(|&b| b == b']').map(|i| i + 1) else {
+                return text.first() == Some(&b'[') && glob_match(rest, &text[1..]);
+            };
+            let (negated, set) = match rest[..end].split_first() {
+                Some((b'^', set)) => (true, set),
+                _ => (false, &rest[..end]),
+            };
+            let Some(&byte) = text.first() else {
+                return false;
+            };
+            let mut found = false;
+            let mut i = 0;
+            while i < set.len() {
+                if i + 2 < set.len() && set[i + 1] == b'-' {
+                    found |= (set[i]..=set[i + 2]).contains(&byte);
+                    i += 3;
+                } else {
+                    found |= set[i] == byte;
+                    i += 1;
+                }
+            }
+            return found != negated && glob_match(&rest[end + 1..], &text[1..]);
+        }
+        Some((b'\\', [escaped, rest @ ..])) => {
+            return text.first() == Some(escaped) && glob_match(rest, &text[1..]);
+        }
+        Some((&byte, rest)) => return text.first() == Some(&byte) && glob_match(rest, &text[1..]),
+    }
+}
+
+// the number of arguments a command takes, counting its name: exactly `n` for n >= 0,
+// at least -n for n < 0. None for commands there are not.
+fn arity(name: &str) -> Option<i64> {
+    match name {
+        "GET" | "INCR" => return Some(2),
+        "SET" => return Some(-3),
+        "DEL" | "EXISTS" | "MGET" | "SCAN" => return Some(-2),
+        "MSET" => return Some(-3),
+        "MULTI" | "EXEC" | "DISCARD" => return Some(1),
+        "PING" | "COMMAND" => return Some(-1),
+        "QUIT" => return Some(1),
+        _ => return None,
+    }
+}
+fn writes(name: &str) -> bool {
+    return matches!(name, "SET" | "DEL" | "INCR" | "MSET");
+}
+// the upper-cased name of the command, or the error for it when it is not one or has
+// the wrong number of arguments
+fn check(args: &[Vec<u8>]) -> Result<String, Value> {
+    let name = String::from_utf8_lossy(&args[0]).to_uppercase();
+    let Some(arity) = arity(&name) else {
+        return Err(Value::error(&format!(
+            "unknown command '{}'",
+            String::from_utf8_lossy(&args[0])
+        )));
+    };
+    let argc = args.len() as i64;
+    if (arity >= 0 && argc != arity)
+        || (arity < 0 && argc < -arity)
+        || (name == "MSET" && argc % 2 == 0)
+    {
+        let name = name.to_lowercase();
+        return Err(Value::error(&format!(
+            "wrong number of arguments for '{name}' command"
+        )));
+    }
+    return Ok(name);
+}
+
+fn parse_i64(bytes: &[u8]) -> Option<i64> {
+    return std::str::from_utf8(bytes).ok()?.parse().ok();
+}
+
+// runs one checked command other than the ones about transactions and the connection
+fn execute(db: &mut KV, name: &str, args: &[Vec<u8>]) -> Value {
+    let tree = match db.open_tree(RESP_TREE) {
+        Ok(tree) => tree,
+        Err(err) => return Value::error(&err.to_string()),
+    };
+    // the tree has no empty key, and keys have to fit into its nodes
+    let keys = match name {
+        "GET" | "SET" | "INCR" => &args[1..2],
+        "DEL" | "EXISTS" | "MGET" | "MSET" => &args[1..],
+        _ => &args[..0],
+    };
+    let step = if name == "MSET" { 2 } else { 1 };
+    let bad_key = |key: &Vec<u8>| key.is_empty() || key.len() > tree.max_key_size();
+    if keys.iter().step_by(step).any(bad_key) {
+        return Value::error("the key is empty or too long");
+    }
+    match name {
+        "PING" => match args.get(1) {
+            Some(message) => return Value::bulk(message.clone()),
+            None => return Value::Simple("PONG".to_string()),
+        },
+        // redis-cli asks for the documentation of the commands on start
+        "COMMAND" => return Value::Array(Some(vec![])),
+        "GET" => match tree.get(&args[1]) {
+            Ok(value) => return Value::Bulk(value),
+            Err(err) => return Value::error(&err.to_string()),
+        },
+        "MGET" => {
+            let values = args[1..].iter().map(|key| tree.get(key).map(Value::Bulk));
+            match values.collect() {
+                Ok(values) => return Value::Array(Some(values)),
+                Err(err) => return Value::error(&err.to_string()),
+            }
+        }
+        "EXISTS" => {
+            let mut count = 0;
+            for key in &args[1..] {
+                match tree.get(key) {
+                    Ok(value) => count += value.is_some() as i64,
+                    Err(err) => return Value::error(&err.to_string()),
+                }
+            }
+            return Value::Integer(count);
+        }
+        "DEL" => {
+            let mut count = 0;
+            for key in &args[1..] {
+                match tree.get(key) {
+                    Ok(Some(_)) => count += tree.delete(key.clone()) as i64,
+                    Ok(None) => {}
+                    Err(err) => return Value::error(&err.to_string()),
+                }
+            }
+            return Value::Integer(count);
+        }
+        "SET" => {
+            let mut req = UpdateReq::new(args[1].clone(), args[2].clone(), MODE_UPSERT);
+            let mut options = args[3..].iter();
+            while let Some(option) = options.next() {
+                match option.to_ascii_uppercase().as_slice() {
+                    b"NX" if req.mode == MODE_UPSERT => req.mode = MODE_INSERT_ONLY,
+                    b"XX" if req.mode == MODE_UPSERT => req.mode = MODE_UPDATE_ONLY,
+                    unit @ (b"EX" | b"PX") if req.flags == 0 => {
+                        let ttl = options.next().and_then(|ttl| parse_i64(ttl));
+                        let Some(ttl @ 1..) = ttl else {
+                            return Value::error("invalid expire time in 'set' command");
+                        };
+                        let millis = if unit == b"EX" {
+                            ttl.saturating_mul(1000)
+                        } else {
+                            ttl
+                        };
+                        req.flags = LEAF_TTL;
+                        req.expires = now_millis().saturating_add(millis as u64);
+                    }
+                    _ => return Value::error("syntax error"),
+                }
+            }
+            let overhead = if req.flags == LEAF_TTL { 8 } else { 0 };
+            if req.value.len() + overhead > tree.max_val_size() {
+                return Value::error("the value is too long");
+            }
+            if !tree.update(&mut req) {
+                return Value::Bulk(None);
+            }
+            return Value::ok();
+        }
+        "MSET" => {
+            if args[2..]
+                .iter()
+                .step_by(2)
+                .any(|value| value.len() > tree.max_val_size())
+            {
+                return Value::error("the value is too long");
+            }
+            for pair in args[1..].chunks(2) {
+                tree.insert(pair[0].clone(), pair[1].clone());
+            }
+            return Value::ok();
+        }
+        "INCR" => {
+            // the key keeps the time it expires
+            let key = Bound::Included(args[1].as_slice());
+            let mut entry = tree.scan(key, key).include_expired();
+            let (flags, stored) = match entry.next_entry() {
+                Some((flags, _, value)) if !is_expired(flags, &value, now_millis()) => {
+                    (flags, value)
+                }
+                _ => (0, b"0".to_vec()),
+            };
+            let (expires, value) = match flags {
+                LEAF_TTL if stored.len() >= 8 => (
+                    u64::from_le_bytes(stored[..8].try_into().unwrap()),
+                    &stored[8..],
+                ),
+                _ => (0, &stored[..]),
+            };
+            let Some(n) = parse_i64(value).and_then(|n| n.checked_add(1)) else {
+                return Value::error("value is not an integer or out of range");
+            };
+            let mut req = UpdateReq::new(args[1].clone(), n.to_string().into_bytes(), MODE_UPSERT);
+            (req.flags, req.expires) = (flags & LEAF_TTL, expires);
+            tree.update(&mut req);
+            return Value::Integer(n);
+        }
+        "SCAN" => {
+            // the cursor is the next key in hex, 0 at the start and once the scan is over
+            let start = match args[1].as_slice() {
+                b"0" => None,
+                cursor => match std::str::from_utf8(cursor).ok().and_then(hex_decode) {
+                    Some(key) => Some(key),
+                    None => return Value::error("invalid cursor"),
+                },
+            };
+            let (mut pattern, mut count) = (None, 10);
+            for option in args[2..].chunks(2) {
+                match (option[0].to_ascii_uppercase().as_slice(), option.get(1)) {
+                    (b"MATCH", Some(glob)) => pattern = Some(glob),
+                    (b"COUNT", Some(n)) => match parse_i64(n) {
+                        Some(n @ 1..) => count = n as usize,
+                        _ => return Value::error("value is not an integer or out of range"),
+                    },
+                    _ => return Value::error("syntax error"),
+                }
+            }
+            let start = start.as_deref().map_or(Bound::Unbounded, Bound::Included);
+            let mut iter = tree.scan(start, Bound::Unbounded);
+            let mut keys = vec![];
+            for (key, _) in iter.by_ref().take(count) {
+                if pattern.is_none_or(|glob| glob_match(glob, &key)) {
+                    keys.push(Value::bulk(key));
+                }
+            }
+            let cursor = match iter.next() {
+                Some((next, _)) => hex_encode(&next),
+                None => "0".to_string(),
+            };
+            return Value::Array(Some(vec![
+                Value::bulk(cursor.into_bytes()),
+                Value::Array(Some(keys)),
+            ]));
+        }
+        _ => unreachable!("{name} is checked"),
+    }
+}
+
+// the commands of a connection between MULTI and EXEC
+struct Multi {
+    queued: Vec<(String, Vec<Vec<u8>>)>,
+    // a command could not be queued, EXEC discards the transaction
+    failed: bool,
+}
+
+fn commit(db: &mut KV, reply: Value) -> Value {
+    if let Err(err) = db.commit() {
+        db.rollback();
+        return Value::error(&format!("commit failed: {err}"));
+    }
+    return reply;
+}
+
+// the KV for one command. A command that panicked on another connection left the lock
+// poisoned, with its changes half done: they are thrown away and the KV serves on.
+fn lock(db: &Mutex<KV>) -> MutexGuard<'_, KV> {
+    match db.lock() {
+        Ok(db) => return db,
+        Err(poisoned) => {
+            let mut kv = poisoned.into_inner();
+            kv.rollback();
+            db.clear_poison();
+            return kv;
+        }
+    }
+}
+
+fn serve_connection(db: &Mutex<KV>, stream: TcpStream) -> io::Result<()> {
+    let mut input = BufReader::new(stream.try_clone()?);
+    let mut out = BufWriter::new(stream);
+    let mut multi: Option<Multi> = None;
+    loop {
+        let args = match read_command(&mut input) {
+            Ok(Some(args)) => args,
+            Ok(None) => return Ok(()),
+            Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+                write_value(&Value::Error(format!("ERR {err}")), &mut out)?;
+                return out.flush();
+            }
+            Err(err) => return Err(err),
+        };
+        let reply = match (check(&args), &mut multi) {
+            (Err(error), Some(multi)) => {
+                multi.failed = true;
+                error
+            }
+            (Err(error), None) => error,
+            (Ok(name), _) if name == "QUIT" => {
+                write_value(&Value::ok(), &mut out)?;
+                return out.flush();
+            }
+            (Ok(name), None) if name == "MULTI" => {
+                multi = Some(Multi {
+                    queued: vec![],
+                    failed: false,
+                });
+                Value::ok()
+            }
+            (Ok(name), Some(_)) if name == "MULTI" => Value::error("MULTI calls can not be nested"),
+            (Ok(name), None) if name == "EXEC" || name == "DISCARD" => {
+                Value::error(&format!("{name} without MULTI"))
+            }
+            (Ok(name), Some(_)) if name == "DISCARD" => {
+                multi = None;
+                Value::ok()
+            }
+            (Ok(name), Some(_)) if name == "EXEC" => {
+                let Multi { queued, failed } = multi.take().unwrap();
+                if failed {
+                    Value::Error(
+                        "EXECABORT Transaction discarded because of previous errors.".to_string(),
+                    )
+                } else {
+                    // one commit for all of them, a command that fails does not undo the
+                    // others, as in redis
+                    let mut db = lock(db);
+                    let replies = queued
+                        .iter()
+                        .map(|(name, args)| execute(&mut db, name, args));
+                    let replies = Value::Array(Some(replies.collect()));
+                    match queued.iter().any(|(name, _)| writes(name)) {
+                        true => commit(&mut db, replies),
+                        false => replies,
+                    }
+                }
+            }
+            (Ok(name), Some(multi)) => {
+                multi.queued.push((name, args));
+                Value::Simple("QUEUED".to_string())
+            }
+            (Ok(name), None) => {
+                let mut db = lock(db);
+                let reply = execute(&mut db, &name, &args);
+                match writes(&name) && !matches!(reply, Value::Error(_)) {
+                    true => commit(&mut db, reply),
+                    false => reply,
+                }
+            }
+        };
+        write_value(&reply, &mut out)?;
+        // answers to pipelined commands go out together
+        if input.buffer().is_empty() {
+            out.flush()?;
+        }
+    }
+}
+
+pub struct Server {
+    listener: TcpListener,
+    db: Arc<Mutex<KV>>,
+}
+impl Server {
+    // creates the tree of the keys when the KV does not have it yet
+    pub fn bind(mut db: KV, addr: impl ToSocketAddrs) -> Result<Server, KVError> {
+        if !db.tree_names()?.iter().any(|name| name == RESP_TREE) {
+            db.create_tree(RESP_TREE)?;
+            db.commit()?;
+        }
+        let listener = TcpListener::bind(addr)?;
+        return Ok(Server {
+            listener,
+            db: Arc::new(Mutex::new(db)),
+        });
+    }
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        return self.listener.local_addr();
+    }
+    // accepts connections until accepting fails, each served on a thread of its own
+    pub fn run(&self) -> io::Result<()> {
+        loop {
+            let (stream, _) = self.listener.accept()?;
+            let db = self.db.clone();
+            thread::spawn(move || {
+                let _ = serve_connection(&db, stream);
+            });
+        }
+    }
+}
+
+// a client for the server, or for redis
+pub struct Client {
+    input: BufReader<TcpStream>,
+    out: BufWriter<TcpStream>,
+}
+impl Client {
+    pub fn connect(addr: impl ToSocketAddrs) -> io::Result<Client> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_read_timeout(Some(Duration::from_secs(30)))?;
+        return Ok(Client {
+            input: BufReader::new(stream.try_clone()?),
+            out: BufWriter::new(stream),
+        });
+    }
+    // sends the command and waits for its reply
+    pub fn command(&mut self, args: &[&[u8]]) -> io::Result<Value> {
+        let args = args.iter().map(|arg| Value::bulk(arg.to_vec())).collect();
+        write_value(&Value::Array(Some(args)), &mut self.out)?;
+        self.out.flush()?;
+        match read_value(&mut self.input)? {
+            Some(reply) => return Ok(reply),
+            None => return Err(io::ErrorKind::UnexpectedEof.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::kv::test::TempFile;
+
+    fn serve(file: &TempFile) -> SocketAddr {
+        let server = Server::bind(KV::open(&file.0).unwrap(), "127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+        return addr;
+    }
+    fn bulk(text: &str) -> Value {
+        return Value::bulk(text.as_bytes().to_vec());
+    }
+    fn array(items: Vec<Value>) -> Value {
+        return Value::Array(Some(items));
+    }
+
+    #[test]
+    fn values_and_globs() {
+        let value = array(vec![
+            Value::Simple("OK".to_string()),
+            Value::Error("ERR no".to_string()),
+            Value::Integer(-7),
+            Value::Bulk(None),
+            Value::bulk(b"a\r\nb".to_vec()),
+            Value::Array(None),
+            array(vec![]),
+        ]);
+        let mut bytes = vec![];
+        write_value(&value, &mut bytes).unwrap();
+        assert_eq!(read_value(&mut bytes.as_slice()).unwrap(), Some(value));
+        for bad in ["$3\r\nab\r\n", "*2\r\n:1\r\n", "$-2\r\n", "?\r\n", ":x\r\n"] {
+            assert!(read_value(&mut bad.as_bytes()).is_err(), "{bad:?}");
+        }
+        // nesting, in a reply or a command, and long lines are errors before they cost
+        // more than a bounded amount of stack or memory
+        let nested = "*1\r\n".repeat(1 << 20);
+        let err = read_value(&mut nested.as_bytes()).unwrap_err();
+        assert!(err.to_string().contains("nested too deep"), "{err}");
+        let err = read_command(&mut nested.as_bytes()).unwrap_err();
+        assert!(err.to_string().contains("arrays of bulk strings"), "{err}");
+        let long = format!("GET {}\r\n", "k".repeat(MAX_LINE));
+        let err = read_command(&mut long.as_bytes()).unwrap_err();
+        assert!(err.to_string().contains("line too long"), "{err}");
+        let command = "*2\r\n$3\r\nGET\r\n$1\r\nk\r\n";
+        let args = read_command(&mut command.as_bytes()).unwrap().unwrap();
+        assert_eq!(args, vec![b"GET".to_vec(), b"k".to_vec()]);
+        for (glob, text, matches) in [
+            ("user:*", "user:1", true),
+            ("user:*", "users", false),
+            ("*", "", true),
+            ("h?llo", "hallo", true),
+            ("h[ae]llo", "hillo", false),
+            ("h[^e]llo", "hallo", true),
+            ("h[a-c]llo", "hbllo", true),
+            ("a\\*", "a*", true),
+            ("a\\*", "ab", false),
+            ("[ab", "[ab", true),
+        ] {
+            assert_eq!(
+                glob_match(glob.as_bytes(), text.as_bytes()),
+                matches,
+                "{glob} {text}"
+            );
+        }
+    }
+
+    #[test]
+    fn commands() {
+        let file = TempFile::new("resp");
+        let mut client = Client::connect(serve(&file)).unwrap();
+        let mut run = |args: &[&str]| {
+            let args: Vec<&[u8]> = args.iter().map(|arg| arg.as_bytes()).collect();
+            return client.command(&args).unwrap();
+        };
+        let ok = Value::ok();
+        assert_eq!(run(&["PING"]), Value::Simple("PONG".to_string()));
+        assert_eq!(run(&["set", "a", "1"]), ok);
+        assert_eq!(run(&["GET", "a"]), bulk("1"));
+        assert_eq!(run(&["GET", "b"]), Value::Bulk(None));
+        assert_eq!(run(&["SET", "a", "2", "NX"]), Value::Bulk(None));
+        assert_eq!(run(&["SET", "b", "2", "XX"]), Value::Bulk(None));
+        assert_eq!(run(&["SET", "b", "2", "NX"]), ok);
+        assert_eq!(run(&["INCR", "a"]), Value::Integer(2));
+        assert_eq!(run(&["INCR", "new"]), Value::Integer(1));
+        assert_eq!(run(&["MSET", "c", "x", "d", "y"]), ok);
+        let values = vec![bulk("2"), Value::Bulk(None), bulk("x")];
+        assert_eq!(run(&["MGET", "a", "z", "c"]), array(values));
+        assert_eq!(
+            run(&["INCR", "c"]),
+            Value::error("value is not an integer or out of range")
+        );
+        assert_eq!(run(&["EXISTS", "a", "z", "a"]), Value::Integer(2));
+        assert_eq!(run(&["DEL", "a", "z", "d"]), Value::Integer(2));
+        assert_eq!(run(&["EXISTS", "a"]), Value::Integer(0));
+
+        // an expiring key keeps its time through INCR
+        assert_eq!(run(&["SET", "t", "5", "PX", "300"]), ok);
+        assert_eq!(run(&["INCR", "t"]), Value::Integer(6));
+        thread::sleep(Duration::from_millis(400));
+        assert_eq!(run(&["GET", "t"]), Value::Bulk(None));
+        assert_eq!(run(&["SET", "t", "1", "NX", "EX", "60"]), ok);
+
+        // SCAN in key order, a page of COUNT keys at a time
+        for i in 0..25 {
+            run(&["SET", &format!("user:{i:02}"), "u"]);
+        }
+        let (mut cursor, mut seen) = ("0".to_string(), vec![]);
+        loop {
+            let reply = run(&["SCAN", &cursor, "MATCH", "user:*", "COUNT", "7"]);
+            let Value::Array(Some(reply)) = reply else {
+                panic!("{reply:?}");
+            };
+            let [Value::Bulk(Some(next)), Value::Array(Some(keys))] = reply.as_slice() else {
+                panic!("{reply:?}");
+            };
+            seen.extend(keys.iter().cloned());
+            cursor = String::from_utf8(next.clone()).unwrap();
+            if cursor == "0" {
+                break;
+            }
+        }
+        let users: Vec<Value> = (0..25).map(|i| bulk(&format!("user:{i:02}"))).collect();
+        assert_eq!(seen, users);
+
+        for (args, error) in [
+            (&["NOPE"][..], "ERR unknown command 'NOPE'"),
+            (&["GET"], "ERR wrong number of arguments for 'get' command"),
+            (
+                &["MSET", "a", "1", "b"],
+                "ERR wrong number of arguments for 'mset' command",
+            ),
+            (
+                &["SET", "a", "1", "EX", "0"],
+                "ERR invalid expire time in 'set' command",
+            ),
+            (&["SET", "a", "1", "NX", "XX"], "ERR syntax error"),
+            (&["GET", ""], "ERR the key is empty or too long"),
+            (&["SCAN", "zz"], "ERR invalid cursor"),
+            (&["EXEC"], "ERR EXEC without MULTI"),
+        ] {
+            assert_eq!(run(args), Value::Error(error.to_string()));
+        }
+
+        // inline commands, answered in order when pipelined
+        let mut stream = TcpStream::connect(client.out.get_ref().peer_addr().unwrap()).unwrap();
+        stream
+            .write_all(b"SET inline yes\r\nGET inline\r\n")
+            .unwrap();
+        let mut input = BufReader::new(stream);
+        assert_eq!(read_value(&mut input).unwrap(), Some(ok));
+        assert_eq!(read_value(&mut input).unwrap(), Some(bulk("yes")));
+
+        // every write was committed
+        let mut db = KV::open(&file.0).unwrap();
+        let tree = db.open_tree(RESP_TREE).unwrap();
+        assert_eq!(tree.get(b"new").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(tree.get(b"inline").unwrap(), Some(b"yes".to_vec()));
+        assert_eq!(tree.get(b"a").unwrap(), None);
+    }
+
+    #[test]
+    fn transactions() {
+        let file = TempFile::new("resp");
+        let addr = serve(&file);
+        let (mut first, mut second) = (
+            Client::connect(addr).unwrap(),
+            Client::connect(addr).unwrap(),
+        );
+        let queued = Value::Simple("QUEUED".to_string());
+        assert_eq!(first.command(&[b"MULTI"]).unwrap(), Value::ok());
+        assert_eq!(first.command(&[b"SET", b"a", b"1"]).unwrap(), queued);
+        assert_eq!(first.command(&[b"INCR", b"a"]).unwrap(), queued);
+        assert_eq!(first.command(&[b"INCR", b"b"]).unwrap(), queued);
+        // nothing runs before EXEC
+        assert_eq!(second.command(&[b"GET", b"a"]).unwrap(), Value::Bulk(None));
+        let replies = vec![Value::ok(), Value::Integer(2), Value::Integer(1)];
+        assert_eq!(first.command(&[b"EXEC"]).unwrap(), array(replies));
+        assert_eq!(
+            second.command(&[b"MGET", b"a", b"b"]).unwrap(),
+            array(vec![bulk("2"), bulk("1")])
+        );
+
+        // a command that cannot be queued discards the transaction
+        assert_eq!(first.command(&[b"MULTI"]).unwrap(), Value::ok());
+        assert_eq!(first.command(&[b"SET", b"a", b"3"]).unwrap(), queued);
+        assert!(matches!(
+            first.command(&[b"SET", b"a"]).unwrap(),
+            Value::Error(_)
+        ));
+        let abort = first.command(&[b"EXEC"]).unwrap();
+        assert!(matches!(abort, Value::Error(message) if message.starts_with("EXECABORT")));
+        assert_eq!(first.command(&[b"MULTI"]).unwrap(), Value::ok());
+        assert_eq!(first.command(&[b"SET", b"a", b"4"]).unwrap(), queued);
+        assert_eq!(
+            first.command(&[b"MULTI"]).unwrap(),
+            Value::error("MULTI calls can not be nested")
+        );
+        assert_eq!(first.command(&[b"DISCARD"]).unwrap(), Value::ok());
+        assert_eq!(second.command(&[b"GET", b"a"]).unwrap(), bulk("2"));
+
+        // one that fails when it runs leaves the others be
+        first.command(&[b"MULTI"]).unwrap();
+        first.command(&[b"SET", b"s", b"text"]).unwrap();
+        first.command(&[b"INCR", b"s"]).unwrap();
+        first.command(&[b"INCR", b"b"]).unwrap();
+        let Value::Array(Some(replies)) = first.command(&[b"EXEC"]).unwrap() else {
+            panic!("EXEC");
+        };
+        assert!(matches!(replies[1], Value::Error(_)));
+        assert_eq!(replies[2], Value::Integer(2));
+        assert_eq!(first.command(&[b"QUIT"]).unwrap(), Value::ok());
+        assert!(first.command(&[b"PING"]).is_err());
+        let mut db = KV::open(&file.0).unwrap();
+        let tree = db.open_tree(RESP_TREE).unwrap();
+        assert_eq!(tree.get(b"s").unwrap(), Some(b"text".to_vec()));
+    }
+
+    #[test]
+    fn poisoned_lock() {
+        let file = TempFile::new("resp");
+        let db = Mutex::new(KV::open(&file.0).unwrap());
+        lock(&db).create_tree(RESP_TREE).unwrap();
+        lock(&db).commit().unwrap();
+        // a command that panics halfway leaves the lock poisoned and its write pending
+        thread::scope(|scope| {
+            let panicked = scope.spawn(|| {
+                let mut db = db.lock().unwrap();
+                let tree = db.open_tree(RESP_TREE).unwrap();
+                tree.insert(b"k".to_vec(), b"v".to_vec());
+                panic!("halfway");
+            });
+            assert!(panicked.join().is_err());
+        });
+        assert!(db.is_poisoned());
+        let mut kv = lock(&db);
+        assert!(!db.is_poisoned());
+        assert_eq!(kv.open_tree(RESP_TREE).unwrap().get(b"k").unwrap(), None);
+    }
+}
@@ -0,0 +1,689 @@
+use std::{cmp::Ordering, collections::HashMap, iter};
+
+use crate::table::{heap_get, ColumnType, IndexDef, TableDef, TableError, Value, DB};
+
+use super::{
+    explain::explain,
+    parser::{format_literal, AggFunc, BinaryOp, Expr, Statement, UnaryOp},
+    plan::{check_columns, plan_filter, plan_select, resolve, Plan, Scan, ScanKind},
+    Output, SqlError,
+};
+
+pub type Row = Vec<Value>;
+
+// every operator is an iterator over the rows of its input, so rows flow one at a time
+// from the B+tree range scan up to the caller and LIMIT stops the scan early
+pub type Rows<'a> = Box<dyn Iterator<Item = Result<Row, SqlError>> + 'a>;
+
+// integer literals are accepted where a float column is expected
+pub fn coerce(value: Value, column_type: ColumnType) -> Value {
+    match (value, column_type) {
+        (Value::Int64(v), ColumnType::Float64) => Value::Float64(v as f64),
+        (value, _) => value,
+    }
+}
+
+// total_cmp puts -0.0 below 0.0, as numbers they are the same
+fn compare_floats(a: f64, b: f64) -> Ordering {
+    let zero = |x: f64| if x == 0.0 { 0.0 } else { x };
+    return zero(a).total_cmp(&zero(b));
+}
+
+// compares values of the same type, integers and floats compare as numbers
+pub fn compare(a: &Value, b: &Value) -> Result<Ordering, SqlError> {
+    let ordering = match (a, b) {
+        (Value::Int64(a), Value::Int64(b)) => a.cmp(b),
+        (Value::Int64(a), Value::Float64(b)) => compare_floats(*a as f64, *b),
+        (Value::Float64(a), Value::Int64(b)) => compare_floats(*a, *b as f64),
+        (Value::Float64(a), Value::Float64(b)) => compare_floats(*a, *b),
+        (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+        (Value::Str(a), Value::Str(b)) => a.cmp(b),
+        (Value::Bytes(a), Value::Bytes(b)) => a.cmp(b),
+        _ => {
+            return Err(SqlError::Eval(format!(
+                "can not compare {} with {}",
+                format_literal(a),
+                format_literal(b)
+            )))
+        }
+    };
+    return Ok(ordering);
+}
+
+fn arithmetic(op: BinaryOp, a: Value, b: Value) -> Result<Value, SqlError> {
+    let overflow = || SqlError::Eval(format!("integer overflow in {}", op.symbol()));
+    match (a, b) {
+        (Value::Int64(a), Value::Int64(b)) => {
+            let result = match op {
+                BinaryOp::Add => a.checked_add(b),
+                BinaryOp::Sub => a.checked_sub(b),
+                BinaryOp::Mul => a.checked_mul(b),
+                _ if b == 0 => return Err(SqlError::Eval("division by zero".to_string())),
+                _ => a.checked_div(b),
+            };
+            return result.map(Value::Int64).ok_or_else(overflow);
+        }
+        (a @ (Value::Int64(_) | Value::Float64(_)), b @ (Value::Int64(_) | Value::Float64(_))) => {
+            let as_float = |value: Value| -> f64 {
+                match value {
+                    Value::Int64(v) => v as f64,
+                    Value::Float64(v) => v,
+                    _ => unreachable!(),
+                }
+            };
+            let (a, b) = (as_float(a), as_float(b));
+            let result = match op {
+                BinaryOp::Add => a + b,
+                BinaryOp::Sub => a - b,
+                BinaryOp::Mul => a * b,
+                _ => a / b,
+            };
+            return Ok(Value::Float64(result));
+        }
+        (a, b) => {
+            return Err(SqlError::Eval(format!(
+                "can not apply {} to {} and {}",
+                op.symbol(),
+                format_literal(&a),
+                format_literal(&b)
+            )))
+        }
+    }
+}
+
+fn boolean(value: Value) -> Result<bool, SqlError> {
+    match value {
+        Value::Bool(v) => return Ok(v),
+        value => {
+            return Err(SqlError::Eval(format!(
+                "expected a boolean, got {}",
+                format_literal(&value)
+            )))
+        }
+    }
+}
+
+// evaluates `expr` against a row whose columns are named by `columns`
+pub fn eval(expr: &Expr, columns: &[String], row: &[Value]) -> Result<Value, SqlError> {
+    match expr {
+        Expr::Column(name) => return Ok(row[resolve(columns, name)?].clone()),
+        Expr::Aggregate(_, _) => {
+            return Err(SqlError::Eval(format!(
+                "{expr} is only allowed in the select list, HAVING and ORDER BY"
+            )));
+        }
+        Expr::Literal(value) => return Ok(value.clone()),
+        Expr::Unary(UnaryOp::Not, operand) => {
+            return Ok(Value::Bool(!boolean(eval(operand, columns, row)?)?));
+        }
+        Expr::Unary(UnaryOp::Neg, operand) => {
+            let value = eval(operand, columns, row)?;
+            return arithmetic(BinaryOp::Sub, Value::Int64(0), value);
+        }
+        Expr::Binary(BinaryOp::And, left, right) => {
+            let result =
+                boolean(eval(left, columns, row)?)? && boolean(eval(right, columns, row)?)?;
+            return Ok(Value::Bool(result));
+        }
+        Expr::Binary(BinaryOp::Or, left, right) => {
+            let result =
+                boolean(eval(left, columns, row)?)? || boolean(eval(right, columns, row)?)?;
+            return Ok(Value::Bool(result));
+        }
+        Expr::Binary(op, left, right) => {
+            let a = eval(left, columns, row)?;
+            let b = eval(right, columns, row)?;
+            let result = match op {
+                BinaryOp::Eq => compare(&a, &b)?.is_eq(),
+                BinaryOp::Ne => compare(&a, &b)?.is_ne(),
+                BinaryOp::Lt => compare(&a, &b)?.is_lt(),
+                BinaryOp::Le => compare(&a, &b)?.is_le(),
+                BinaryOp::Gt => compare(&a, &b)?.is_gt(),
+                BinaryOp::Ge => compare(&a, &b)?.is_ge(),
+                _ => return arithmetic(*op, a, b),
+            };
+            return Ok(Value::Bool(result));
+        }
+    }
+}
+
+// the row if it satisfies `predicate`
+fn keep(
+    predicate: &Expr,
+    columns: &[String],
+    row: Result<Row, SqlError>,
+) -> Option<Result<Row, SqlError>> {
+    let row = match row {
+        Ok(row) => row,
+        Err(err) => return Some(Err(err)),
+    };
+    match eval(predicate, columns, &row).and_then(boolean) {
+        Ok(true) => return Some(Ok(row)),
+        Ok(false) => return None,
+        Err(err) => return Some(Err(err)),
+    }
+}
+
+// the running state of one aggregate over the rows of a group
+enum Acc {
+    Count(i64),
+    Sum(Option<Value>),
+    Min(Option<Value>),
+    Max(Option<Value>),
+    Avg(f64, i64),
+}
+impl Acc {
+    fn new(func: AggFunc) -> Acc {
+        match func {
+            AggFunc::Count => Acc::Count(0),
+            AggFunc::Sum => Acc::Sum(None),
+            AggFunc::Min => Acc::Min(None),
+            AggFunc::Max => Acc::Max(None),
+            AggFunc::Avg => Acc::Avg(0.0, 0),
+        }
+    }
+    fn add(&mut self, value: Value) -> Result<(), SqlError> {
+        let not_a_number = |value: &Value| {
+            return SqlError::Eval(format!(
+                "can not aggregate {} as a number",
+                format_literal(value)
+            ));
+        };
+        match self {
+            Acc::Count(n) => *n += 1,
+            Acc::Sum(sum) => {
+                if !matches!(value, Value::Int64(_) | Value::Float64(_)) {
+                    return Err(not_a_number(&value));
+                }
+                *sum = Some(match sum.take() {
+                    Some(sum) => arithmetic(BinaryOp::Add, sum, value)?,
+                    None => value,
+                });
+            }
+            Acc::Min(min) => {
+                if min.is_none() || compare(&value, min.as_ref().unwrap())?.is_lt() {
+                    *min = Some(value);
+                }
+            }
+            Acc::Max(max) => {
+                if max.is_none() || compare(&value, max.as_ref().unwrap())?.is_gt() {
+                    *max = Some(value);
+                }
+            }
+            Acc::Avg(sum, n) => {
+                match value {
+                    Value::Int64(v) => *sum += v as f64,
+                    Value::Float64(v) => *sum += v,
+                    value => return Err(not_a_number(&value)),
+                }
+                *n += 1;
+            }
+        }
+        return Ok(());
+    }
+    // SUM, MIN, MAX and AVG over no rows are an error, there is no NULL to stand for them.
+    // Only a query without GROUP BY has a group without rows.
+    fn finish(self) -> Result<Value, SqlError> {
+        let empty = |name: &str| SqlError::Eval(format!("{name} over no rows has no value"));
+        match self {
+            Acc::Count(n) => return Ok(Value::Int64(n)),
+            Acc::Sum(sum) => return sum.ok_or_else(|| empty("SUM")),
+            Acc::Min(value) => return value.ok_or_else(|| empty("MIN")),
+            Acc::Max(value) => return value.ok_or_else(|| empty("MAX")),
+            Acc::Avg(_, 0) => return Err(empty("AVG")),
+            Acc::Avg(sum, n) => return Ok(Value::Float64(sum / n as f64)),
+        }
+    }
+}
+
+// hash aggregation: one entry per distinct group key, in the order groups first appear
+fn aggregate(
+    rows: Rows,
+    columns: &[String],
+    group_by: &[Expr],
+    aggregates: &[Expr],
+) -> Result<Vec<Row>, SqlError> {
+    let new_accs = || -> Vec<Acc> {
+        return aggregates
+            .iter()
+            .map(|expr| match expr {
+                Expr::Aggregate(func, _) => Acc::new(*func),
+                _ => unreachable!("not an aggregate"),
+            })
+            .collect();
+    };
+    let mut groups: Vec<(Row, Vec<Acc>)> = vec![];
+    let mut positions: HashMap<Vec<u8>, usize> = HashMap::new();
+    for row in rows {
+        let row = row?;
+        let mut values = vec![];
+        let mut key = vec![];
+        for expr in group_by {
+            let value = eval(expr, columns, &row)?;
+            // the type goes first, values of different types never share a group
+            key.push(value.column_type() as u8);
+            value.encode(&mut key);
+            values.push(value);
+        }
+        let position = *positions.entry(key).or_insert_with(|| {
+            groups.push((values, new_accs()));
+            return groups.len() - 1;
+        });
+        for (acc, expr) in groups[position].1.iter_mut().zip(aggregates) {
+            if let Expr::Aggregate(_, arg) = expr {
+                let value = match arg {
+                    Some(arg) => eval(arg, columns, &row)?,
+                    None => Value::Int64(1),
+                };
+                acc.add(value)?;
+            }
+        }
+    }
+    // without GROUP BY there is a single group, even over no rows
+    if group_by.is_empty() && groups.is_empty() {
+        groups.push((vec![], new_accs()));
+    }
+    let mut out = vec![];
+    for (mut row, accs) in groups {
+        for acc in accs {
+            row.push(acc.finish()?);
+        }
+        out.push(row);
+    }
+    return Ok(out);
+}
+
+fn scan_rows<'a>(db: &'a DB, scan: &Scan) -> Rows<'a> {
+    let Some((start, end)) = scan.key_range() else {
+        return Box::new(iter::empty());
+    };
+    let def = scan.table.clone();
+    let rows = &db.tables[&def.name];
+    let start = start.as_ref().map(|key| key.as_slice());
+    let end = end.as_ref().map(|key| key.as_slice());
+    match &scan.kind {
+        ScanKind::PrimaryKey | ScanKind::Full => {
+            let iter = rows.scan(start, end);
+            return Box::new(iter.map(move |(key, value)| Ok(def.decode_row(&key, &value))));
+        }
+        ScanKind::Index(name) => {
+            let index = def.index(name).expect("planned index is missing").clone();
+            let iter = db.indexes[&(def.name.clone(), name.clone())].scan(start, end);
+            return Box::new(iter.map(move |(index_key, _)| {
+                let key = def.index_key_to_pkey(&index, &index_key);
+                let value = heap_get(rows, &key).expect("index entry without a row");
+                return Ok(def.decode_row(&key, &value));
+            }));
+        }
+    }
+}
+
+// the rows `plan` produces, pulled lazily through the operator pipeline
+pub fn run<'a>(db: &'a DB, plan: &'a Plan) -> Rows<'a> {
+    match plan {
+        Plan::Scan(scan) => return scan_rows(db, scan),
+        Plan::Filter { input, predicate } => {
+            let columns = input.columns();
+            let rows = run(db, input).filter_map(move |row| keep(predicate, &columns, row));
+            return Box::new(rows);
+        }
+        Plan::NestedLoopJoin { input, right, on } => {
+            let columns = plan.columns();
+            let rows = run(db, input).flat_map(move |left| -> Rows<'a> {
+                let left = match left {
+                    Ok(left) => left,
+                    Err(err) => return Box::new(iter::once(Err(err))),
+                };
+                let columns = columns.clone();
+                return Box::new(run(db, right).filter_map(move |row| {
+                    let row = row.map(|row| [left.clone(), row].concat());
+                    return keep(on, &columns, row);
+                }));
+            });
+            return Box::new(rows);
+        }
+        Plan::IndexJoin {
+            input,
+            right,
+            keys,
+            on,
+        } => {
+            let columns = plan.columns();
+            let left_columns = input.columns();
+            let rows = run(db, input).flat_map(move |left| -> Rows<'a> {
+                let left = match left {
+                    Ok(left) => left,
+                    Err(err) => return Box::new(iter::once(Err(err))),
+                };
+                let mut scan = right.clone();
+                for (expr, name) in keys.iter().zip(right.key_columns()) {
+                    let column_type =
+                        scan.table.columns[scan.table.column_index(name).unwrap()].column_type;
+                    match eval(expr, &left_columns, &left) {
+                        Ok(value) => scan.eq.push(coerce(value, column_type)),
+                        Err(err) => return Box::new(iter::once(Err(err))),
+                    }
+                }
+                // a key of another type can still compare equal, 1 = 1.0, so the
+                // right table is read whole then and the ON clause decides
+                if scan
+                    .key_columns()
+                    .iter()
+                    .zip(&scan.eq)
+                    .any(|(name, value)| {
+                        let column = scan.table.column_index(name).unwrap();
+                        return scan.table.columns[column].column_type != value.column_type();
+                    })
+                {
+                    scan.eq.clear();
+                }
+                let columns = columns.clone();
+                return Box::new(scan_rows(db, &scan).filter_map(move |row| {
+                    let row = row.map(|row| [left.clone(), row].concat());
+                    return keep(on, &columns, row);
+                }));
+            });
+            return Box::new(rows);
+        }
+        Plan::Aggregate {
+            input,
+            group_by,
+            aggregates,
+        } => {
+            let columns = input.columns();
+            match aggregate(run(db, input), &columns, group_by, aggregates) {
+                Ok(rows) => return Box::new(rows.into_iter().map(Ok)),
+                Err(err) => return Box::new(iter::once(Err(err))),
+            }
+        }
+        Plan::Sort { input, order_by } => {
+            // sorting needs every row, so this is where the pipeline stops streaming
+            let columns = input.columns();
+            let sorted = || -> Result<Vec<Row>, SqlError> {
+                let mut keyed = vec![];
+                for row in run(db, input) {
+                    let row = row?;
+                    let mut keys = vec![];
+                    for order in order_by {
+                        keys.push(eval(&order.expr, &columns, &row)?);
+                    }
+                    keyed.push((keys, row));
+                }
+                let mut failed = None;
+                keyed.sort_by(|(a, _), (b, _)| {
+                    for ((a, b), order) in a.iter().zip(b).zip(order_by) {
+                        let ordering = compare(a, b).unwrap_or_else(|err| {
+                            failed.get_or_insert(err);
+                            return Ordering::Equal;
+                        });
+                        if ordering.is_ne() {
+                            return if order.desc {
+                                ordering.reverse()
+                            } else {
+                                ordering
+                            };
+                        }
+                    }
+                    return Ordering::Equal;
+                });
+                if let Some(err) = failed {
+                    return Err(err);
+                }
+                return Ok(keyed.into_iter().map(|(_, row)| row).collect());
+            };
+            match sorted() {
+                Ok(rows) => return Box::new(rows.into_iter().map(Ok)),
+                Err(err) => return Box::new(iter::once(Err(err))),
+            }
+        }
+        Plan::Limit {
+            input,
+            offset,
+            limit,
+        } => {
+            let rows = run(db, input).skip(*offset as usize);
+            match limit {
+                Some(limit) => return Box::new(rows.take(*limit as usize)),
+                None => return Box::new(rows),
+            }
+        }
+        Plan::Project {
+            input,
+            exprs,
+            names: _,
+        } => {
+            let columns = input.columns();
+            let rows = run(db, input).map(move |row| {
+                let row = row?;
+                return exprs
+                    .iter()
+                    .map(|expr| eval(expr, &columns, &row))
+                    .collect::<Result<Row, SqlError>>();
+            });
+            return Box::new(rows);
+        }
+    }
+}
+
+// evaluates the constant expressions of an INSERT into a row in table column order
+fn insert_values(
+    def: &TableDef,
+    columns: &Option<Vec<String>>,
+    exprs: &[Expr],
+) -> Result<Row, SqlError> {
+    let names: Vec<String> = match columns {
+        Some(columns) => columns.clone(),
+        None => def
+            .columns
+            .iter()
+            .map(|column| column.name.clone())
+            .collect(),
+    };
+    if names.len() != exprs.len() || names.len() != def.columns.len() {
+        return Err(SqlError::Eval(format!(
+            "INSERT into {} needs a value for each of its {} columns",
+            def.name,
+            def.columns.len()
+        )));
+    }
+    let mut row: Vec<Option<Value>> = vec![None; def.columns.len()];
+    for (name, expr) in names.iter().zip(exprs) {
+        let i = def
+            .column_index(name)
+            .ok_or_else(|| SqlError::Table(TableError::NoSuchColumn(name.clone())))?;
+        check_columns(expr, &[])?;
+        row[i] = Some(coerce(eval(expr, &[], &[])?, def.columns[i].column_type));
+    }
+    if row.iter().any(|value| value.is_none()) {
+        return Err(SqlError::Eval(format!(
+            "INSERT into {} names a column twice",
+            def.name
+        )));
+    }
+    let row: Row = row.into_iter().map(|value| value.unwrap()).collect();
+    def.check_row(&row)?;
+    return Ok(row);
+}
+
+pub fn execute(db: &mut DB, statement: &Statement) -> Result<Output, SqlError> {
+    match statement {
+        Statement::CreateTable {
+            name,
+            columns,
+            primary_key,
+        } => {
+            let columns: Vec<(&str, ColumnType)> = columns
+                .iter()
+                .map(|(name, column_type)| (name.as_str(), *column_type))
+                .collect();
+            let primary_key: Vec<&str> = primary_key.iter().map(|name| name.as_str()).collect();
+            db.create_table(TableDef::new(name, &columns, &primary_key))?;
+            return Ok(Output::Done);
+        }
+        Statement::CreateIndex {
+            name,
+            table,
+            columns,
+            unique,
+        } => {
+            let columns: Vec<&str> = columns.iter().map(|name| name.as_str()).collect();
+            db.create_index(table, IndexDef::new(name, &columns, *unique))?;
+            return Ok(Output::Done);
+        }
+        Statement::Insert {
+            table,
+            columns,
+            rows,
+        } => {
+            let def = db.get_table(table)?;
+            // every row is checked before the first one is written
+            let rows = rows
+                .iter()
+                .map(|exprs| insert_values(&def, columns, exprs))
+                .collect::<Result<Vec<Row>, SqlError>>()?;
+            for row in &rows {
+                db.check_size(&def, row)?;
+            }
+            // a key that clashes with an earlier row of the statement only shows up once
+            // that row is written, the rows written so far are then deleted again
+            for (done, row) in rows.iter().enumerate() {
+                if let Err(err) = db.insert_row(table, row.clone()) {
+                    for row in &rows[..done] {
+                        db.delete_row(table, &def.pkey_of(row))?;
+                    }
+                    return Err(err.into());
+                }
+            }
+            return Ok(Output::Affected(rows.len()));
+        }
+        Statement::Select(select) => {
+            let plan = plan_select(db, select)?;
+            let rows = run(db, &plan).collect::<Result<Vec<Row>, SqlError>>()?;
+            return Ok(Output::Rows {
+                columns: plan.columns(),
+                rows,
+            });
+        }
+        Statement::Update {
+            table,
+            assignments,
+            filter,
+        } => {
+            let plan = plan_filter(db, table, filter.as_ref())?;
+            let def = db.get_table(table)?;
+            let columns = plan.columns();
+            let mut targets = vec![];
+            for (name, expr) in assignments {
+                let i = def
+                    .column_index(name)
+                    .ok_or_else(|| SqlError::Table(TableError::NoSuchColumn(name.clone())))?;
+                if def.primary_key.contains(name) {
+                    return Err(SqlError::Eval(format!(
+                        "primary key column {name} can not be updated"
+                    )));
+                }
+                check_columns(expr, &columns)?;
+                targets.push((i, expr));
+            }
+            // the matching rows are collected first, the scan can not run over a tree
+            // that is being written
+            let mut updated = vec![];
+            for row in run(db, &plan) {
+                let old = row?;
+                let mut row = old.clone();
+                for (i, expr) in &targets {
+                    row[*i] = coerce(eval(expr, &columns, &old)?, def.columns[*i].column_type);
+                }
+                def.check_row(&row)?;
+                db.check_size(&def, &row)?;
+                updated.push((old, row));
+            }
+            // on a unique violation the rows updated so far get their old values back, in
+            // reverse order so that every step is a state the table has already been in
+            for (done, (_, row)) in updated.iter().enumerate() {
+                if let Err(err) = db.update_row(table, row.clone()) {
+                    for (old, _) in updated[..done].iter().rev() {
+                        db.update_row(table, old.clone())?;
+                    }
+                    return Err(err.into());
+                }
+            }
+            return Ok(Output::Affected(updated.len()));
+        }
+        Statement::Explain(statement) => {
+            let lines = explain(db, statement)?;
+            return Ok(Output::Rows {
+                columns: vec!["plan".to_string()],
+                rows: lines
+                    .into_iter()
+                    .map(|line| vec![Value::Str(line)])
+                    .collect(),
+            });
+        }
+        Statement::Delete { table, filter } => {
+            let plan = plan_filter(db, table, filter.as_ref())?;
+            let def = db.get_table(table)?;
+            let pkeys = run(db, &plan)
+                .map(|row| row.map(|row| def.pkey_of(&row)))
+                .collect::<Result<Vec<Row>, SqlError>>()?;
+            for pkey in &pkeys {
+                db.delete_row(table, pkey)?;
+            }
+            return Ok(Output::Affected(pkeys.len()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sql::parser::parse;
+
+    #[test]
+    fn operators_stream_rows() {
+        let mut db = DB::new();
+        crate::sql::execute(&mut db, "CREATE TABLE t (id INT PRIMARY KEY, v INT)").unwrap();
+        for i in 0..1000 {
+            crate::sql::execute(&mut db, &format!("INSERT INTO t VALUES ({i}, {})", i * i))
+                .unwrap();
+        }
+        let select = match parse("SELECT v FROM t WHERE id >= 10 AND v / 0 = 1 LIMIT 2") {
+            Ok(Statement::Select(select)) => select,
+            _ => unreachable!(),
+        };
+        let plan = plan_select(&db, &select).unwrap();
+        // the first row already fails the division, nothing past it is evaluated
+        let mut rows = run(&db, &plan);
+        assert_eq!(
+            rows.next(),
+            Some(Err(SqlError::Eval("division by zero".to_string())))
+        );
+
+        let select = match parse("SELECT id, v FROM t WHERE id > 995 OR id < 2 LIMIT 3") {
+            Ok(Statement::Select(select)) => select,
+            _ => unreachable!(),
+        };
+        let plan = plan_select(&db, &select).unwrap();
+        let rows: Vec<Row> = run(&db, &plan).map(Result::unwrap).collect();
+        assert_eq!(
+            rows,
+            vec![
+                vec![Value::Int64(0), Value::Int64(0)],
+                vec![Value::Int64(1), Value::Int64(1)],
+                vec![Value::Int64(996), Value::Int64(996 * 996)],
+            ]
+        );
+        assert_eq!(
+            eval(
+                &Expr::Binary(
+                    BinaryOp::Mul,
+                    Box::new(Expr::Literal(Value::Int64(i64::MAX))),
+                    Box::new(Expr::Literal(Value::Int64(2)))
+                ),
+                &[],
+                &[]
+            ),
+            Err(SqlError::Eval("integer overflow in *".to_string()))
+        );
+    }
+}
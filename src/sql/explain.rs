@@ -0,0 +1,346 @@
+use std::ops::Bound;
+
+use crate::table::DB;
+
+use super::{
+    parser::{format_literal, Expr, Statement},
+    plan::{plan_filter, plan_select, Plan, Scan, ScanKind},
+    SqlError,
+};
+
+fn tree_estimate(db: &DB, scan: &Scan) -> u64 {
+    let Some((start, end)) = scan.key_range() else {
+        return 0;
+    };
+    let tree = match &scan.kind {
+        ScanKind::Index(name) => &db.indexes[&(scan.table.name.clone(), name.clone())],
+        _ => &db.tables[&scan.table.name],
+    };
+    return tree.estimate_count(
+        start.as_ref().map(|key| key.as_slice()),
+        end.as_ref().map(|key| key.as_slice()),
+    );
+}
+
+// the number of rows `plan` is expected to yield. Scans are estimated from the shape of
+// their tree, filters are not estimated and pass on the count of their input.
+pub fn estimate(db: &DB, plan: &Plan) -> u64 {
+    match plan {
+        Plan::Scan(scan) => return tree_estimate(db, scan),
+        Plan::NestedLoopJoin { input, right, .. } => {
+            return estimate(db, input).saturating_mul(estimate(db, right));
+        }
+        Plan::IndexJoin {
+            input, right, keys, ..
+        } => {
+            let left = estimate(db, input);
+            let unique = keys.len() == right.key_columns().len()
+                && match &right.kind {
+                    ScanKind::PrimaryKey => true,
+                    ScanKind::Index(name) => right.table.index(name).unwrap().unique,
+                    ScanKind::Full => false,
+                };
+            if unique {
+                return left;
+            }
+            // like a foreign key, every row on the right is taken to match one row on
+            // the left
+            return tree_estimate(db, right).max(left);
+        }
+        Plan::Aggregate {
+            input, group_by, ..
+        } => {
+            if group_by.is_empty() {
+                return 1;
+            }
+            return estimate(db, input);
+        }
+        Plan::Limit {
+            input,
+            offset,
+            limit,
+        } => {
+            let rows = estimate(db, input).saturating_sub(*offset);
+            return limit.map_or(rows, |limit| rows.min(limit));
+        }
+        Plan::Filter { input, .. } | Plan::Sort { input, .. } | Plan::Project { input, .. } => {
+            return estimate(db, input);
+        }
+    }
+}
+
+fn hex(key: &[u8]) -> String {
+    return key.iter().map(|byte| format!("{byte:02x}")).collect();
+}
+
+// the conditions on the key columns, then the encoded bounds handed to the tree
+fn describe_scan(scan: &Scan, keys: &[Expr]) -> String {
+    let mut line = format!("Scan {}", scan.table.name);
+    if scan.alias != scan.table.name {
+        line += &format!(" AS {}", scan.alias);
+    }
+    match &scan.kind {
+        ScanKind::PrimaryKey => line += " by primary key",
+        ScanKind::Index(name) => line += &format!(" by index {name}"),
+        ScanKind::Full => return line + " full",
+    }
+    let columns = scan.key_columns();
+    let mut conds = vec![];
+    for (name, expr) in columns.iter().zip(keys) {
+        conds.push(format!("{name} = {expr}"));
+    }
+    if !keys.is_empty() {
+        return format!("{line} {} for each row", conds.join(" AND "));
+    }
+    for (name, value) in columns.iter().zip(&scan.eq) {
+        conds.push(format!("{name} = {}", format_literal(value)));
+    }
+    if let Some(name) = columns.get(scan.eq.len()) {
+        match &scan.low {
+            Bound::Included(value) => conds.push(format!("{name} >= {}", format_literal(value))),
+            Bound::Excluded(value) => conds.push(format!("{name} > {}", format_literal(value))),
+            Bound::Unbounded => {}
+        }
+        match &scan.high {
+            Bound::Included(value) => conds.push(format!("{name} <= {}", format_literal(value))),
+            Bound::Excluded(value) => conds.push(format!("{name} < {}", format_literal(value))),
+            Bound::Unbounded => {}
+        }
+    }
+    if !conds.is_empty() {
+        line += &format!(" {}", conds.join(" AND "));
+    }
+    let range = match scan.key_range() {
+        None => "empty".to_string(),
+        Some((start, end)) => {
+            let start = match start {
+                Bound::Included(key) => format!("[{}", hex(&key)),
+                Bound::Excluded(key) => format!("({}", hex(&key)),
+                Bound::Unbounded => "(-inf".to_string(),
+            };
+            let end = match end {
+                Bound::Included(key) => format!("{}]", hex(&key)),
+                Bound::Excluded(key) => format!("{})", hex(&key)),
+                Bound::Unbounded => "+inf)".to_string(),
+            };
+            format!("{start}, {end}")
+        }
+    };
+    return format!("{line} range {range}");
+}
+
+fn explain_plan(db: &DB, plan: &Plan, depth: usize, lines: &mut Vec<String>) {
+    let list = |exprs: &mut dyn Iterator<Item = String>| -> String {
+        return exprs.collect::<Vec<String>>().join(", ");
+    };
+    let line = match plan {
+        Plan::Scan(scan) => describe_scan(scan, &[]),
+        Plan::NestedLoopJoin { on, .. } => format!("NestedLoopJoin ON {on}"),
+        Plan::IndexJoin { on, .. } => format!("IndexNestedLoopJoin ON {on}"),
+        Plan::Filter { predicate, .. } => format!("Filter {predicate}"),
+        Plan::Aggregate {
+            group_by,
+            aggregates,
+            ..
+        } => {
+            let mut line = "HashAggregate".to_string();
+            if !group_by.is_empty() {
+                line += &format!(
+                    " GROUP BY {}",
+                    list(&mut group_by.iter().map(Expr::to_string))
+                );
+            }
+            format!(
+                "{line} {}",
+                list(&mut aggregates.iter().map(Expr::to_string))
+            )
+        }
+        Plan::Sort { order_by, .. } => {
+            let mut keys = order_by.iter().map(|order| {
+                return format!("{}{}", order.expr, if order.desc { " DESC" } else { "" });
+            });
+            format!("Sort {}", list(&mut keys))
+        }
+        Plan::Limit { offset, limit, .. } => match limit {
+            Some(limit) if *offset > 0 => format!("Limit {limit} OFFSET {offset}"),
+            Some(limit) => format!("Limit {limit}"),
+            None => format!("Offset {offset}"),
+        },
+        Plan::Project { exprs, names, .. } => {
+            let mut items = exprs.iter().zip(names).map(|(expr, name)| {
+                let expr = expr.to_string();
+                // `*` expands to columns named after themselves without their table
+                if expr == *name || expr.ends_with(&format!(".{name}")) {
+                    return expr;
+                }
+                return format!("{expr} AS {name}");
+            });
+            format!("Project {}", list(&mut items))
+        }
+    };
+    let indent = "  ".repeat(depth);
+    lines.push(format!("{indent}{line} (~{} rows)", estimate(db, plan)));
+    match plan {
+        Plan::Scan(_) => {}
+        Plan::NestedLoopJoin { input, right, .. } => {
+            explain_plan(db, input, depth + 1, lines);
+            explain_plan(db, right, depth + 1, lines);
+        }
+        Plan::IndexJoin {
+            input, right, keys, ..
+        } => {
+            explain_plan(db, input, depth + 1, lines);
+            let indent = "  ".repeat(depth + 1);
+            let scan = describe_scan(right, keys);
+            let rows = tree_estimate(db, right);
+            lines.push(format!("{indent}{scan} (~{rows} rows in all)"));
+        }
+        Plan::Filter { input, .. }
+        | Plan::Aggregate { input, .. }
+        | Plan::Sort { input, .. }
+        | Plan::Limit { input, .. }
+        | Plan::Project { input, .. } => explain_plan(db, input, depth + 1, lines),
+    }
+}
+
+// the plan tree of a statement, one operator per line and inputs indented below it
+pub fn explain(db: &DB, statement: &Statement) -> Result<Vec<String>, SqlError> {
+    let mut lines = vec![];
+    match statement {
+        Statement::Select(select) => {
+            let plan = plan_select(db, select)?;
+            explain_plan(db, &plan, 0, &mut lines);
+        }
+        Statement::Update {
+            table,
+            assignments,
+            filter,
+        } => {
+            let plan = plan_filter(db, table, filter.as_ref())?;
+            let assignments: Vec<String> = assignments
+                .iter()
+                .map(|(name, expr)| format!("{name} = {expr}"))
+                .collect();
+            lines.push(format!("Update {table} SET {}", assignments.join(", ")));
+            explain_plan(db, &plan, 1, &mut lines);
+        }
+        Statement::Delete { table, filter } => {
+            let plan = plan_filter(db, table, filter.as_ref())?;
+            lines.push(format!("Delete FROM {table}"));
+            explain_plan(db, &plan, 1, &mut lines);
+        }
+        _ => {
+            return Err(SqlError::Eval(
+                "only SELECT, UPDATE and DELETE have a plan".to_string(),
+            ))
+        }
+    }
+    return Ok(lines);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        sql::{execute, Output},
+        table::Value,
+    };
+
+    fn explain_lines(db: &mut DB, sql: &str) -> Vec<String> {
+        match execute(db, &format!("EXPLAIN {sql}")).unwrap() {
+            Output::Rows { columns, rows } => {
+                assert_eq!(columns, vec!["plan".to_string()]);
+                return rows
+                    .into_iter()
+                    .map(|row| match &row[..] {
+                        [Value::Str(line)] => line.clone(),
+                        _ => panic!("{row:?}"),
+                    })
+                    .collect();
+            }
+            output => panic!("{output:?}"),
+        }
+    }
+
+    #[test]
+    fn plans_show_scans_bounds_and_estimates() {
+        let mut db = DB::new();
+        for sql in [
+            "CREATE TABLE users (id INT PRIMARY KEY, name TEXT, age INT)",
+            "CREATE INDEX by_age ON users (age)",
+            "CREATE TABLE orders (id INT PRIMARY KEY, user INT)",
+        ] {
+            execute(&mut db, sql).unwrap();
+        }
+        for i in 0..40 {
+            let sql = format!("INSERT INTO users VALUES ({i}, 'u{i}', {})", 20 + i % 10);
+            execute(&mut db, &sql).unwrap();
+            let sql = format!("INSERT INTO orders VALUES ({i}, {})", i / 2);
+            execute(&mut db, &sql).unwrap();
+        }
+
+        assert_eq!(
+            explain_lines(
+                &mut db,
+                "SELECT name FROM users WHERE id >= 10 AND id < 14 ORDER BY name DESC LIMIT 2"
+            ),
+            vec![
+                "Project name (~2 rows)",
+                "  Limit 2 (~2 rows)",
+                "    Sort name DESC (~4 rows)",
+                "      Filter id >= 10 AND id < 14 (~4 rows)",
+                "        Scan users by primary key id >= 10 AND id < 14 \
+                 range [800000000000000a, 800000000000000e) (~4 rows)",
+            ]
+        );
+        assert_eq!(
+            explain_lines(&mut db, "SELECT * FROM users WHERE age = 25")[2],
+            "    Scan users by index by_age age = 25 \
+             range [8000000000000019, 800000000000001a) (~4 rows)"
+        );
+        assert_eq!(
+            explain_lines(&mut db, "SELECT COUNT(*) FROM users WHERE name = 'u1'"),
+            vec![
+                "Project COUNT(*) (~1 rows)",
+                "  HashAggregate COUNT(*) (~1 rows)",
+                "    Filter name = 'u1' (~40 rows)",
+                "      Scan users full (~40 rows)",
+            ]
+        );
+        assert_eq!(
+            explain_lines(
+                &mut db,
+                "SELECT u.name, o.id FROM orders o JOIN users u ON u.id = o.user WHERE o.id < 5"
+            ),
+            vec![
+                "Project u.name, o.id (~5 rows)",
+                "  Filter o.id < 5 (~5 rows)",
+                "    IndexNestedLoopJoin ON u.id = o.user (~5 rows)",
+                "      Scan orders AS o by primary key id < 5 \
+                 range (-inf, 8000000000000005) (~5 rows)",
+                "      Scan users AS u by primary key id = o.user for each row (~40 rows in all)",
+            ]
+        );
+        assert_eq!(
+            explain_lines(&mut db, "DELETE FROM users WHERE id = 3"),
+            vec![
+                "Delete FROM users",
+                "  Filter id = 3 (~1 rows)",
+                "    Scan users by primary key id = 3 \
+                 range [8000000000000003, 8000000000000004) (~1 rows)",
+            ]
+        );
+        // explaining runs nothing
+        assert_eq!(
+            explain_lines(&mut db, "UPDATE users SET age = 1")[0],
+            "Update users SET age = 1"
+        );
+        assert_eq!(
+            execute(&mut db, "SELECT COUNT(*) FROM users WHERE age = 1"),
+            Ok(Output::Rows {
+                columns: vec!["COUNT(*)".to_string()],
+                rows: vec![vec![Value::Int64(0)]]
+            })
+        );
+    }
+}
@@ -0,0 +1,409 @@
+#![allow(clippy::needless_return)]
+// a small SQL front end over the table layer: `parser` turns text into statements,
+// `plan` picks how a query reaches its rows and `exec` runs the plan as a pipeline of
+// iterators fed by B+tree range scans. `explain` prints plans instead of running them.
+pub mod exec;
+pub mod explain;
+pub mod parser;
+pub mod plan;
+
+use std::fmt;
+
+use crate::table::{TableError, Value, DB};
+
+#[derive(Debug, PartialEq)]
+pub enum SqlError {
+    Syntax(String),
+    Table(TableError),
+    Eval(String),
+}
+impl fmt::Display for SqlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SqlError::Syntax(message) => write!(f, "syntax error: {message}"),
+            SqlError::Table(err) => write!(f, "{err}"),
+            SqlError::Eval(message) => write!(f, "{message}"),
+        }
+    }
+}
+impl From<TableError> for SqlError {
+    fn from(err: TableError) -> SqlError {
+        return SqlError::Table(err);
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Output {
+    Done,
+    // rows inserted, updated or deleted
+    Affected(usize),
+    Rows {
+        columns: Vec<String>,
+        rows: Vec<Vec<Value>>,
+    },
+}
+
+// parses and runs one statement
+pub fn execute(db: &mut DB, sql: &str) -> Result<Output, SqlError> {
+    let statement = parser::parse(sql)?;
+    return exec::execute(db, &statement);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rows(db: &mut DB, sql: &str) -> Vec<Vec<Value>> {
+        match execute(db, sql).unwrap() {
+            Output::Rows { rows, .. } => return rows,
+            output => panic!("{sql} gave {output:?}"),
+        }
+    }
+
+    #[test]
+    fn statements_run_end_to_end() {
+        let mut db = DB::new();
+        let create = "CREATE TABLE users (tenant INT, name TEXT, score FLOAT, \
+                      active BOOL, PRIMARY KEY (tenant, name))";
+        assert_eq!(execute(&mut db, create), Ok(Output::Done));
+        assert_eq!(
+            execute(&mut db, "CREATE INDEX by_score ON users (score)"),
+            Ok(Output::Done)
+        );
+        for i in 0..100 {
+            let sql = format!(
+                "INSERT INTO users VALUES ({}, 'user{i}', {}, {})",
+                i % 4,
+                i % 10,
+                i % 2 == 0
+            );
+            assert_eq!(execute(&mut db, &sql), Ok(Output::Affected(1)));
+        }
+        assert_eq!(
+            execute(
+                &mut db,
+                "insert into users (name, tenant, score, active) \
+                 values ('x', -1, 0.5, false), ('y', -1, -2, true);"
+            ),
+            Ok(Output::Affected(2))
+        );
+
+        let output = execute(
+            &mut db,
+            "SELECT name, score * 2 AS double FROM users \
+             WHERE tenant = 1 AND score >= 5 ORDER BY double DESC, name LIMIT 3",
+        );
+        assert_eq!(
+            output,
+            Ok(Output::Rows {
+                columns: vec!["name".to_string(), "double".to_string()],
+                rows: vec![
+                    vec![Value::Str("user29".to_string()), Value::Float64(18.0)],
+                    vec![Value::Str("user49".to_string()), Value::Float64(18.0)],
+                    vec![Value::Str("user69".to_string()), Value::Float64(18.0)],
+                ],
+            })
+        );
+        assert_eq!(
+            rows(&mut db, "SELECT * FROM users WHERE score = 3").len(),
+            10
+        );
+        assert_eq!(
+            rows(
+                &mut db,
+                "SELECT name FROM users WHERE tenant = -1 LIMIT 1 OFFSET 1"
+            ),
+            vec![vec![Value::Str("y".to_string())]]
+        );
+
+        assert_eq!(
+            execute(
+                &mut db,
+                "UPDATE users SET score = score + 100 WHERE score = 3 AND NOT active"
+            ),
+            Ok(Output::Affected(10))
+        );
+        assert_eq!(
+            rows(&mut db, "SELECT * FROM users WHERE score = 103").len(),
+            10
+        );
+        assert_eq!(
+            execute(&mut db, "DELETE FROM users WHERE tenant = 0 OR score > 100"),
+            Ok(Output::Affected(35))
+        );
+        assert_eq!(rows(&mut db, "SELECT * FROM users").len(), 67);
+
+        assert_eq!(
+            execute(&mut db, "UPDATE users SET tenant = 5"),
+            Err(SqlError::Eval(
+                "primary key column tenant can not be updated".to_string()
+            ))
+        );
+        assert_eq!(
+            execute(&mut db, "SELECT * FROM users WHERE name = 1"),
+            Err(SqlError::Eval("can not compare 'x' with 1".to_string()))
+        );
+        assert_eq!(
+            execute(&mut db, "SELECT nope FROM users"),
+            Err(SqlError::Table(TableError::NoSuchColumn(
+                "nope".to_string()
+            )))
+        );
+        assert!(matches!(
+            execute(&mut db, "SELECT FROM users"),
+            Err(SqlError::Syntax(_))
+        ));
+    }
+
+    #[test]
+    fn oversize_rows_are_errors() {
+        let mut db = DB::new();
+        execute(
+            &mut db,
+            "CREATE TABLE notes (id INT, body TEXT, PRIMARY KEY (id))",
+        )
+        .unwrap();
+        let too_large = Err(SqlError::Table(TableError::BadRow(
+            "row too large".to_string(),
+        )));
+        let long = "x".repeat(5000);
+        let insert = format!("INSERT INTO notes VALUES (1, 'short'), (2, '{long}')");
+        assert_eq!(execute(&mut db, &insert), too_large);
+        // nothing of a refused statement is written
+        assert_eq!(rows(&mut db, "SELECT * FROM notes").len(), 0);
+        execute(
+            &mut db,
+            "INSERT INTO notes VALUES (1, 'short'), (2, 'short')",
+        )
+        .unwrap();
+        let update = format!("UPDATE notes SET body = '{long}' WHERE id >= 1");
+        assert_eq!(execute(&mut db, &update), too_large);
+        assert_eq!(
+            rows(&mut db, "SELECT body FROM notes WHERE id = 2"),
+            vec![vec![Value::Str("short".to_string())]]
+        );
+    }
+
+    #[test]
+    fn failed_statements_write_nothing() {
+        let mut db = DB::new();
+        for sql in [
+            "CREATE TABLE t (id INT, v INT, PRIMARY KEY (id))",
+            "CREATE UNIQUE INDEX by_v ON t (v)",
+            "INSERT INTO t VALUES (1, 10), (2, 20), (3, 30), (4, 31)",
+        ] {
+            execute(&mut db, sql).unwrap();
+        }
+        let before = rows(&mut db, "SELECT * FROM t");
+        // the last row clashes with an earlier row of the same statement
+        assert_eq!(
+            execute(&mut db, "INSERT INTO t VALUES (5, 50), (6, 60), (5, 70)"),
+            Err(SqlError::Table(TableError::DuplicateKey))
+        );
+        assert_eq!(
+            execute(&mut db, "INSERT INTO t VALUES (5, 50), (6, 60), (7, 50)"),
+            Err(SqlError::Table(TableError::UniqueViolation(
+                "by_v".to_string()
+            )))
+        );
+        assert_eq!(rows(&mut db, "SELECT * FROM t"), before);
+        assert!(rows(&mut db, "SELECT id FROM t WHERE v = 50").is_empty());
+        // the last row updated clashes with a row that is not updated
+        assert_eq!(
+            execute(&mut db, "UPDATE t SET v = v + 1 WHERE id <= 3"),
+            Err(SqlError::Table(TableError::UniqueViolation(
+                "by_v".to_string()
+            )))
+        );
+        assert_eq!(rows(&mut db, "SELECT * FROM t"), before);
+        assert_eq!(
+            rows(&mut db, "SELECT id FROM t WHERE v = 10"),
+            vec![vec![Value::Int64(1)]]
+        );
+        assert!(rows(&mut db, "SELECT id FROM t WHERE v = 11").is_empty());
+    }
+
+    #[test]
+    fn negative_zero_is_zero() {
+        let mut db = DB::new();
+        for sql in [
+            "CREATE TABLE m (id INT, x FLOAT, y FLOAT, PRIMARY KEY (id))",
+            "CREATE UNIQUE INDEX by_x ON m (x)",
+            "INSERT INTO m VALUES (1, 0.0, -0.0)",
+        ] {
+            execute(&mut db, sql).unwrap();
+        }
+        assert_eq!(
+            execute(&mut db, "INSERT INTO m VALUES (2, -0.0, 0)"),
+            Err(SqlError::Table(TableError::UniqueViolation(
+                "by_x".to_string()
+            )))
+        );
+        let one = vec![vec![Value::Int64(1)]];
+        // through the index and in a filter over every row
+        assert_eq!(rows(&mut db, "SELECT id FROM m WHERE x = -0.0"), one);
+        assert_eq!(rows(&mut db, "SELECT id FROM m WHERE y = 0.0"), one);
+        assert_eq!(rows(&mut db, "SELECT id FROM m WHERE y >= 0"), one);
+        assert!(rows(&mut db, "SELECT id FROM m WHERE y < 0.0").is_empty());
+    }
+
+    #[test]
+    fn joins_and_aggregates() {
+        let mut db = DB::new();
+        for sql in [
+            "CREATE TABLE users (id INT PRIMARY KEY, name TEXT, city TEXT)",
+            "CREATE TABLE orders (id INT PRIMARY KEY, user INT, total FLOAT)",
+            "CREATE INDEX by_user ON orders (user)",
+            "CREATE TABLE cities (name TEXT PRIMARY KEY, country TEXT)",
+            "INSERT INTO cities VALUES ('oslo', 'no'), ('lima', 'pe')",
+        ] {
+            execute(&mut db, sql).unwrap();
+        }
+        for i in 0..20 {
+            let city = if i % 3 == 0 { "oslo" } else { "lima" };
+            let sql = format!("INSERT INTO users VALUES ({i}, 'u{i}', '{city}')");
+            execute(&mut db, &sql).unwrap();
+        }
+        // user i has i % 4 orders
+        let mut id = 0;
+        for i in 0..20 {
+            for j in 0..i % 4 {
+                let sql = format!("INSERT INTO orders VALUES ({id}, {i}, {})", j * 10 + 5);
+                execute(&mut db, &sql).unwrap();
+                id += 1;
+            }
+        }
+
+        let plan_of = |db: &DB, sql: &str| -> plan::Plan {
+            let parser::Statement::Select(select) = parser::parse(sql).unwrap() else {
+                unreachable!();
+            };
+            return plan::plan_select(db, &select).unwrap();
+        };
+        let join_kind = |plan: &plan::Plan| -> &'static str {
+            let mut plan = plan;
+            loop {
+                match plan {
+                    plan::Plan::IndexJoin { .. } => return "index",
+                    plan::Plan::NestedLoopJoin { .. } => return "nested loop",
+                    plan::Plan::Scan(_) => return "none",
+                    plan::Plan::Filter { input, .. }
+                    | plan::Plan::Aggregate { input, .. }
+                    | plan::Plan::Sort { input, .. }
+                    | plan::Plan::Limit { input, .. }
+                    | plan::Plan::Project { input, .. } => plan = input,
+                }
+            }
+        };
+
+        let by_user = "SELECT u.name, COUNT(*) AS n, SUM(o.total), MAX(total) \
+                       FROM users u JOIN orders o ON o.user = u.id \
+                       WHERE u.id < 8 GROUP BY u.name ORDER BY n DESC, u.name";
+        assert_eq!(join_kind(&plan_of(&db, by_user)), "index");
+        assert_eq!(
+            rows(&mut db, by_user),
+            vec![
+                vec![
+                    Value::Str("u3".to_string()),
+                    Value::Int64(3),
+                    Value::Float64(45.0),
+                    Value::Float64(25.0)
+                ],
+                vec![
+                    Value::Str("u7".to_string()),
+                    Value::Int64(3),
+                    Value::Float64(45.0),
+                    Value::Float64(25.0)
+                ],
+                vec![
+                    Value::Str("u2".to_string()),
+                    Value::Int64(2),
+                    Value::Float64(20.0),
+                    Value::Float64(15.0)
+                ],
+                vec![
+                    Value::Str("u6".to_string()),
+                    Value::Int64(2),
+                    Value::Float64(20.0),
+                    Value::Float64(15.0)
+                ],
+                vec![
+                    Value::Str("u1".to_string()),
+                    Value::Int64(1),
+                    Value::Float64(5.0),
+                    Value::Float64(5.0)
+                ],
+                vec![
+                    Value::Str("u5".to_string()),
+                    Value::Int64(1),
+                    Value::Float64(5.0),
+                    Value::Float64(5.0)
+                ],
+            ]
+        );
+
+        // the join to cities has no key to look up by, the same query over the primary
+        // key of cities does
+        let by_country = "SELECT c.country, COUNT(*), AVG(o.total) FROM orders o \
+                          JOIN users u ON u.id = o.user JOIN cities c ON c.country != u.city \
+                          GROUP BY c.country HAVING COUNT(*) > 1 ORDER BY c.country";
+        assert_eq!(join_kind(&plan_of(&db, by_country)), "nested loop");
+        let by_city = "SELECT c.country, COUNT(*), MIN(o.total) FROM orders o \
+                       JOIN users u ON u.id = o.user JOIN cities c ON c.name = u.city \
+                       GROUP BY c.country HAVING COUNT(*) > 1 ORDER BY c.country";
+        assert_eq!(join_kind(&plan_of(&db, by_city)), "index");
+        assert_eq!(
+            rows(&mut db, by_city),
+            vec![
+                vec![
+                    Value::Str("no".to_string()),
+                    Value::Int64(11),
+                    Value::Float64(5.0)
+                ],
+                vec![
+                    Value::Str("pe".to_string()),
+                    Value::Int64(19),
+                    Value::Float64(5.0)
+                ],
+            ]
+        );
+        assert_eq!(rows(&mut db, by_country).len(), 2);
+
+        // one row without GROUP BY even over no rows, an error where it has no value
+        assert_eq!(
+            rows(&mut db, "SELECT COUNT(*) FROM users WHERE id > 100"),
+            vec![vec![Value::Int64(0)]]
+        );
+        for func in ["SUM", "MIN", "MAX", "AVG"] {
+            let sql = format!("SELECT COUNT(*), {func}(id) FROM users WHERE id > 100");
+            let message = format!("{func} over no rows has no value");
+            assert_eq!(execute(&mut db, &sql), Err(SqlError::Eval(message)));
+        }
+        assert_eq!(
+            rows(
+                &mut db,
+                "SELECT city, COUNT(*) * 10 FROM users GROUP BY city"
+            ),
+            vec![
+                vec![Value::Str("oslo".to_string()), Value::Int64(70)],
+                vec![Value::Str("lima".to_string()), Value::Int64(130)],
+            ]
+        );
+        assert_eq!(
+            execute(&mut db, "SELECT name, COUNT(*) FROM users GROUP BY city"),
+            Err(SqlError::Eval(
+                "column name must appear in GROUP BY or inside an aggregate".to_string()
+            ))
+        );
+        assert_eq!(
+            execute(&mut db, "SELECT id FROM users JOIN orders ON id = user"),
+            Err(SqlError::Eval("column id is ambiguous".to_string()))
+        );
+        assert_eq!(
+            execute(&mut db, "SELECT * FROM users WHERE COUNT(*) > 1"),
+            Err(SqlError::Eval(
+                "aggregates are not allowed in WHERE".to_string()
+            ))
+        );
+    }
+}
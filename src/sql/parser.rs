@@ -0,0 +1,978 @@
+use std::fmt;
+
+use crate::table::{ColumnType, Value};
+
+use super::SqlError;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Token {
+    // identifiers and keywords alike, keywords are matched case-insensitively
+    Word(String),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Bytes(Vec<u8>),
+    Symbol(&'static str),
+}
+
+const SYMBOLS: [&str; 16] = [
+    "<=", ">=", "!=", "<>", "=", "<", ">", "+", "-", "*", "/", "(", ")", ",", ";", ".",
+];
+
+// words that can not be used as table, column or alias names
+const RESERVED: [&str; 33] = [
+    "SELECT", "FROM", "WHERE", "ORDER", "BY", "LIMIT", "OFFSET", "ASC", "DESC", "AS", "AND", "OR",
+    "NOT", "TRUE", "FALSE", "INSERT", "INTO", "VALUES", "UPDATE", "SET", "DELETE", "CREATE",
+    "TABLE", "INDEX", "UNIQUE", "ON", "PRIMARY", "KEY", "INNER", "JOIN", "GROUP", "HAVING",
+    "EXPLAIN",
+];
+
+pub fn tokenize(sql: &str) -> Result<Vec<Token>, SqlError> {
+    let chars: Vec<char> = sql.chars().collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '-' && chars.get(i + 1) == Some(&'-') {
+            // comment to the end of the line
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if (c == 'x' || c == 'X') && chars.get(i + 1) == Some(&'\'') {
+            let (text, next) = quoted(&chars, i + 1)?;
+            tokens.push(Token::Bytes(parse_hex(&text)?));
+            i = next;
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(Token::Word(chars[start..i].iter().collect()));
+        } else if c.is_ascii_digit() {
+            let start = i;
+            let mut float = false;
+            while i < chars.len() && chars[i].is_ascii_digit() {
+                i += 1;
+            }
+            if chars.get(i) == Some(&'.') {
+                float = true;
+                i += 1;
+                while i < chars.len() && chars[i].is_ascii_digit() {
+                    i += 1;
+                }
+            }
+            if matches!(chars.get(i), Some('e') | Some('E')) {
+                float = true;
+                i += 1;
+                if matches!(chars.get(i), Some('+') | Some('-')) {
+                    i += 1;
+                }
+                while i < chars.len() && chars[i].is_ascii_digit() {
+                    i += 1;
+                }
+            }
+            let text: String = chars[start..i].iter().collect();
+            let token = if float {
+                text.parse().map(Token::Float).ok()
+            } else {
+                text.parse().map(Token::Int).ok()
+            };
+            tokens.push(token.ok_or_else(|| SqlError::Syntax(format!("bad number {text}")))?);
+        } else if c == '\'' {
+            let (text, next) = quoted(&chars, i)?;
+            tokens.push(Token::Str(text));
+            i = next;
+        } else {
+            let rest: String = chars[i..chars.len().min(i + 2)].iter().collect();
+            let symbol = SYMBOLS
+                .iter()
+                .find(|symbol| rest.starts_with(**symbol))
+                .ok_or_else(|| SqlError::Syntax(format!("unexpected character {c:?}")))?;
+            tokens.push(Token::Symbol(if *symbol == "<>" { "!=" } else { symbol }));
+            i += symbol.len();
+        }
+    }
+    return Ok(tokens);
+}
+
+// a string in single quotes starting at `start`, with '' standing for a quote
+fn quoted(chars: &[char], start: usize) -> Result<(String, usize), SqlError> {
+    let mut text = String::new();
+    let mut i = start + 1;
+    loop {
+        match chars.get(i) {
+            None => return Err(SqlError::Syntax("unterminated string".to_string())),
+            Some('\'') if chars.get(i + 1) == Some(&'\'') => {
+                text.push('\'');
+                i += 2;
+            }
+            Some('\'') => return Ok((text, i + 1)),
+            Some(c) => {
+                text.push(*c);
+                i += 1;
+            }
+        }
+    }
+}
+
+fn parse_hex(text: &str) -> Result<Vec<u8>, SqlError> {
+    let bad = || SqlError::Syntax(format!("bad hex literal {text:?}"));
+    if !text.len().is_multiple_of(2) || !text.is_ascii() {
+        return Err(bad());
+    }
+    return (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&text[i..i + 2], 16).map_err(|_| bad()))
+        .collect();
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BinaryOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    And,
+    Or,
+}
+impl BinaryOp {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            BinaryOp::Eq => "=",
+            BinaryOp::Ne => "!=",
+            BinaryOp::Lt => "<",
+            BinaryOp::Le => "<=",
+            BinaryOp::Gt => ">",
+            BinaryOp::Ge => ">=",
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::And => "AND",
+            BinaryOp::Or => "OR",
+        }
+    }
+    fn precedence(&self) -> u8 {
+        match self {
+            BinaryOp::Or => 1,
+            BinaryOp::And => 2,
+            BinaryOp::Add | BinaryOp::Sub => 4,
+            BinaryOp::Mul | BinaryOp::Div => 5,
+            _ => 3,
+        }
+    }
+    // the same comparison with its operands swapped, `1 < a` is `a > 1`
+    pub fn flip(&self) -> BinaryOp {
+        match self {
+            BinaryOp::Lt => BinaryOp::Gt,
+            BinaryOp::Le => BinaryOp::Ge,
+            BinaryOp::Gt => BinaryOp::Lt,
+            BinaryOp::Ge => BinaryOp::Le,
+            op => *op,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AggFunc {
+    Count,
+    Sum,
+    Min,
+    Max,
+    Avg,
+}
+impl AggFunc {
+    pub fn from_name(name: &str) -> Option<AggFunc> {
+        match name.to_ascii_uppercase().as_str() {
+            "COUNT" => Some(AggFunc::Count),
+            "SUM" => Some(AggFunc::Sum),
+            "MIN" => Some(AggFunc::Min),
+            "MAX" => Some(AggFunc::Max),
+            "AVG" => Some(AggFunc::Avg),
+            _ => None,
+        }
+    }
+    pub fn name(&self) -> &'static str {
+        match self {
+            AggFunc::Count => "COUNT",
+            AggFunc::Sum => "SUM",
+            AggFunc::Min => "MIN",
+            AggFunc::Max => "MAX",
+            AggFunc::Avg => "AVG",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    // a column name, qualified as `table.column` or not
+    Column(String),
+    Literal(Value),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    // an aggregate over the rows of a group, `COUNT(*)` has no argument
+    Aggregate(AggFunc, Option<Box<Expr>>),
+}
+impl Expr {
+    pub fn has_aggregate(&self) -> bool {
+        match self {
+            Expr::Aggregate(_, _) => return true,
+            Expr::Unary(_, operand) => return operand.has_aggregate(),
+            Expr::Binary(_, left, right) => return left.has_aggregate() || right.has_aggregate(),
+            _ => return false,
+        }
+    }
+}
+
+// SQL literal syntax, so a printed expression parses back to the same expression
+pub fn format_literal(value: &Value) -> String {
+    match value {
+        Value::Int64(v) => v.to_string(),
+        Value::Float64(v) => format!("{v:?}"),
+        Value::Bool(v) => (if *v { "TRUE" } else { "FALSE" }).to_string(),
+        Value::Str(v) => format!("'{}'", v.replace('\'', "''")),
+        Value::Bytes(v) => {
+            let hex: String = v.iter().map(|byte| format!("{byte:02x}")).collect();
+            format!("x'{hex}'")
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Column(name) => write!(f, "{name}"),
+            Expr::Literal(value) => write!(f, "{}", format_literal(value)),
+            Expr::Unary(UnaryOp::Neg, operand) => write!(f, "-{}", Parens(operand, 6)),
+            Expr::Unary(UnaryOp::Not, operand) => write!(f, "NOT {}", Parens(operand, 3)),
+            Expr::Binary(op, left, right) => {
+                let precedence = op.precedence();
+                write!(
+                    f,
+                    "{} {} {}",
+                    Parens(left, precedence),
+                    op.symbol(),
+                    Parens(right, precedence + 1)
+                )
+            }
+            Expr::Aggregate(func, None) => write!(f, "{}(*)", func.name()),
+            Expr::Aggregate(func, Some(arg)) => write!(f, "{}({arg})", func.name()),
+        }
+    }
+}
+
+// prints an operand, in parentheses when it binds looser than `precedence`
+struct Parens<'a>(&'a Expr, u8);
+impl fmt::Display for Parens<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let precedence = match self.0 {
+            Expr::Binary(op, _, _) => op.precedence(),
+            Expr::Unary(UnaryOp::Not, _) => 3,
+            _ => 7,
+        };
+        if precedence < self.1 {
+            return write!(f, "({})", self.0);
+        }
+        return write!(f, "{}", self.0);
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct OrderBy {
+    pub expr: Expr,
+    pub desc: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SelectItem {
+    // `*`, every column of the input
+    Star,
+    // an expression with an optional `AS` name
+    Expr(Expr, Option<String>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TableRef {
+    pub table: String,
+    pub alias: Option<String>,
+}
+impl TableRef {
+    // the name the columns of the table are qualified with
+    pub fn name(&self) -> &str {
+        return self.alias.as_deref().unwrap_or(&self.table);
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Join {
+    pub table: TableRef,
+    pub on: Expr,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Select {
+    pub items: Vec<SelectItem>,
+    pub from: TableRef,
+    pub joins: Vec<Join>,
+    pub filter: Option<Expr>,
+    pub group_by: Vec<Expr>,
+    pub having: Option<Expr>,
+    pub order_by: Vec<OrderBy>,
+    pub limit: Option<u64>,
+    pub offset: u64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Statement {
+    CreateTable {
+        name: String,
+        columns: Vec<(String, ColumnType)>,
+        primary_key: Vec<String>,
+    },
+    CreateIndex {
+        name: String,
+        table: String,
+        columns: Vec<String>,
+        unique: bool,
+    },
+    Insert {
+        table: String,
+        // the columns the values are given for, all of them in table order when missing
+        columns: Option<Vec<String>>,
+        rows: Vec<Vec<Expr>>,
+    },
+    Select(Select),
+    Update {
+        table: String,
+        assignments: Vec<(String, Expr)>,
+        filter: Option<Expr>,
+    },
+    Delete {
+        table: String,
+        filter: Option<Expr>,
+    },
+    // the plan of a SELECT, UPDATE or DELETE instead of its result
+    Explain(Box<Statement>),
+}
+
+// parses one statement, optionally followed by a semicolon
+pub fn parse(sql: &str) -> Result<Statement, SqlError> {
+    let mut parser = Parser {
+        tokens: tokenize(sql)?,
+        pos: 0,
+    };
+    let statement = parser.statement()?;
+    parser.symbol(";");
+    if let Some(token) = parser.peek() {
+        return Err(SqlError::Syntax(format!(
+            "unexpected {token:?} after statement"
+        )));
+    }
+    return Ok(statement);
+}
+
+pub struct Parser {
+    pub tokens: Vec<Token>,
+    pub pos: usize,
+}
+impl Parser {
+    pub fn peek(&self) -> Option<&Token> {
+        return self.tokens.get(self.pos);
+    }
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        return token;
+    }
+    fn unexpected<T>(&self, expected: &str) -> Result<T, SqlError> {
+        match self.peek() {
+            Some(token) => Err(SqlError::Syntax(format!(
+                "expected {expected}, found {token:?}"
+            ))),
+            None => Err(SqlError::Syntax(format!(
+                "expected {expected}, found the end of input"
+            ))),
+        }
+    }
+    pub fn is_keyword(&self, keyword: &str) -> bool {
+        return matches!(self.peek(), Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword));
+    }
+    // consumes `keyword` if it comes next
+    pub fn keyword(&mut self, keyword: &str) -> bool {
+        if self.is_keyword(keyword) {
+            self.pos += 1;
+            return true;
+        }
+        return false;
+    }
+    pub fn expect_keyword(&mut self, keyword: &str) -> Result<(), SqlError> {
+        if !self.keyword(keyword) {
+            return self.unexpected(keyword);
+        }
+        return Ok(());
+    }
+    pub fn symbol(&mut self, symbol: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Symbol(next)) if *next == symbol) {
+            self.pos += 1;
+            return true;
+        }
+        return false;
+    }
+    pub fn expect_symbol(&mut self, symbol: &str) -> Result<(), SqlError> {
+        if !self.symbol(symbol) {
+            return self.unexpected(&format!("{symbol:?}"));
+        }
+        return Ok(());
+    }
+    fn is_name(&self) -> bool {
+        return matches!(self.peek(), Some(Token::Word(word))
+            if !RESERVED.iter().any(|reserved| word.eq_ignore_ascii_case(reserved)));
+    }
+    pub fn name(&mut self) -> Result<String, SqlError> {
+        match self.peek() {
+            Some(Token::Word(word)) if self.is_name() => {
+                let word = word.clone();
+                self.pos += 1;
+                return Ok(word);
+            }
+            _ => return self.unexpected("a name"),
+        }
+    }
+    // one or more comma separated items of `item`
+    pub fn list<T>(
+        &mut self,
+        mut item: impl FnMut(&mut Parser) -> Result<T, SqlError>,
+    ) -> Result<Vec<T>, SqlError> {
+        let mut items = vec![item(self)?];
+        while self.symbol(",") {
+            items.push(item(self)?);
+        }
+        return Ok(items);
+    }
+    fn names_in_parens(&mut self) -> Result<Vec<String>, SqlError> {
+        self.expect_symbol("(")?;
+        let names = self.list(Parser::name)?;
+        self.expect_symbol(")")?;
+        return Ok(names);
+    }
+
+    pub fn statement(&mut self) -> Result<Statement, SqlError> {
+        if self.keyword("EXPLAIN") {
+            if !["SELECT", "UPDATE", "DELETE"]
+                .iter()
+                .any(|keyword| self.is_keyword(keyword))
+            {
+                return self.unexpected("SELECT, UPDATE or DELETE");
+            }
+            return Ok(Statement::Explain(Box::new(self.statement()?)));
+        }
+        if self.keyword("CREATE") {
+            let unique = self.keyword("UNIQUE");
+            if unique || self.is_keyword("INDEX") {
+                return self.create_index(unique);
+            }
+            return self.create_table();
+        }
+        if self.keyword("INSERT") {
+            return self.insert();
+        }
+        if self.is_keyword("SELECT") {
+            return Ok(Statement::Select(self.select()?));
+        }
+        if self.keyword("UPDATE") {
+            return self.update();
+        }
+        if self.keyword("DELETE") {
+            self.expect_keyword("FROM")?;
+            let table = self.name()?;
+            let filter = self.filter()?;
+            return Ok(Statement::Delete { table, filter });
+        }
+        return self.unexpected("a statement");
+    }
+    // CREATE TABLE name (column type [PRIMARY KEY], ..., [PRIMARY KEY (columns)])
+    fn create_table(&mut self) -> Result<Statement, SqlError> {
+        self.expect_keyword("TABLE")?;
+        let name = self.name()?;
+        let mut columns = vec![];
+        let mut primary_key = vec![];
+        self.expect_symbol("(")?;
+        loop {
+            if self.keyword("PRIMARY") {
+                self.expect_keyword("KEY")?;
+                primary_key.extend(self.names_in_parens()?);
+            } else {
+                let column = self.name()?;
+                columns.push((column.clone(), self.column_type()?));
+                if self.keyword("PRIMARY") {
+                    self.expect_keyword("KEY")?;
+                    primary_key.push(column);
+                }
+            }
+            if !self.symbol(",") {
+                break;
+            }
+        }
+        self.expect_symbol(")")?;
+        return Ok(Statement::CreateTable {
+            name,
+            columns,
+            primary_key,
+        });
+    }
+    fn column_type(&mut self) -> Result<ColumnType, SqlError> {
+        let column_type = match self.peek() {
+            Some(Token::Word(word)) => match word.to_ascii_uppercase().as_str() {
+                "INT" | "INTEGER" | "BIGINT" | "INT64" => Some(ColumnType::Int64),
+                "FLOAT" | "DOUBLE" | "REAL" | "FLOAT64" => Some(ColumnType::Float64),
+                "BOOL" | "BOOLEAN" => Some(ColumnType::Bool),
+                "TEXT" | "STRING" | "VARCHAR" => Some(ColumnType::Str),
+                "BYTES" | "BLOB" => Some(ColumnType::Bytes),
+                _ => None,
+            },
+            _ => None,
+        };
+        match column_type {
+            Some(column_type) => {
+                self.pos += 1;
+                return Ok(column_type);
+            }
+            None => return self.unexpected("a column type"),
+        }
+    }
+    // CREATE [UNIQUE] INDEX name ON table (columns)
+    fn create_index(&mut self, unique: bool) -> Result<Statement, SqlError> {
+        self.expect_keyword("INDEX")?;
+        let name = self.name()?;
+        self.expect_keyword("ON")?;
+        let table = self.name()?;
+        let columns = self.names_in_parens()?;
+        return Ok(Statement::CreateIndex {
+            name,
+            table,
+            columns,
+            unique,
+        });
+    }
+    // INSERT INTO table [(columns)] VALUES (exprs), ...
+    fn insert(&mut self) -> Result<Statement, SqlError> {
+        self.expect_keyword("INTO")?;
+        let table = self.name()?;
+        let mut columns = None;
+        if matches!(self.peek(), Some(Token::Symbol("("))) {
+            columns = Some(self.names_in_parens()?);
+        }
+        self.expect_keyword("VALUES")?;
+        let rows = self.list(|parser| {
+            parser.expect_symbol("(")?;
+            let row = parser.list(Parser::expr)?;
+            parser.expect_symbol(")")?;
+            return Ok(row);
+        })?;
+        return Ok(Statement::Insert {
+            table,
+            columns,
+            rows,
+        });
+    }
+    // SELECT items FROM table [alias] [[INNER] JOIN table [alias] ON expr]... [WHERE expr]
+    // [GROUP BY exprs [HAVING expr]] [ORDER BY expr [ASC|DESC], ...] [LIMIT n [OFFSET n]]
+    pub fn select(&mut self) -> Result<Select, SqlError> {
+        self.expect_keyword("SELECT")?;
+        let items = self.list(|parser| {
+            if parser.symbol("*") {
+                return Ok(SelectItem::Star);
+            }
+            let expr = parser.expr()?;
+            let mut alias = None;
+            if parser.keyword("AS") {
+                alias = Some(parser.name()?);
+            }
+            return Ok(SelectItem::Expr(expr, alias));
+        })?;
+        self.expect_keyword("FROM")?;
+        let from = self.table_ref()?;
+        let mut joins = vec![];
+        loop {
+            if self.keyword("INNER") {
+                self.expect_keyword("JOIN")?;
+            } else if !self.keyword("JOIN") {
+                break;
+            }
+            let table = self.table_ref()?;
+            self.expect_keyword("ON")?;
+            joins.push(Join {
+                table,
+                on: self.expr()?,
+            });
+        }
+        let filter = self.filter()?;
+        let mut group_by = vec![];
+        let mut having = None;
+        if self.keyword("GROUP") {
+            self.expect_keyword("BY")?;
+            group_by = self.list(Parser::expr)?;
+            if self.keyword("HAVING") {
+                having = Some(self.expr()?);
+            }
+        }
+        let mut order_by = vec![];
+        if self.keyword("ORDER") {
+            self.expect_keyword("BY")?;
+            order_by = self.list(|parser| {
+                let expr = parser.expr()?;
+                let desc = parser.keyword("DESC");
+                if !desc {
+                    parser.keyword("ASC");
+                }
+                return Ok(OrderBy { expr, desc });
+            })?;
+        }
+        let mut limit = None;
+        let mut offset = 0;
+        if self.keyword("LIMIT") {
+            limit = Some(self.count()?);
+            if self.keyword("OFFSET") {
+                offset = self.count()?;
+            }
+        }
+        return Ok(Select {
+            items,
+            from,
+            joins,
+            filter,
+            group_by,
+            having,
+            order_by,
+            limit,
+            offset,
+        });
+    }
+    fn table_ref(&mut self) -> Result<TableRef, SqlError> {
+        let table = self.name()?;
+        let mut alias = None;
+        if self.keyword("AS") || self.is_name() {
+            alias = Some(self.name()?);
+        }
+        return Ok(TableRef { table, alias });
+    }
+    fn count(&mut self) -> Result<u64, SqlError> {
+        match self.peek() {
+            Some(Token::Int(n)) if *n >= 0 => {
+                let n = *n as u64;
+                self.pos += 1;
+                return Ok(n);
+            }
+            _ => return self.unexpected("a row count"),
+        }
+    }
+    // UPDATE table SET column = expr, ... [WHERE expr]
+    fn update(&mut self) -> Result<Statement, SqlError> {
+        let table = self.name()?;
+        self.expect_keyword("SET")?;
+        let assignments = self.list(|parser| {
+            let column = parser.name()?;
+            parser.expect_symbol("=")?;
+            return Ok((column, parser.expr()?));
+        })?;
+        let filter = self.filter()?;
+        return Ok(Statement::Update {
+            table,
+            assignments,
+            filter,
+        });
+    }
+    fn filter(&mut self) -> Result<Option<Expr>, SqlError> {
+        if self.keyword("WHERE") {
+            return Ok(Some(self.expr()?));
+        }
+        return Ok(None);
+    }
+
+    // expressions, loosest binding first: OR, AND, NOT, comparisons, + -, * /, unary minus
+    pub fn expr(&mut self) -> Result<Expr, SqlError> {
+        let mut left = self.and()?;
+        while self.keyword("OR") {
+            left = Expr::Binary(BinaryOp::Or, Box::new(left), Box::new(self.and()?));
+        }
+        return Ok(left);
+    }
+    fn and(&mut self) -> Result<Expr, SqlError> {
+        let mut left = self.not()?;
+        while self.keyword("AND") {
+            left = Expr::Binary(BinaryOp::And, Box::new(left), Box::new(self.not()?));
+        }
+        return Ok(left);
+    }
+    fn not(&mut self) -> Result<Expr, SqlError> {
+        if self.keyword("NOT") {
+            return Ok(Expr::Unary(UnaryOp::Not, Box::new(self.not()?)));
+        }
+        return self.comparison();
+    }
+    fn comparison(&mut self) -> Result<Expr, SqlError> {
+        let left = self.additive()?;
+        let op = match self.peek() {
+            Some(Token::Symbol("=")) => BinaryOp::Eq,
+            Some(Token::Symbol("!=")) => BinaryOp::Ne,
+            Some(Token::Symbol("<")) => BinaryOp::Lt,
+            Some(Token::Symbol("<=")) => BinaryOp::Le,
+            Some(Token::Symbol(">")) => BinaryOp::Gt,
+            Some(Token::Symbol(">=")) => BinaryOp::Ge,
+            _ => return Ok(left),
+        };
+        self.pos += 1;
+        return Ok(Expr::Binary(op, Box::new(left), Box::new(self.additive()?)));
+    }
+    fn additive(&mut self) -> Result<Expr, SqlError> {
+        let mut left = self.multiplicative()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Symbol("+")) => BinaryOp::Add,
+                Some(Token::Symbol("-")) => BinaryOp::Sub,
+                _ => return Ok(left),
+            };
+            self.pos += 1;
+            left = Expr::Binary(op, Box::new(left), Box::new(self.multiplicative()?));
+        }
+    }
+    fn multiplicative(&mut self) -> Result<Expr, SqlError> {
+        let mut left = self.unary()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Symbol("*")) => BinaryOp::Mul,
+                Some(Token::Symbol("/")) => BinaryOp::Div,
+                _ => return Ok(left),
+            };
+            self.pos += 1;
+            left = Expr::Binary(op, Box::new(left), Box::new(self.unary()?));
+        }
+    }
+    fn unary(&mut self) -> Result<Expr, SqlError> {
+        if self.symbol("-") {
+            // negative numbers stay literals, so the planner can use them as key bounds
+            match self.unary()? {
+                Expr::Literal(Value::Int64(v)) => return Ok(Expr::Literal(Value::Int64(-v))),
+                Expr::Literal(Value::Float64(v)) => {
+                    return Ok(Expr::Literal(Value::Float64(-v)));
+                }
+                operand => return Ok(Expr::Unary(UnaryOp::Neg, Box::new(operand))),
+            }
+        }
+        return self.primary();
+    }
+    pub fn primary(&mut self) -> Result<Expr, SqlError> {
+        if self.keyword("TRUE") {
+            return Ok(Expr::Literal(Value::Bool(true)));
+        }
+        if self.keyword("FALSE") {
+            return Ok(Expr::Literal(Value::Bool(false)));
+        }
+        if self.symbol("(") {
+            let expr = self.expr()?;
+            self.expect_symbol(")")?;
+            return Ok(expr);
+        }
+        let literal = match self.peek() {
+            Some(Token::Int(v)) => Value::Int64(*v),
+            Some(Token::Float(v)) => Value::Float64(*v),
+            Some(Token::Str(v)) => Value::Str(v.clone()),
+            Some(Token::Bytes(v)) => Value::Bytes(v.clone()),
+            _ => {
+                let name = self.name()?;
+                if let Some(func) = AggFunc::from_name(&name) {
+                    if self.symbol("(") {
+                        let mut arg = None;
+                        if func != AggFunc::Count || !self.symbol("*") {
+                            arg = Some(Box::new(self.expr()?));
+                        }
+                        self.expect_symbol(")")?;
+                        return Ok(Expr::Aggregate(func, arg));
+                    }
+                }
+                if self.symbol(".") {
+                    return Ok(Expr::Column(format!("{name}.{}", self.name()?)));
+                }
+                return Ok(Expr::Column(name));
+            }
+        };
+        self.next();
+        return Ok(Expr::Literal(literal));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn column(name: &str) -> Box<Expr> {
+        return Box::new(Expr::Column(name.to_string()));
+    }
+
+    #[test]
+    fn statements_parse_into_an_ast() {
+        let statement = parse(
+            "select a, b + 1 as c from t where a >= -5 and not (b = 'it''s' or c < 2.5) \
+             order by b desc, a limit 10 offset 2;",
+        )
+        .unwrap();
+        let filter = Expr::Binary(
+            BinaryOp::And,
+            Box::new(Expr::Binary(
+                BinaryOp::Ge,
+                column("a"),
+                Box::new(Expr::Literal(Value::Int64(-5))),
+            )),
+            Box::new(Expr::Unary(
+                UnaryOp::Not,
+                Box::new(Expr::Binary(
+                    BinaryOp::Or,
+                    Box::new(Expr::Binary(
+                        BinaryOp::Eq,
+                        column("b"),
+                        Box::new(Expr::Literal(Value::Str("it's".to_string()))),
+                    )),
+                    Box::new(Expr::Binary(
+                        BinaryOp::Lt,
+                        column("c"),
+                        Box::new(Expr::Literal(Value::Float64(2.5))),
+                    )),
+                )),
+            )),
+        );
+        assert_eq!(
+            statement,
+            Statement::Select(Select {
+                items: vec![
+                    SelectItem::Expr(*column("a"), None),
+                    SelectItem::Expr(
+                        Expr::Binary(
+                            BinaryOp::Add,
+                            column("b"),
+                            Box::new(Expr::Literal(Value::Int64(1)))
+                        ),
+                        Some("c".to_string())
+                    ),
+                ],
+                from: TableRef {
+                    table: "t".to_string(),
+                    alias: None
+                },
+                joins: vec![],
+                filter: Some(filter.clone()),
+                group_by: vec![],
+                having: None,
+                order_by: vec![
+                    OrderBy {
+                        expr: *column("b"),
+                        desc: true
+                    },
+                    OrderBy {
+                        expr: *column("a"),
+                        desc: false
+                    },
+                ],
+                limit: Some(10),
+                offset: 2,
+            })
+        );
+        // printing puts back only the parentheses that matter
+        assert_eq!(
+            filter.to_string(),
+            "a >= -5 AND NOT (b = 'it''s' OR c < 2.5)"
+        );
+        assert_eq!(
+            parse("SELECT * FROM t WHERE a - (b - c) * 2 = x'00ff'").unwrap(),
+            parse("SELECT * FROM t WHERE (a - ((b - c) * 2)) = X'00FF'").unwrap()
+        );
+
+        let statement = parse(
+            "SELECT u.name, COUNT(*), sum(o.total) FROM users u JOIN orders AS o \
+             ON o.user = u.id GROUP BY u.name HAVING COUNT(*) > 1",
+        );
+        let Ok(Statement::Select(select)) = statement else {
+            panic!("{statement:?}");
+        };
+        assert_eq!(
+            select.joins,
+            vec![Join {
+                table: TableRef {
+                    table: "orders".to_string(),
+                    alias: Some("o".to_string())
+                },
+                on: Expr::Binary(BinaryOp::Eq, column("o.user"), column("u.id")),
+            }]
+        );
+        assert_eq!(select.from.name(), "u");
+        assert_eq!(select.group_by, vec![*column("u.name")]);
+        let items: Vec<String> = select
+            .items
+            .iter()
+            .map(|item| match item {
+                SelectItem::Expr(expr, _) => expr.to_string(),
+                SelectItem::Star => "*".to_string(),
+            })
+            .collect();
+        assert_eq!(items, vec!["u.name", "COUNT(*)", "SUM(o.total)"]);
+        assert_eq!(select.having.unwrap().to_string(), "COUNT(*) > 1");
+
+        assert_eq!(
+            parse("CREATE TABLE t (id INT PRIMARY KEY, body BLOB, at DOUBLE)").unwrap(),
+            Statement::CreateTable {
+                name: "t".to_string(),
+                columns: vec![
+                    ("id".to_string(), ColumnType::Int64),
+                    ("body".to_string(), ColumnType::Bytes),
+                    ("at".to_string(), ColumnType::Float64),
+                ],
+                primary_key: vec!["id".to_string()],
+            }
+        );
+        assert_eq!(
+            parse("create unique index i on t (a, b)").unwrap(),
+            Statement::CreateIndex {
+                name: "i".to_string(),
+                table: "t".to_string(),
+                columns: vec!["a".to_string(), "b".to_string()],
+                unique: true,
+            }
+        );
+        assert_eq!(
+            parse("DELETE FROM t"),
+            Ok(Statement::Delete {
+                table: "t".to_string(),
+                filter: None
+            })
+        );
+
+        for bad in [
+            "SELECT * FROM select",
+            "SELECT a FROM t WHERE",
+            "SELECT a FROM t LIMIT -1",
+            "INSERT INTO t VALUES (1",
+            "SELECT 'open FROM t",
+            "SELECT a FROM t; SELECT b FROM t",
+            "CREATE TABLE t (a NUMBER)",
+            "SELECT SUM(*) FROM t",
+            "SELECT a FROM t JOIN u",
+            "EXPLAIN INSERT INTO t VALUES (1)",
+            "EXPLAIN EXPLAIN SELECT a FROM t",
+        ] {
+            assert!(matches!(parse(bad), Err(SqlError::Syntax(_))), "{bad}");
+        }
+    }
+}
@@ -0,0 +1,729 @@
+use std::ops::Bound;
+
+use crate::table::{TableDef, TableError, Value, DB};
+
+use super::{
+    exec::coerce,
+    parser::{BinaryOp, Expr, OrderBy, Select, SelectItem},
+    SqlError,
+};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ScanKind {
+    // the table tree itself, in primary key order
+    PrimaryKey,
+    // the named index tree, each entry followed by a lookup of its row
+    Index(String),
+    Full,
+}
+
+// start and end bounds of a range scan over encoded keys
+pub type KeyRange = (Bound<Vec<u8>>, Bound<Vec<u8>>);
+
+// a range scan over the key columns of the primary key or of an index: the leading key
+// columns are fixed to `eq`, the next one is limited to `low..high`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Scan {
+    pub table: TableDef,
+    // the name the columns are qualified with, `alias.column`
+    pub alias: String,
+    pub kind: ScanKind,
+    pub eq: Vec<Value>,
+    pub low: Bound<Value>,
+    pub high: Bound<Value>,
+}
+impl Scan {
+    pub fn columns(&self) -> Vec<String> {
+        return self
+            .table
+            .columns
+            .iter()
+            .map(|column| format!("{}.{}", self.alias, column.name))
+            .collect();
+    }
+    // names of the key columns the scan runs over, none for a full scan
+    pub fn key_columns(&self) -> &[String] {
+        match &self.kind {
+            ScanKind::PrimaryKey => return &self.table.primary_key,
+            ScanKind::Index(name) => return &self.table.index(name).unwrap().columns,
+            ScanKind::Full => return &[],
+        }
+    }
+    // the bounds passed to the range scan of the tree, None when no key can match
+    pub fn key_range(&self) -> Option<KeyRange> {
+        let mut prefix = vec![];
+        for value in &self.eq {
+            value.encode(&mut prefix);
+        }
+        let with = |value: &Value| -> Vec<u8> {
+            let mut key = prefix.clone();
+            value.encode(&mut key);
+            return key;
+        };
+        // keys are the encoded key columns followed by more bytes, so a bound on a
+        // column value that includes everything starting with it goes past that prefix
+        let start = match &self.low {
+            Bound::Unbounded if prefix.is_empty() => Bound::Unbounded,
+            Bound::Unbounded => Bound::Included(prefix.clone()),
+            Bound::Included(value) => Bound::Included(with(value)),
+            Bound::Excluded(value) => Bound::Included(prefix_end(&with(value))?),
+        };
+        let end = match &self.high {
+            Bound::Unbounded => prefix_end(&prefix).map_or(Bound::Unbounded, Bound::Excluded),
+            Bound::Included(value) => {
+                prefix_end(&with(value)).map_or(Bound::Unbounded, Bound::Excluded)
+            }
+            Bound::Excluded(value) => Bound::Excluded(with(value)),
+        };
+        return Some((start, end));
+    }
+}
+
+// the smallest key greater than every key starting with `prefix`, None if there is none
+pub fn prefix_end(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < 0xff {
+            end.push(last + 1);
+            return Some(end);
+        }
+    }
+    return None;
+}
+
+// a tree of operators, each pulling rows from its input
+#[derive(Clone, Debug, PartialEq)]
+pub enum Plan {
+    Scan(Scan),
+    // every row of `left` joined with every row of `right` that satisfies `on`
+    NestedLoopJoin {
+        input: Box<Plan>,
+        right: Box<Plan>,
+        on: Expr,
+    },
+    // for every row of `left`, `keys` evaluated on it fix the leading key columns of the
+    // scan over `right`, so only the rows of `right` that can match are read
+    IndexJoin {
+        input: Box<Plan>,
+        right: Scan,
+        keys: Vec<Expr>,
+        on: Expr,
+    },
+    Filter {
+        input: Box<Plan>,
+        predicate: Expr,
+    },
+    // one row per distinct value of `group_by`, holding the group values followed by the
+    // aggregates over the rows of the group
+    Aggregate {
+        input: Box<Plan>,
+        group_by: Vec<Expr>,
+        aggregates: Vec<Expr>,
+    },
+    Sort {
+        input: Box<Plan>,
+        order_by: Vec<OrderBy>,
+    },
+    Limit {
+        input: Box<Plan>,
+        offset: u64,
+        limit: Option<u64>,
+    },
+    Project {
+        input: Box<Plan>,
+        exprs: Vec<Expr>,
+        names: Vec<String>,
+    },
+}
+impl Plan {
+    // names of the columns in the rows the plan produces
+    pub fn columns(&self) -> Vec<String> {
+        match self {
+            Plan::Scan(scan) => scan.columns(),
+            Plan::NestedLoopJoin { input, right, .. } => {
+                let mut columns = input.columns();
+                columns.extend(right.columns());
+                columns
+            }
+            Plan::IndexJoin { input, right, .. } => {
+                let mut columns = input.columns();
+                columns.extend(right.columns());
+                columns
+            }
+            Plan::Aggregate {
+                group_by,
+                aggregates,
+                ..
+            } => group_by
+                .iter()
+                .chain(aggregates)
+                .map(|expr| expr.to_string())
+                .collect(),
+            Plan::Filter { input, .. } | Plan::Sort { input, .. } | Plan::Limit { input, .. } => {
+                input.columns()
+            }
+            Plan::Project { names, .. } => names.clone(),
+        }
+    }
+}
+
+// a conjunct of the WHERE clause comparing a column with a constant
+struct KeyCond<'a> {
+    column: &'a str,
+    op: BinaryOp,
+    value: &'a Value,
+}
+
+// the terms of `expr` joined by AND
+fn conjuncts<'a>(expr: &'a Expr, out: &mut Vec<&'a Expr>) {
+    match expr {
+        Expr::Binary(BinaryOp::And, left, right) => {
+            conjuncts(left, out);
+            conjuncts(right, out);
+        }
+        _ => out.push(expr),
+    }
+}
+
+fn key_conds(expr: &Expr) -> Vec<KeyCond<'_>> {
+    let mut terms = vec![];
+    conjuncts(expr, &mut terms);
+    let mut conds = vec![];
+    for term in terms {
+        if let Expr::Binary(op, left, right) = term {
+            match (left.as_ref(), right.as_ref()) {
+                (Expr::Column(column), Expr::Literal(value)) => conds.push(KeyCond {
+                    column,
+                    op: *op,
+                    value,
+                }),
+                (Expr::Literal(value), Expr::Column(column)) => conds.push(KeyCond {
+                    column,
+                    op: op.flip(),
+                    value,
+                }),
+                _ => {}
+            }
+        }
+    }
+    return conds;
+}
+
+// whether `name`, qualified or not, refers to `column` of the table named `alias`
+fn names_column(name: &str, alias: &str, column: &str) -> bool {
+    return name == column
+        || name
+            .strip_prefix(alias)
+            .and_then(|rest| rest.strip_prefix('.'))
+            == Some(column);
+}
+
+// fixes as many leading `key_columns` as the conditions allow
+fn match_key(
+    def: &TableDef,
+    alias: &str,
+    key_columns: &[String],
+    conds: &[KeyCond],
+) -> (Vec<Value>, Bound<Value>, Bound<Value>) {
+    let mut eq = vec![];
+    let mut low = Bound::Unbounded;
+    let mut high = Bound::Unbounded;
+    for name in key_columns {
+        let column_type = def.columns[def.column_index(name).unwrap()].column_type;
+        let usable = conds.iter().filter_map(|cond| {
+            let value = coerce(cond.value.clone(), column_type);
+            if !names_column(cond.column, alias, name) || value.column_type() != column_type {
+                return None;
+            }
+            return Some((cond.op, value));
+        });
+        let usable: Vec<(BinaryOp, Value)> = usable.collect();
+        if let Some((_, value)) = usable.iter().find(|(op, _)| *op == BinaryOp::Eq) {
+            eq.push(value.clone());
+            continue;
+        }
+        for (op, value) in usable {
+            match op {
+                BinaryOp::Gt if low == Bound::Unbounded => low = Bound::Excluded(value),
+                BinaryOp::Ge if low == Bound::Unbounded => low = Bound::Included(value),
+                BinaryOp::Lt if high == Bound::Unbounded => high = Bound::Excluded(value),
+                BinaryOp::Le if high == Bound::Unbounded => high = Bound::Included(value),
+                _ => {}
+            }
+        }
+        break;
+    }
+    return (eq, low, high);
+}
+
+// picks the primary key or the index whose leading columns the WHERE clause pins down
+// best, each equality counting for more than a range. The WHERE clause itself is still
+// applied to every row the scan yields.
+pub fn plan_scan(def: &TableDef, alias: &str, filter: Option<&Expr>) -> Scan {
+    let conds = filter.map(key_conds).unwrap_or_default();
+    let score = |(eq, low, high): &(Vec<Value>, Bound<Value>, Bound<Value>)| -> usize {
+        let range = *low != Bound::Unbounded || *high != Bound::Unbounded;
+        return eq.len() * 2 + range as usize;
+    };
+    let mut best = (ScanKind::Full, (vec![], Bound::Unbounded, Bound::Unbounded));
+    let mut best_score = 0;
+    let pkey = match_key(def, alias, &def.primary_key, &conds);
+    if score(&pkey) > 0 {
+        best_score = score(&pkey);
+        // a point lookup on the primary key can not be beaten
+        let point = pkey.0.len() == def.primary_key.len();
+        best = (ScanKind::PrimaryKey, pkey);
+        if point {
+            best_score = usize::MAX;
+        }
+    }
+    for index in &def.indexes {
+        let key = match_key(def, alias, &index.columns, &conds);
+        if score(&key) > best_score {
+            best_score = score(&key);
+            best = (ScanKind::Index(index.name.clone()), key);
+        }
+    }
+    let (kind, (eq, low, high)) = best;
+    return Scan {
+        table: def.clone(),
+        alias: alias.to_string(),
+        kind,
+        eq,
+        low,
+        high,
+    };
+}
+
+// the position of the column `name` refers to: an exact match, or for an unqualified
+// name the one column of that name whatever table it comes from
+pub fn resolve(columns: &[String], name: &str) -> Result<usize, SqlError> {
+    if let Some(i) = columns.iter().position(|column| column == name) {
+        return Ok(i);
+    }
+    let mut found = None;
+    if !name.contains('.') {
+        for (i, column) in columns.iter().enumerate() {
+            if column.rsplit_once('.').map(|(_, column)| column) == Some(name) {
+                if found.is_some() {
+                    return Err(SqlError::Eval(format!("column {name} is ambiguous")));
+                }
+                found = Some(i);
+            }
+        }
+    }
+    return found.ok_or_else(|| TableError::NoSuchColumn(name.to_string()).into());
+}
+
+// fails on the first column `expr` uses that is not among `columns`
+pub fn check_columns(expr: &Expr, columns: &[String]) -> Result<(), SqlError> {
+    match expr {
+        Expr::Column(name) => return resolve(columns, name).map(|_| ()),
+        Expr::Literal(_) | Expr::Aggregate(_, None) => return Ok(()),
+        Expr::Unary(_, operand) | Expr::Aggregate(_, Some(operand)) => {
+            return check_columns(operand, columns);
+        }
+        Expr::Binary(_, left, right) => {
+            check_columns(left, columns)?;
+            return check_columns(right, columns);
+        }
+    }
+}
+
+fn check_filter(filter: &Expr, columns: &[String]) -> Result<(), SqlError> {
+    if filter.has_aggregate() {
+        return Err(SqlError::Eval(
+            "aggregates are not allowed in WHERE".to_string(),
+        ));
+    }
+    return check_columns(filter, columns);
+}
+
+// a scan of the table with the WHERE clause applied on top
+pub fn plan_filter(db: &DB, table: &str, filter: Option<&Expr>) -> Result<Plan, SqlError> {
+    let def = db.get_table(table)?;
+    let mut plan = Plan::Scan(plan_scan(&def, table, filter));
+    if let Some(filter) = filter {
+        check_filter(filter, &plan.columns())?;
+        plan = Plan::Filter {
+            input: Box::new(plan),
+            predicate: filter.clone(),
+        };
+    }
+    return Ok(plan);
+}
+
+// the key of `def` whose leading columns the equalities `right column = expression over
+// the left rows` of a join condition fix best, with those expressions in key order
+fn join_key(
+    def: &TableDef,
+    alias: &str,
+    on: &Expr,
+    left_columns: &[String],
+) -> Option<(ScanKind, Vec<Expr>)> {
+    let mut terms = vec![];
+    conjuncts(on, &mut terms);
+    let mut pairs = vec![];
+    for term in terms {
+        if let Expr::Binary(BinaryOp::Eq, a, b) = term {
+            for (column, other) in [(a, b), (b, a)] {
+                let Expr::Column(name) = column.as_ref() else {
+                    continue;
+                };
+                let Some(column) = def.columns.iter().find(|column| {
+                    return names_column(name, alias, &column.name)
+                        && (name.contains('.') || resolve(left_columns, name).is_err());
+                }) else {
+                    continue;
+                };
+                if !other.has_aggregate() && check_columns(other, left_columns).is_ok() {
+                    pairs.push((column.name.clone(), other.as_ref().clone()));
+                }
+            }
+        }
+    }
+    let keys_for = |key_columns: &[String]| -> Vec<Expr> {
+        let mut keys = vec![];
+        for name in key_columns {
+            match pairs.iter().find(|(column, _)| column == name) {
+                Some((_, expr)) => keys.push(expr.clone()),
+                None => break,
+            }
+        }
+        return keys;
+    };
+    let mut best = (ScanKind::PrimaryKey, keys_for(&def.primary_key));
+    for index in &def.indexes {
+        let keys = keys_for(&index.columns);
+        if keys.len() > best.1.len() {
+            best = (ScanKind::Index(index.name.clone()), keys);
+        }
+    }
+    if best.1.is_empty() {
+        return None;
+    }
+    return Some(best);
+}
+
+// the scans of the FROM clause joined left to right. A join looks up the matching rows
+// of the right table through its primary key or an index when the ON clause fixes their
+// leading columns, and scans the right table again for each left row otherwise.
+fn plan_from(db: &DB, select: &Select) -> Result<Plan, SqlError> {
+    let filter = select.filter.as_ref();
+    let def = db.get_table(&select.from.table)?;
+    let mut plan = Plan::Scan(plan_scan(&def, select.from.name(), filter));
+    for join in &select.joins {
+        let def = db.get_table(&join.table.table)?;
+        let alias = join.table.name();
+        let left_columns = plan.columns();
+        let prefix = format!("{alias}.");
+        if left_columns
+            .iter()
+            .any(|column| column.starts_with(&prefix))
+        {
+            return Err(SqlError::Eval(format!(
+                "table name {alias} is used twice, give one of them an alias"
+            )));
+        }
+        // the WHERE clause still applies after the join, so its constant conditions on
+        // the right table can narrow the scan of it
+        let right = plan_scan(&def, alias, filter);
+        let mut columns = left_columns.clone();
+        columns.extend(right.columns());
+        check_filter(&join.on, &columns)?;
+        plan = match join_key(&def, alias, &join.on, &left_columns) {
+            Some((kind, keys)) => Plan::IndexJoin {
+                input: Box::new(plan),
+                right: Scan {
+                    kind,
+                    eq: vec![],
+                    low: Bound::Unbounded,
+                    high: Bound::Unbounded,
+                    ..right
+                },
+                keys,
+                on: join.on.clone(),
+            },
+            None => Plan::NestedLoopJoin {
+                input: Box::new(plan),
+                right: Box::new(Plan::Scan(right)),
+                on: join.on.clone(),
+            },
+        };
+    }
+    if let Some(filter) = filter {
+        check_filter(filter, &plan.columns())?;
+        plan = Plan::Filter {
+            input: Box::new(plan),
+            predicate: filter.clone(),
+        };
+    }
+    return Ok(plan);
+}
+
+// adds the aggregates in `expr` that are not in `out` yet
+fn collect_aggregates(expr: &Expr, out: &mut Vec<Expr>) -> Result<(), SqlError> {
+    match expr {
+        Expr::Aggregate(_, arg) => {
+            if arg.as_ref().is_some_and(|arg| arg.has_aggregate()) {
+                return Err(SqlError::Eval(format!(
+                    "aggregates can not be nested: {expr}"
+                )));
+            }
+            if !out.contains(expr) {
+                out.push(expr.clone());
+            }
+        }
+        Expr::Unary(_, operand) => collect_aggregates(operand, out)?,
+        Expr::Binary(_, left, right) => {
+            collect_aggregates(left, out)?;
+            collect_aggregates(right, out)?;
+        }
+        _ => {}
+    }
+    return Ok(());
+}
+
+// `expr` over the output of an aggregation: the group expressions and aggregates it
+// contains become references to the columns holding their values
+fn over_groups(expr: &Expr, computed: &[Expr], columns: &[String]) -> Result<Expr, SqlError> {
+    if let Some(i) = computed.iter().position(|known| known == expr) {
+        return Ok(Expr::Column(columns[i].clone()));
+    }
+    let rewritten = match expr {
+        Expr::Unary(op, operand) => {
+            Expr::Unary(*op, Box::new(over_groups(operand, computed, columns)?))
+        }
+        Expr::Binary(op, left, right) => Expr::Binary(
+            *op,
+            Box::new(over_groups(left, computed, columns)?),
+            Box::new(over_groups(right, computed, columns)?),
+        ),
+        expr => expr.clone(),
+    };
+    if let Expr::Column(name) = &rewritten {
+        if resolve(columns, name).is_err() {
+            return Err(SqlError::Eval(format!(
+                "column {name} must appear in GROUP BY or inside an aggregate"
+            )));
+        }
+    }
+    return Ok(rewritten);
+}
+
+// from -> join -> filter -> aggregate -> having -> sort -> limit -> project. Sorting
+// comes before the projection so ORDER BY can use any column of the input, or the name
+// given to a selected expression.
+pub fn plan_select(db: &DB, select: &Select) -> Result<Plan, SqlError> {
+    let mut plan = plan_from(db, select)?;
+    let mut columns = plan.columns();
+    let mut exprs = vec![];
+    let mut names = vec![];
+    for item in &select.items {
+        match item {
+            SelectItem::Star => {
+                for column in &columns {
+                    exprs.push(Expr::Column(column.clone()));
+                    // columns keep their table name only when there are several tables
+                    let name = match column.split_once('.') {
+                        Some((_, name)) if select.joins.is_empty() => name.to_string(),
+                        _ => column.clone(),
+                    };
+                    names.push(name);
+                }
+            }
+            SelectItem::Expr(expr, alias) => {
+                check_columns(expr, &columns)?;
+                exprs.push(expr.clone());
+                names.push(alias.clone().unwrap_or_else(|| expr.to_string()));
+            }
+        }
+    }
+    let mut order_by = select.order_by.clone();
+    for order in &mut order_by {
+        if let Expr::Column(name) = &order.expr {
+            if resolve(&columns, name).is_err() {
+                if let Some(i) = names.iter().position(|alias| alias == name) {
+                    order.expr = exprs[i].clone();
+                }
+            }
+        }
+        check_columns(&order.expr, &columns)?;
+    }
+    let mut having = select.having.clone();
+    let aggregated = !select.group_by.is_empty()
+        || having.is_some()
+        || exprs.iter().any(Expr::has_aggregate)
+        || order_by.iter().any(|order| order.expr.has_aggregate());
+    if aggregated {
+        let mut aggregates = vec![];
+        for group in &select.group_by {
+            check_filter(group, &columns)?;
+        }
+        for expr in exprs.iter().chain(&having) {
+            collect_aggregates(expr, &mut aggregates)?;
+        }
+        for order in &order_by {
+            collect_aggregates(&order.expr, &mut aggregates)?;
+        }
+        if let Some(having) = &having {
+            check_columns(having, &columns)?;
+        }
+        plan = Plan::Aggregate {
+            input: Box::new(plan),
+            group_by: select.group_by.clone(),
+            aggregates: aggregates.clone(),
+        };
+        columns = plan.columns();
+        let computed: Vec<Expr> = select.group_by.iter().cloned().chain(aggregates).collect();
+        for expr in &mut exprs {
+            *expr = over_groups(expr, &computed, &columns)?;
+        }
+        for order in &mut order_by {
+            order.expr = over_groups(&order.expr, &computed, &columns)?;
+        }
+        if let Some(expr) = having.take() {
+            plan = Plan::Filter {
+                input: Box::new(plan),
+                predicate: over_groups(&expr, &computed, &columns)?,
+            };
+        }
+    }
+    if !order_by.is_empty() {
+        plan = Plan::Sort {
+            input: Box::new(plan),
+            order_by,
+        };
+    }
+    if select.limit.is_some() || select.offset > 0 {
+        plan = Plan::Limit {
+            input: Box::new(plan),
+            offset: select.offset,
+            limit: select.limit,
+        };
+    }
+    return Ok(Plan::Project {
+        input: Box::new(plan),
+        exprs,
+        names,
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        sql::parser::parse,
+        table::{ColumnType, IndexDef},
+    };
+
+    fn filter_of(sql: &str) -> Expr {
+        match parse(&format!("SELECT * FROM t WHERE {sql}")).unwrap() {
+            crate::sql::parser::Statement::Select(select) => return select.filter.unwrap(),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn planner_picks_the_best_key() {
+        let mut def = TableDef::new(
+            "t",
+            &[
+                ("a", ColumnType::Int64),
+                ("b", ColumnType::Str),
+                ("c", ColumnType::Float64),
+                ("d", ColumnType::Int64),
+            ],
+            &["a", "b"],
+        );
+        def.indexes
+            .push(IndexDef::new("by_c_d", &["c", "d"], false));
+        def.indexes.push(IndexDef::new("by_d", &["d"], true));
+        let plan = |sql: &str| -> (ScanKind, Vec<Value>, Bound<Value>, Bound<Value>) {
+            let scan = plan_scan(&def, "t", Some(&filter_of(sql)));
+            return (scan.kind, scan.eq, scan.low, scan.high);
+        };
+
+        assert_eq!(
+            plan("a = 1 AND b = 'x' AND d = 4"),
+            (
+                ScanKind::PrimaryKey,
+                vec![Value::Int64(1), Value::Str("x".to_string())],
+                Bound::Unbounded,
+                Bound::Unbounded
+            )
+        );
+        assert_eq!(
+            plan("a = 1 AND c = 2 AND 4 < d"),
+            (
+                ScanKind::Index("by_c_d".to_string()),
+                vec![Value::Float64(2.0)],
+                Bound::Excluded(Value::Int64(4)),
+                Bound::Unbounded
+            )
+        );
+        assert_eq!(
+            plan("a >= 3 AND a < 9 AND d > 0"),
+            (
+                ScanKind::PrimaryKey,
+                vec![],
+                Bound::Included(Value::Int64(3)),
+                Bound::Excluded(Value::Int64(9))
+            )
+        );
+        assert_eq!(plan("b = 'x' OR a = 1").0, ScanKind::Full);
+        assert_eq!(plan("b = 'x' AND d + 1 = 2").0, ScanKind::Full);
+        assert_eq!(
+            plan("t.d = 3 AND u.a = 1").0,
+            ScanKind::Index("by_d".to_string())
+        );
+        assert_eq!(plan("d = 'wrong type'").0, ScanKind::Full);
+
+        let range = |eq: Vec<i64>, low, high| -> Option<KeyRange> {
+            let scan = Scan {
+                table: def.clone(),
+                alias: "t".to_string(),
+                kind: ScanKind::PrimaryKey,
+                eq: eq.into_iter().map(Value::Int64).collect(),
+                low,
+                high,
+            };
+            return scan.key_range();
+        };
+        let key = |values: &[i64]| -> Vec<u8> {
+            let mut key = vec![];
+            values
+                .iter()
+                .for_each(|v| Value::Int64(*v).encode(&mut key));
+            return key;
+        };
+        assert_eq!(
+            range(vec![], Bound::Unbounded, Bound::Unbounded),
+            Some((Bound::Unbounded, Bound::Unbounded))
+        );
+        assert_eq!(
+            range(
+                vec![1],
+                Bound::Excluded(Value::Int64(2)),
+                Bound::Included(Value::Int64(5))
+            ),
+            Some((
+                Bound::Included(prefix_end(&key(&[1, 2])).unwrap()),
+                Bound::Excluded(prefix_end(&key(&[1, 5])).unwrap())
+            ))
+        );
+        assert_eq!(
+            range(vec![7], Bound::Unbounded, Bound::Excluded(Value::Int64(0))),
+            Some((Bound::Included(key(&[7])), Bound::Excluded(key(&[7, 0]))))
+        );
+        assert_eq!(
+            range(
+                vec![],
+                Bound::Excluded(Value::Int64(i64::MAX)),
+                Bound::Unbounded
+            ),
+            None
+        );
+        assert_eq!(prefix_end(&[1, 0xff, 0xff]), Some(vec![2]));
+    }
+}
@@ -0,0 +1,848 @@
+#![allow(clippy::needless_return)]
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    ops::Bound,
+};
+
+use crate::{
+    codec::Codec,
+    B_tree::{BTree, WriteBatch},
+};
+
+// name under which the catalog of table definitions is reserved, user tables can not
+// start with '@'
+pub const CATALOG_TABLE: &str = "@table";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColumnType {
+    Int64 = 1,
+    Float64 = 2,
+    Bool = 3,
+    Str = 4,
+    Bytes = 5,
+}
+impl ColumnType {
+    pub fn from_u8(tag: u8) -> Option<ColumnType> {
+        match tag {
+            1 => Some(ColumnType::Int64),
+            2 => Some(ColumnType::Float64),
+            3 => Some(ColumnType::Bool),
+            4 => Some(ColumnType::Str),
+            5 => Some(ColumnType::Bytes),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Int64(i64),
+    Float64(f64),
+    Bool(bool),
+    Str(String),
+    Bytes(Vec<u8>),
+}
+impl Value {
+    pub fn column_type(&self) -> ColumnType {
+        match self {
+            Value::Int64(_) => ColumnType::Int64,
+            Value::Float64(_) => ColumnType::Float64,
+            Value::Bool(_) => ColumnType::Bool,
+            Value::Str(_) => ColumnType::Str,
+            Value::Bytes(_) => ColumnType::Bytes,
+        }
+    }
+    // order-preserving, so encoded primary keys sort like the values themselves
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Value::Int64(v) => v.encode(out),
+            Value::Float64(v) => v.encode(out),
+            Value::Bool(v) => v.encode(out),
+            Value::Str(v) => v.encode(out),
+            Value::Bytes(v) => v.encode(out),
+        }
+    }
+    pub fn decode(column_type: ColumnType, input: &mut &[u8]) -> Option<Value> {
+        match column_type {
+            ColumnType::Int64 => i64::decode(input).map(Value::Int64),
+            ColumnType::Float64 => f64::decode(input).map(Value::Float64),
+            ColumnType::Bool => bool::decode(input).map(Value::Bool),
+            ColumnType::Str => String::decode(input).map(Value::Str),
+            ColumnType::Bytes => Vec::<u8>::decode(input).map(Value::Bytes),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Column {
+    pub name: String,
+    pub column_type: ColumnType,
+}
+
+// a secondary index, stored in a tree of its own keyed by the indexed columns followed by
+// the primary key, with empty values
+#[derive(Clone, Debug, PartialEq)]
+pub struct IndexDef {
+    pub name: String,
+    pub columns: Vec<String>,
+    pub unique: bool,
+}
+impl IndexDef {
+    pub fn new(name: &str, columns: &[&str], unique: bool) -> IndexDef {
+        return IndexDef {
+            name: name.to_string(),
+            columns: columns.iter().map(|name| name.to_string()).collect(),
+            unique,
+        };
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TableDef {
+    pub name: String,
+    pub columns: Vec<Column>,
+    // names of the primary key columns, in key order
+    pub primary_key: Vec<String>,
+    pub indexes: Vec<IndexDef>,
+}
+impl TableDef {
+    pub fn new(name: &str, columns: &[(&str, ColumnType)], primary_key: &[&str]) -> TableDef {
+        return TableDef {
+            name: name.to_string(),
+            columns: columns
+                .iter()
+                .map(|(name, column_type)| Column {
+                    name: name.to_string(),
+                    column_type: *column_type,
+                })
+                .collect(),
+            primary_key: primary_key.iter().map(|name| name.to_string()).collect(),
+            indexes: vec![],
+        };
+    }
+    pub fn index(&self, name: &str) -> Option<&IndexDef> {
+        return self.indexes.iter().find(|index| index.name == name);
+    }
+    pub fn column_index(&self, name: &str) -> Option<usize> {
+        return self.columns.iter().position(|column| column.name == name);
+    }
+    fn pkey_indexes(&self) -> Vec<usize> {
+        return self
+            .primary_key
+            .iter()
+            .map(|name| self.column_index(name).unwrap())
+            .collect();
+    }
+    fn check(&self) -> Result<(), TableError> {
+        if self.name.is_empty() || self.name.starts_with('@') {
+            return Err(TableError::BadDefinition(format!(
+                "invalid table name {:?}",
+                self.name
+            )));
+        }
+        if self.columns.is_empty() || self.primary_key.is_empty() {
+            return Err(TableError::BadDefinition(
+                "a table needs columns and a primary key".to_string(),
+            ));
+        }
+        for (i, column) in self.columns.iter().enumerate() {
+            if self.column_index(&column.name) != Some(i) {
+                return Err(TableError::BadDefinition(format!(
+                    "duplicate column {}",
+                    column.name
+                )));
+            }
+        }
+        for name in &self.primary_key {
+            if self.column_index(name).is_none() {
+                return Err(TableError::NoSuchColumn(name.clone()));
+            }
+        }
+        return Ok(());
+    }
+    fn check_index(&self, index: &IndexDef) -> Result<(), TableError> {
+        if self.index(&index.name).is_some() {
+            return Err(TableError::IndexExists(index.name.clone()));
+        }
+        if index.name.is_empty() || index.columns.is_empty() {
+            return Err(TableError::BadDefinition(
+                "an index needs a name and columns".to_string(),
+            ));
+        }
+        for name in &index.columns {
+            if self.column_index(name).is_none() {
+                return Err(TableError::NoSuchColumn(name.clone()));
+            }
+        }
+        return Ok(());
+    }
+    // checks that `row` holds one value of the right type per column
+    pub fn check_row(&self, row: &[Value]) -> Result<(), TableError> {
+        if row.len() != self.columns.len() {
+            return Err(TableError::BadRow(format!(
+                "{} expects {} values, got {}",
+                self.name,
+                self.columns.len(),
+                row.len()
+            )));
+        }
+        for (column, value) in self.columns.iter().zip(row) {
+            if column.column_type != value.column_type() {
+                return Err(TableError::TypeMismatch(column.name.clone()));
+            }
+        }
+        return Ok(());
+    }
+    pub fn encode_pkey(&self, pkey: &[Value]) -> Result<Vec<u8>, TableError> {
+        let indexes = self.pkey_indexes();
+        if pkey.len() != indexes.len() {
+            return Err(TableError::BadRow(format!(
+                "{} has {} primary key columns, got {}",
+                self.name,
+                indexes.len(),
+                pkey.len()
+            )));
+        }
+        let mut key = vec![];
+        for (i, value) in indexes.into_iter().zip(pkey) {
+            if self.columns[i].column_type != value.column_type() {
+                return Err(TableError::TypeMismatch(self.columns[i].name.clone()));
+            }
+            value.encode(&mut key);
+        }
+        return Ok(key);
+    }
+    // encodes the given leading columns of `index`, checking their types
+    pub fn encode_index_prefix(
+        &self,
+        index: &IndexDef,
+        values: &[Value],
+    ) -> Result<Vec<u8>, TableError> {
+        let mut prefix = vec![];
+        for (name, value) in index.columns.iter().zip(values) {
+            let column = &self.columns[self.column_index(name).unwrap()];
+            if column.column_type != value.column_type() {
+                return Err(TableError::TypeMismatch(name.clone()));
+            }
+            value.encode(&mut prefix);
+        }
+        return Ok(prefix);
+    }
+    pub fn encode_index_key(&self, index: &IndexDef, row: &[Value]) -> Vec<u8> {
+        let mut key = vec![];
+        for name in &index.columns {
+            row[self.column_index(name).unwrap()].encode(&mut key);
+        }
+        key.extend(self.encode_row(row).0);
+        return key;
+    }
+    // the encoded primary key (the key of the row in the table tree) in an index key
+    pub fn index_key_to_pkey(&self, index: &IndexDef, key: &[u8]) -> Vec<u8> {
+        let mut input = key;
+        for name in &index.columns {
+            let column = &self.columns[self.column_index(name).unwrap()];
+            Value::decode(column.column_type, &mut input).expect("corrupt index key");
+        }
+        return input.to_vec();
+    }
+    pub fn pkey_of(&self, row: &[Value]) -> Vec<Value> {
+        return self
+            .pkey_indexes()
+            .into_iter()
+            .map(|i| row[i].clone())
+            .collect();
+    }
+    // a row is stored as encoded primary key -> the other columns, in column order
+    pub fn encode_row(&self, row: &[Value]) -> (Vec<u8>, Vec<u8>) {
+        let indexes = self.pkey_indexes();
+        let mut key = vec![];
+        for i in &indexes {
+            row[*i].encode(&mut key);
+        }
+        let mut value = vec![];
+        for (i, column_value) in row.iter().enumerate() {
+            if !indexes.contains(&i) {
+                column_value.encode(&mut value);
+            }
+        }
+        return (key, value);
+    }
+    pub fn decode_row(&self, key: &[u8], value: &[u8]) -> Vec<Value> {
+        let indexes = self.pkey_indexes();
+        let mut row: Vec<Option<Value>> = vec![None; self.columns.len()];
+        let mut input = key;
+        for i in &indexes {
+            row[*i] = Value::decode(self.columns[*i].column_type, &mut input);
+        }
+        let mut input = value;
+        for (i, column) in self.columns.iter().enumerate() {
+            if !indexes.contains(&i) {
+                row[i] = Value::decode(column.column_type, &mut input);
+            }
+        }
+        return row
+            .into_iter()
+            .map(|value| value.expect("corrupt row"))
+            .collect();
+    }
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = vec![];
+        self.name.encode(&mut out);
+        (self.columns.len() as u32).encode(&mut out);
+        for column in &self.columns {
+            column.name.encode(&mut out);
+            (column.column_type as u8).encode(&mut out);
+        }
+        (self.primary_key.len() as u32).encode(&mut out);
+        for name in &self.primary_key {
+            name.encode(&mut out);
+        }
+        (self.indexes.len() as u32).encode(&mut out);
+        for index in &self.indexes {
+            index.name.encode(&mut out);
+            index.unique.encode(&mut out);
+            (index.columns.len() as u32).encode(&mut out);
+            for name in &index.columns {
+                name.encode(&mut out);
+            }
+        }
+        return out;
+    }
+    pub fn from_bytes(bytes: &[u8]) -> Option<TableDef> {
+        let mut input = bytes;
+        let name = String::decode(&mut input)?;
+        let mut columns = vec![];
+        for _ in 0..u32::decode(&mut input)? {
+            let name = String::decode(&mut input)?;
+            let column_type = ColumnType::from_u8(u8::decode(&mut input)?)?;
+            columns.push(Column { name, column_type });
+        }
+        let mut primary_key = vec![];
+        for _ in 0..u32::decode(&mut input)? {
+            primary_key.push(String::decode(&mut input)?);
+        }
+        let mut indexes = vec![];
+        for _ in 0..u32::decode(&mut input)? {
+            let name = String::decode(&mut input)?;
+            let unique = bool::decode(&mut input)?;
+            let mut columns = vec![];
+            for _ in 0..u32::decode(&mut input)? {
+                columns.push(String::decode(&mut input)?);
+            }
+            indexes.push(IndexDef {
+                name,
+                columns,
+                unique,
+            });
+        }
+        return Some(TableDef {
+            name,
+            columns,
+            primary_key,
+            indexes,
+        });
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TableError {
+    TableExists(String),
+    NoSuchTable(String),
+    NoSuchColumn(String),
+    TypeMismatch(String),
+    BadDefinition(String),
+    BadRow(String),
+    DuplicateKey,
+    IndexExists(String),
+    NoSuchIndex(String),
+    UniqueViolation(String),
+}
+impl fmt::Display for TableError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TableError::TableExists(name) => write!(f, "table {name} already exists"),
+            TableError::NoSuchTable(name) => write!(f, "no such table: {name}"),
+            TableError::NoSuchColumn(name) => write!(f, "no such column: {name}"),
+            TableError::TypeMismatch(name) => write!(f, "wrong type for column {name}"),
+            TableError::BadDefinition(message) => write!(f, "bad table definition: {message}"),
+            TableError::BadRow(message) => write!(f, "bad row: {message}"),
+            TableError::DuplicateKey => write!(f, "duplicate primary key"),
+            TableError::IndexExists(name) => write!(f, "index {name} already exists"),
+            TableError::NoSuchIndex(name) => write!(f, "no such index: {name}"),
+            TableError::UniqueViolation(name) => {
+                write!(f, "duplicate value in unique index {name}")
+            }
+        }
+    }
+}
+
+// how the rows matching a set of `column = value` conditions are reached
+#[derive(Debug, PartialEq)]
+pub enum Access {
+    // a scan over the rows sharing the first n primary key columns, a point lookup when
+    // all of them are given
+    PrimaryKey(usize),
+    // a scan over the entries of the named index sharing its first n columns
+    Index(String, usize),
+    FullScan,
+}
+
+// the catalog, tables and indexes are trees in memory, which a read can not fail on
+pub fn heap_get(tree: &BTree, key: &[u8]) -> Option<Vec<u8>> {
+    return tree.get(key).expect("reading a tree in memory");
+}
+
+// tables with typed columns on top of BTree. The definitions live in the reserved
+// catalog tree (name -> encoded TableDef), the rows of each table in a tree of its own
+// and every secondary index in one more tree, keyed by (table, index).
+#[derive(Default)]
+pub struct DB {
+    pub catalog: BTree,
+    pub tables: HashMap<String, BTree>,
+    pub indexes: HashMap<(String, String), BTree>,
+}
+impl DB {
+    pub fn new() -> DB {
+        return DB {
+            catalog: BTree::new(),
+            tables: HashMap::new(),
+            indexes: HashMap::new(),
+        };
+    }
+    pub fn create_table(&mut self, def: TableDef) -> Result<(), TableError> {
+        def.check()?;
+        if !self
+            .catalog
+            .insert_new(def.name.as_bytes().to_vec(), def.to_bytes())
+        {
+            return Err(TableError::TableExists(def.name));
+        }
+        self.tables.insert(def.name, BTree::new());
+        return Ok(());
+    }
+    pub fn get_table(&self, name: &str) -> Result<TableDef, TableError> {
+        let bytes = heap_get(&self.catalog, name.as_bytes())
+            .ok_or_else(|| TableError::NoSuchTable(name.to_string()))?;
+        return Ok(TableDef::from_bytes(&bytes).expect("corrupt table definition"));
+    }
+    pub fn table_names(&self) -> Vec<String> {
+        return self
+            .catalog
+            .scan(Bound::Unbounded, Bound::Unbounded)
+            .map(|(name, _)| String::from_utf8(name).expect("corrupt table name"))
+            .collect();
+    }
+    fn rows(&mut self, name: &str) -> &mut BTree {
+        return self.tables.get_mut(name).expect("table without a tree");
+    }
+    fn index_tree(&self, table: &str, index: &str) -> &BTree {
+        return &self.indexes[&(table.to_string(), index.to_string())];
+    }
+    fn index_tree_mut(&mut self, table: &str, index: &str) -> &mut BTree {
+        return self
+            .indexes
+            .get_mut(&(table.to_string(), index.to_string()))
+            .expect("index without a tree");
+    }
+    // builds the index from the rows already in the table, then records it in the catalog
+    pub fn create_index(&mut self, table: &str, index: IndexDef) -> Result<(), TableError> {
+        let mut def = self.get_table(table)?;
+        def.check_index(&index)?;
+        let mut batch = WriteBatch::new();
+        let mut seen = HashSet::new();
+        let mut tree = BTree::new();
+        for (key, value) in self.tables[table].scan(Bound::Unbounded, Bound::Unbounded) {
+            let row = def.decode_row(&key, &value);
+            let index_key = def.encode_index_key(&index, &row);
+            if index_key.len() > tree.max_key_size() {
+                return Err(TableError::BadRow("index key too large".to_string()));
+            }
+            let prefix_len = index_key.len() - key.len();
+            if index.unique && !seen.insert(index_key[..prefix_len].to_vec()) {
+                return Err(TableError::UniqueViolation(index.name));
+            }
+            batch.put(index_key, vec![]);
+        }
+        tree.write(batch).expect("writing a tree in memory");
+        self.indexes
+            .insert((table.to_string(), index.name.clone()), tree);
+        def.indexes.push(index);
+        self.catalog
+            .update_existing(table.as_bytes().to_vec(), def.to_bytes());
+        return Ok(());
+    }
+    // fails if the encoded row or one of its index keys does not fit into its tree
+    pub fn check_size(&self, def: &TableDef, row: &[Value]) -> Result<(), TableError> {
+        let (key, value) = def.encode_row(row);
+        let rows = &self.tables[&def.name];
+        if key.len() > rows.max_key_size() || value.len() > rows.max_val_size() {
+            return Err(TableError::BadRow("row too large".to_string()));
+        }
+        for index in &def.indexes {
+            let tree = self.index_tree(&def.name, &index.name);
+            if def.encode_index_key(index, row).len() > tree.max_key_size() {
+                return Err(TableError::BadRow("index key too large".to_string()));
+            }
+        }
+        return Ok(());
+    }
+    // fails if another row (one with a different primary key) already holds the values of
+    // `row` in a unique index
+    fn check_unique(&self, def: &TableDef, row: &[Value]) -> Result<(), TableError> {
+        let pkey = def.encode_row(row).0;
+        for index in def.indexes.iter().filter(|index| index.unique) {
+            let index_key = def.encode_index_key(index, row);
+            let prefix = &index_key[..index_key.len() - pkey.len()];
+            let tree = self.index_tree(&def.name, &index.name);
+            let clash = tree
+                .scan(Bound::Included(prefix), Bound::Unbounded)
+                .take_while(|(key, _)| key.starts_with(prefix))
+                .any(|(key, _)| def.index_key_to_pkey(index, &key) != pkey);
+            if clash {
+                return Err(TableError::UniqueViolation(index.name.clone()));
+            }
+        }
+        return Ok(());
+    }
+    // fails with DuplicateKey if a row with the same primary key exists. Constraints are
+    // all checked before anything is written, so the row and its index entries are
+    // written together or not at all.
+    pub fn insert_row(&mut self, table: &str, row: Vec<Value>) -> Result<(), TableError> {
+        let def = self.get_table(table)?;
+        def.check_row(&row)?;
+        self.check_size(&def, &row)?;
+        let (key, value) = def.encode_row(&row);
+        if heap_get(&self.tables[table], &key).is_some() {
+            return Err(TableError::DuplicateKey);
+        }
+        self.check_unique(&def, &row)?;
+        self.rows(table).insert(key, value);
+        for index in &def.indexes {
+            let index_key = def.encode_index_key(index, &row);
+            self.index_tree_mut(table, &index.name)
+                .insert(index_key, vec![]);
+        }
+        return Ok(());
+    }
+    pub fn get_row(&self, table: &str, pkey: &[Value]) -> Result<Option<Vec<Value>>, TableError> {
+        let def = self.get_table(table)?;
+        let key = def.encode_pkey(pkey)?;
+        let value = heap_get(&self.tables[table], &key);
+        return Ok(value.map(|value| def.decode_row(&key, &value)));
+    }
+    // replaces the row with the same primary key, returns false if there is none
+    pub fn update_row(&mut self, table: &str, row: Vec<Value>) -> Result<bool, TableError> {
+        let def = self.get_table(table)?;
+        def.check_row(&row)?;
+        self.check_size(&def, &row)?;
+        let (key, value) = def.encode_row(&row);
+        let old_row = match heap_get(&self.tables[table], &key) {
+            Some(old_value) => def.decode_row(&key, &old_value),
+            None => return Ok(false),
+        };
+        self.check_unique(&def, &row)?;
+        self.rows(table).update_existing(key, value);
+        for index in &def.indexes {
+            let old_key = def.encode_index_key(index, &old_row);
+            let new_key = def.encode_index_key(index, &row);
+            if old_key != new_key {
+                let tree = self.index_tree_mut(table, &index.name);
+                tree.delete(old_key);
+                tree.insert(new_key, vec![]);
+            }
+        }
+        return Ok(true);
+    }
+    pub fn delete_row(&mut self, table: &str, pkey: &[Value]) -> Result<bool, TableError> {
+        let def = self.get_table(table)?;
+        let key = def.encode_pkey(pkey)?;
+        let old_row = match heap_get(&self.tables[table], &key) {
+            Some(old_value) => def.decode_row(&key, &old_value),
+            None => return Ok(false),
+        };
+        self.rows(table).delete(key);
+        for index in &def.indexes {
+            let old_key = def.encode_index_key(index, &old_row);
+            self.index_tree_mut(table, &index.name).delete(old_key);
+        }
+        return Ok(true);
+    }
+    // picks the cheapest way to reach the rows with the given columns fixed: the primary
+    // key or the index with the most leading columns covered, a full scan if neither helps
+    pub fn access_path(&self, def: &TableDef, columns: &[&str]) -> Access {
+        let covered = |key_columns: &[String]| -> usize {
+            return key_columns
+                .iter()
+                .take_while(|name| columns.contains(&name.as_str()))
+                .count();
+        };
+        let mut best = Access::FullScan;
+        let mut best_covered = 0;
+        let pkey_covered = covered(&def.primary_key);
+        if pkey_covered > 0 {
+            best = Access::PrimaryKey(pkey_covered);
+            best_covered = pkey_covered;
+            if pkey_covered == def.primary_key.len() {
+                return best;
+            }
+        }
+        for index in &def.indexes {
+            let index_covered = covered(&index.columns);
+            if index_covered > best_covered {
+                best = Access::Index(index.name.clone(), index_covered);
+                best_covered = index_covered;
+            }
+        }
+        return best;
+    }
+    // rows where every given column equals its value, found through `access_path`
+    pub fn find_rows(
+        &self,
+        table: &str,
+        filter: &[(&str, Value)],
+    ) -> Result<Vec<Vec<Value>>, TableError> {
+        let def = self.get_table(table)?;
+        for (name, value) in filter {
+            let i = def
+                .column_index(name)
+                .ok_or_else(|| TableError::NoSuchColumn(name.to_string()))?;
+            if def.columns[i].column_type != value.column_type() {
+                return Err(TableError::TypeMismatch(name.to_string()));
+            }
+        }
+        let value_of = |name: &String| -> Value {
+            return filter
+                .iter()
+                .find(|(column, _)| column == name)
+                .unwrap()
+                .1
+                .clone();
+        };
+        let columns: Vec<&str> = filter.iter().map(|(name, _)| *name).collect();
+        let rows = &self.tables[table];
+        let mut found = vec![];
+        match self.access_path(&def, &columns) {
+            Access::PrimaryKey(n) => {
+                let values: Vec<Value> = def.primary_key[..n].iter().map(value_of).collect();
+                let mut prefix = vec![];
+                values.iter().for_each(|value| value.encode(&mut prefix));
+                for (key, value) in rows
+                    .scan(Bound::Included(&prefix), Bound::Unbounded)
+                    .take_while(|(key, _)| key.starts_with(&prefix))
+                {
+                    found.push(def.decode_row(&key, &value));
+                }
+            }
+            Access::Index(name, n) => {
+                let index = def.index(&name).unwrap();
+                let values: Vec<Value> = index.columns[..n].iter().map(value_of).collect();
+                let prefix = def.encode_index_prefix(index, &values)?;
+                for (index_key, _) in self
+                    .index_tree(table, &name)
+                    .scan(Bound::Included(&prefix), Bound::Unbounded)
+                    .take_while(|(key, _)| key.starts_with(&prefix))
+                {
+                    let key = def.index_key_to_pkey(index, &index_key);
+                    let value = heap_get(rows, &key).expect("index entry without a row");
+                    found.push(def.decode_row(&key, &value));
+                }
+            }
+            Access::FullScan => {
+                for (key, value) in rows.scan(Bound::Unbounded, Bound::Unbounded) {
+                    found.push(def.decode_row(&key, &value));
+                }
+            }
+        }
+        found.retain(|row| {
+            return filter
+                .iter()
+                .all(|(name, value)| row[def.column_index(name).unwrap()] == *value);
+        });
+        return Ok(found);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn users() -> TableDef {
+        return TableDef::new(
+            "users",
+            &[
+                ("tenant", ColumnType::Int64),
+                ("name", ColumnType::Str),
+                ("score", ColumnType::Float64),
+                ("active", ColumnType::Bool),
+            ],
+            &["tenant", "name"],
+        );
+    }
+
+    fn user(tenant: i64, name: &str, score: f64) -> Vec<Value> {
+        return vec![
+            Value::Int64(tenant),
+            Value::Str(name.to_string()),
+            Value::Float64(score),
+            Value::Bool(true),
+        ];
+    }
+
+    #[test]
+    fn table_definitions_live_in_the_catalog() {
+        let mut db = DB::new();
+        db.create_table(users()).unwrap();
+        assert_eq!(
+            db.create_table(users()),
+            Err(TableError::TableExists("users".to_string()))
+        );
+        assert_eq!(db.get_table("users").unwrap(), users());
+        assert_eq!(db.table_names(), vec!["users".to_string()]);
+        assert_eq!(
+            db.get_table("orders"),
+            Err(TableError::NoSuchTable("orders".to_string()))
+        );
+        let reserved = TableDef::new(CATALOG_TABLE, &[("a", ColumnType::Int64)], &["a"]);
+        assert!(db.create_table(reserved).is_err());
+        let bad_pkey = TableDef::new("t", &[("a", ColumnType::Int64)], &["b"]);
+        assert_eq!(
+            db.create_table(bad_pkey),
+            Err(TableError::NoSuchColumn("b".to_string()))
+        );
+    }
+
+    #[test]
+    fn rows_round_trip() {
+        let mut db = DB::new();
+        db.create_table(users()).unwrap();
+        for i in 0..300 {
+            db.insert_row("users", user(i % 3, &format!("user{i}"), i as f64))
+                .unwrap();
+        }
+        assert_eq!(
+            db.insert_row("users", user(0, "user0", 1.0)),
+            Err(TableError::DuplicateKey)
+        );
+        let pkey = [Value::Int64(2), Value::Str("user5".to_string())];
+        assert_eq!(
+            db.get_row("users", &pkey).unwrap(),
+            Some(user(2, "user5", 5.0))
+        );
+        assert!(db.update_row("users", user(2, "user5", -1.5)).unwrap());
+        assert!(!db.update_row("users", user(9, "nobody", 0.0)).unwrap());
+        assert_eq!(
+            db.get_row("users", &pkey).unwrap(),
+            Some(user(2, "user5", -1.5))
+        );
+        assert_eq!(
+            db.insert_row("users", vec![Value::Int64(1)]),
+            Err(TableError::BadRow(
+                "users expects 4 values, got 1".to_string()
+            ))
+        );
+        assert_eq!(
+            db.get_row("users", &[Value::Str("x".to_string()), Value::Int64(1)]),
+            Err(TableError::TypeMismatch("tenant".to_string()))
+        );
+        for i in 0..300 {
+            let pkey = [Value::Int64(i % 3), Value::Str(format!("user{i}"))];
+            assert!(db.delete_row("users", &pkey).unwrap());
+            assert!(!db.delete_row("users", &pkey).unwrap());
+        }
+        assert_eq!(db.get_row("users", &pkey).unwrap(), None);
+
+        // rows and index keys too large for their trees are refused before any write
+        let too_large = |what: &str| TableError::BadRow(format!("{what} too large"));
+        let long = "x".repeat(2000);
+        let err = db.insert_row("users", user(1, &long, 0.0)).unwrap_err();
+        assert_eq!(err, too_large("row"));
+        let err = db.update_row("users", user(1, &long, 0.0)).unwrap_err();
+        assert_eq!(err, too_large("row"));
+        let long = "y".repeat(600);
+        db.insert_row("users", user(1, &long, 0.0)).unwrap();
+        let by_name = IndexDef::new("by_name", &["name"], false);
+        let err = db.create_index("users", by_name.clone()).unwrap_err();
+        assert_eq!(err, too_large("index key"));
+        assert!(db.delete_row("users", &user(1, &long, 0.0)[..2]).unwrap());
+        db.create_index("users", by_name).unwrap();
+        let err = db.insert_row("users", user(1, &long, 0.0)).unwrap_err();
+        assert_eq!(err, too_large("index key"));
+        assert_eq!(
+            db.get_row("users", &user(1, &long, 0.0)[..2]).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn secondary_indexes_follow_row_writes() {
+        let mut db = DB::new();
+        db.create_table(users()).unwrap();
+        for i in 0..200 {
+            db.insert_row("users", user(i % 4, &format!("user{i}"), (i % 10) as f64))
+                .unwrap();
+        }
+        let by_score = IndexDef::new("by_score", &["score", "active"], false);
+        db.create_index("users", by_score.clone()).unwrap();
+        assert_eq!(
+            db.create_index("users", by_score),
+            Err(TableError::IndexExists("by_score".to_string()))
+        );
+        assert_eq!(
+            db.create_index("users", IndexDef::new("by_score2", &["score"], true)),
+            Err(TableError::UniqueViolation("by_score2".to_string()))
+        );
+        db.create_index("users", IndexDef::new("by_name", &["name"], true))
+            .unwrap();
+        assert_eq!(db.get_table("users").unwrap().indexes.len(), 2);
+
+        let def = db.get_table("users").unwrap();
+        assert_eq!(
+            db.access_path(&def, &["score"]),
+            Access::Index("by_score".to_string(), 1)
+        );
+        assert_eq!(
+            db.access_path(&def, &["name", "tenant"]),
+            Access::PrimaryKey(2)
+        );
+        assert_eq!(db.access_path(&def, &["active"]), Access::FullScan);
+
+        let sevens = db
+            .find_rows("users", &[("score", Value::Float64(7.0))])
+            .unwrap();
+        assert_eq!(sevens.len(), 20);
+        assert!(sevens.iter().all(|row| row[2] == Value::Float64(7.0)));
+
+        // the unique index rejects a second row with the same name, before writing anything
+        assert_eq!(
+            db.insert_row("users", user(9, "user3", 0.0)),
+            Err(TableError::UniqueViolation("by_name".to_string()))
+        );
+        assert_eq!(db.get_row("users", &user(9, "user3", 0.0)[..2]), Ok(None));
+
+        // moving a row to another score moves its index entry along
+        assert!(db.update_row("users", user(3, "user7", 100.0)).unwrap());
+        assert_eq!(
+            db.find_rows("users", &[("score", Value::Float64(7.0))])
+                .unwrap()
+                .len(),
+            19
+        );
+        let moved = db
+            .find_rows("users", &[("score", Value::Float64(100.0))])
+            .unwrap();
+        assert_eq!(moved, vec![user(3, "user7", 100.0)]);
+
+        let pkey = [Value::Int64(3), Value::Str("user7".to_string())];
+        assert!(db.delete_row("users", &pkey).unwrap());
+        let by_name = db
+            .find_rows("users", &[("name", Value::Str("user7".to_string()))])
+            .unwrap();
+        assert!(by_name.is_empty());
+        assert!(db
+            .find_rows("users", &[("score", Value::Float64(100.0))])
+            .unwrap()
+            .is_empty());
+        db.insert_row("users", user(0, "user7", 1.0)).unwrap();
+    }
+}
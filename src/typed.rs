@@ -0,0 +1,113 @@
+#![allow(clippy::needless_return)]
+use std::{marker::PhantomData, ops::Bound};
+
+use crate::{codec::Codec, B_tree::BTree};
+
+// a BTree holding typed keys and values. Keys go through their order-preserving encoding,
+// so the byte-wise order of the tree is the natural order of `K` and range scans over
+// composite keys such as `(u32, i64, String)` come out sorted field by field.
+pub struct TypedTree<K: Codec, V: Codec> {
+    pub tree: BTree,
+    marker: PhantomData<(K, V)>,
+}
+impl<K: Codec, V: Codec> Default for TypedTree<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<K: Codec, V: Codec> TypedTree<K, V> {
+    pub fn new() -> TypedTree<K, V> {
+        return TypedTree {
+            tree: BTree::new(),
+            marker: PhantomData,
+        };
+    }
+    pub fn insert(&mut self, key: &K, value: &V) {
+        self.tree.insert(key.to_bytes(), value.to_bytes());
+    }
+    pub fn get(&self, key: &K) -> Option<V> {
+        let value = self
+            .tree
+            .get(&key.to_bytes())
+            .expect("reading a tree in memory")?;
+        return Some(V::from_bytes(&value).expect("corrupt value in typed tree"));
+    }
+    pub fn delete(&mut self, key: &K) -> bool {
+        return self.tree.delete(key.to_bytes());
+    }
+    pub fn range(&self, start: Bound<&K>, end: Bound<&K>) -> impl Iterator<Item = (K, V)> + '_ {
+        let start = start.map(|key| key.to_bytes());
+        let end = end.map(|key| key.to_bytes());
+        let iter = self.tree.scan(
+            start.as_ref().map(|key| key.as_slice()),
+            end.as_ref().map(|key| key.as_slice()),
+        );
+        return iter.map(decode_pair::<K, V>);
+    }
+    // every entry whose key starts with the encoded `prefix`, e.g. all keys of one tenant
+    // with a `(u32,)` prefix over `(u32, i64, String)` keys
+    pub fn scan_prefix<P: Codec>(&self, prefix: &P) -> impl Iterator<Item = (K, V)> + '_ {
+        let prefix = prefix.to_bytes();
+        let iter = self.tree.scan(Bound::Included(&prefix), Bound::Unbounded);
+        return iter
+            .take_while(move |(key, _)| key.starts_with(&prefix))
+            .map(decode_pair::<K, V>);
+    }
+}
+
+fn decode_pair<K: Codec, V: Codec>((key, value): (Vec<u8>, Vec<u8>)) -> (K, V) {
+    return (
+        K::from_bytes(&key).expect("corrupt key in typed tree"),
+        V::from_bytes(&value).expect("corrupt value in typed tree"),
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn composite_keys_scan_in_order() {
+        let mut events: TypedTree<(u32, i64, String), f64> = TypedTree::new();
+        for tenant in [2u32, 1, 3] {
+            for ts in [-20i64, 5, -3, 400] {
+                for id in ["b", "a"] {
+                    let key = (tenant, ts, id.to_string());
+                    events.insert(&key, &(ts as f64 / 2.0));
+                }
+            }
+        }
+        assert_eq!(events.get(&(1, -3, "a".to_string())), Some(-1.5));
+        assert_eq!(events.get(&(1, -4, "a".to_string())), None);
+
+        let all: Vec<_> = events.range(Bound::Unbounded, Bound::Unbounded).collect();
+        assert_eq!(all.len(), 24);
+        assert!(all.windows(2).all(|pair| pair[0].0 < pair[1].0));
+
+        let tenant_two: Vec<_> = events.scan_prefix(&(2u32,)).map(|(key, _)| key).collect();
+        assert_eq!(tenant_two.len(), 8);
+        assert_eq!(tenant_two[0], (2, -20, "a".to_string()));
+        assert_eq!(tenant_two[7], (2, 400, "b".to_string()));
+
+        let window: Vec<_> = events
+            .range(
+                Bound::Included(&(3, -3, String::new())),
+                Bound::Excluded(&(3, 400, String::new())),
+            )
+            .map(|(key, _)| (key.1, key.2))
+            .collect();
+        assert_eq!(
+            window,
+            vec![
+                (-3, "a".to_string()),
+                (-3, "b".to_string()),
+                (5, "a".to_string()),
+                (5, "b".to_string())
+            ]
+        );
+
+        assert!(events.delete(&(3, 5, "a".to_string())));
+        assert!(!events.delete(&(3, 5, "a".to_string())));
+        assert_eq!(events.scan_prefix(&(3u32, 5i64)).count(), 1);
+    }
+}